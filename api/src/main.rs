@@ -43,6 +43,7 @@ async fn generate_image(
     }
 
     response.json().await
+        .map(Json)
         .map_err(|e| format!("Failed to parse response: {}", e))
 }
 
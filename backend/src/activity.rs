@@ -0,0 +1,412 @@
+// NFT provenance: the mint and every listing and sale, read from the marketplace
+// index or, when there is none, decoded from the mint's own transactions. A
+// wallet's history (its mints, listings, buys and sells) comes from the index
+// alone, so it costs no RPC calls.
+//
+// The program has no cancel or price-update instruction: a listing keeps its
+// price and ends only in a sale, so neither shows up as an event. buy_nft pays
+// the whole price to the seller, so no creator royalties are paid on sales.
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_sdk::{commitment_config::CommitmentConfig, native_token::lamports_to_sol, pubkey::Pubkey};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use crate::cache::{ChainCache, FirstTransaction};
+use crate::chain::{rfc3339, ChainClient};
+use crate::index_db::IndexDb;
+use crate::indexer::{self, Commitment, MarketplaceEvent};
+use crate::marketplace_program;
+
+// Transactions fetched at once when decoding a mint's history from the chain
+const DECODE_CONCURRENCY: usize = 8;
+
+// Without an index, listings and sales are read from at most this many of a
+// mint's newest transactions: one page of getSignaturesForAddress
+const MAX_CHAIN_ACTIVITY_TRANSACTIONS: usize = 1000;
+
+// Declared in chronological order within a slot
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityKind {
+    Mint,
+    List,
+    Sale,
+}
+
+impl FromStr for ActivityKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "mint" => Ok(ActivityKind::Mint),
+            "list" => Ok(ActivityKind::List),
+            "sale" => Ok(ActivityKind::Sale),
+            other => Err(format!("Unknown event type {} (expected mint, list or sale)", other)),
+        }
+    }
+}
+
+/// Comma-separated event types, e.g. `list,sale`
+pub fn parse_kinds(value: &str) -> Result<Vec<ActivityKind>, String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|kind| !kind.is_empty())
+        .map(ActivityKind::from_str)
+        .collect()
+}
+
+// An event as read from the index or the chain
+pub struct ActivityRecord {
+    pub kind: ActivityKind,
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub mint: String,
+    pub price: Option<u64>,
+    pub seller: Option<String>,
+    pub buyer: Option<String>,
+    pub listing_address: Option<String>,
+    pub commitment: Commitment,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ActivityEvent {
+    pub event_type: ActivityKind,
+    pub mint: String,
+    pub signature: String,
+    pub slot: u64,
+    pub timestamp: Option<String>, // Block time, RFC 3339
+    pub price: Option<u64>, // Lamports
+    pub seller: Option<String>,
+    pub buyer: Option<String>,
+    pub listing_address: Option<String>,
+    pub commitment: String, // "confirmed" or "finalized"
+}
+
+impl From<ActivityRecord> for ActivityEvent {
+    fn from(record: ActivityRecord) -> Self {
+        ActivityEvent {
+            event_type: record.kind,
+            mint: record.mint,
+            signature: record.signature,
+            slot: record.slot,
+            timestamp: rfc3339(record.block_time),
+            price: record.price,
+            seller: record.seller,
+            buyer: record.buyer,
+            listing_address: record.listing_address,
+            commitment: record.commitment.as_str().to_string(),
+        }
+    }
+}
+
+pub struct ActivityPage {
+    pub events: Vec<ActivityEvent>,
+    pub total_count: usize,
+}
+
+/// Newest first; `kinds` empty for every event type
+pub async fn nft_activity(
+    client: &dyn ChainClient,
+    cache: &ChainCache,
+    index: Option<&IndexDb>,
+    mint: &Pubkey,
+    kinds: &[ActivityKind],
+    offset: usize,
+    limit: usize,
+) -> Result<ActivityPage, String> {
+    let wanted = |kind| kinds.is_empty() || kinds.contains(&kind);
+    let marketplace_events = wanted(ActivityKind::List) || wanted(ActivityKind::Sale);
+
+    let mut records = match (index, marketplace_events) {
+        (Some(index), true) => index.mint_activity(&mint.to_string()).await?,
+        (None, true) => marketplace_activity_from_chain(client, cache, mint).await?,
+        (_, false) => Vec::new(),
+    };
+    if wanted(ActivityKind::Mint) {
+        if let Some(first) = first_transaction(cache, index, mint).await? {
+            records.push(mint_record(mint, first.signature, first.slot, first.block_time));
+        }
+    }
+    records.retain(|record| wanted(record.kind));
+    sort_newest_first(&mut records);
+
+    let total_count = records.len();
+    let events = records.into_iter().skip(offset).take(limit).map(ActivityEvent::from).collect();
+    Ok(ActivityPage { events, total_count })
+}
+
+/// The mint's first transaction, from the index when it has recorded the mint,
+/// otherwise from the chain (None when its history is too long to search)
+pub async fn first_transaction(
+    cache: &ChainCache,
+    index: Option<&IndexDb>,
+    mint: &Pubkey,
+) -> Result<Option<FirstTransaction>, String> {
+    if let Some(index) = index {
+        if let Some(first) = index.mint_transaction(&mint.to_string()).await? {
+            return Ok(Some(first));
+        }
+    }
+    cache.first_transaction(mint).await
+        .map_err(|e| format!("Failed to fetch mint history: {}", e))
+}
+
+fn sort_newest_first(records: &mut [ActivityRecord]) {
+    records.sort_by(|a, b| (b.slot, b.kind, &b.signature).cmp(&(a.slot, a.kind, &a.signature)));
+}
+
+fn mint_record(mint: &Pubkey, signature: String, slot: u64, block_time: Option<i64>) -> ActivityRecord {
+    ActivityRecord {
+        kind: ActivityKind::Mint,
+        signature,
+        slot,
+        block_time,
+        mint: mint.to_string(),
+        price: None,
+        seller: None,
+        buyer: None,
+        listing_address: None,
+        commitment: Commitment::Finalized,
+    }
+}
+
+// List and buy instructions in the mint's newest MAX_CHAIN_ACTIVITY_TRANSACTIONS
+// finalized transactions. Each transaction is decoded once and cached.
+async fn marketplace_activity_from_chain(
+    client: &dyn ChainClient,
+    cache: &ChainCache,
+    mint: &Pubkey,
+) -> Result<Vec<ActivityRecord>, String> {
+    let config = GetConfirmedSignaturesForAddress2Config {
+        limit: Some(MAX_CHAIN_ACTIVITY_TRANSACTIONS),
+        commitment: Some(CommitmentConfig::finalized()),
+        ..GetConfirmedSignaturesForAddress2Config::default()
+    };
+    let signatures = client.get_signatures_for_address_with_config(mint, config).await
+        .map_err(|e| format!("Failed to fetch signatures for {}: {}", mint, e))?;
+
+    let program_id = marketplace_program::program_id();
+    let transactions: Vec<_> = stream::iter(signatures.into_iter().filter(|status| status.err.is_none()))
+        .map(|status| async move {
+            let key = format!("marketplace_transaction:{}", status.signature);
+            cache.finalized(&key, indexer::decode_transaction(client, None, &program_id, &status, Commitment::Finalized)).await
+        })
+        .buffered(DECODE_CONCURRENCY)
+        .collect()
+        .await;
+    let mut records = Vec::new();
+    for transaction in transactions {
+        let transaction = transaction?;
+        for (_, event) in transaction.events.iter().filter(|(_, event)| event.nft_mint() == *mint) {
+            let (kind, listing, seller, buyer, price) = match event {
+                MarketplaceEvent::Listed { listing, seller, price, .. } => (ActivityKind::List, listing, seller, None, price),
+                MarketplaceEvent::Sold { listing, seller, buyer, price, .. } => (ActivityKind::Sale, listing, seller, Some(buyer), price),
+            };
+            records.push(ActivityRecord {
+                kind,
+                signature: transaction.signature.clone(),
+                slot: transaction.slot,
+                block_time: transaction.block_time,
+                mint: mint.to_string(),
+                price: Some(*price),
+                seller: Some(seller.to_string()),
+                buyer: buyer.map(|buyer| buyer.to_string()),
+                listing_address: Some(listing.to_string()),
+                commitment: transaction.commitment,
+            });
+        }
+    }
+    Ok(records)
+}
+
+// A wallet's side of an event; declared in chronological order within a slot
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WalletActivityKind {
+    Mint,
+    List,
+    Buy,
+    Sell,
+}
+
+impl WalletActivityKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WalletActivityKind::Mint => "mint",
+            WalletActivityKind::List => "list",
+            WalletActivityKind::Buy => "buy",
+            WalletActivityKind::Sell => "sell",
+        }
+    }
+}
+
+impl FromStr for WalletActivityKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "mint" => Ok(WalletActivityKind::Mint),
+            "list" => Ok(WalletActivityKind::List),
+            "buy" => Ok(WalletActivityKind::Buy),
+            "sell" => Ok(WalletActivityKind::Sell),
+            other => Err(format!("Unknown event type {} (expected mint, list, buy or sell)", other)),
+        }
+    }
+}
+
+/// Comma-separated wallet event types, e.g. `buy,sell`
+pub fn parse_wallet_kinds(value: &str) -> Result<Vec<WalletActivityKind>, String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|kind| !kind.is_empty())
+        .map(WalletActivityKind::from_str)
+        .collect()
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WalletActivityEvent {
+    pub event_type: WalletActivityKind,
+    pub mint: String,
+    pub signature: String,
+    pub slot: u64,
+    pub timestamp: Option<String>, // Block time, RFC 3339
+    pub amount: Option<u64>, // Lamports: the sale price, or what the wallet spent on a mint besides the fee
+    pub fee: u64, // Network fee in lamports, when the wallet paid it
+    pub royalties: u64, // Creator royalties in lamports; buy_nft pays none
+    pub counterparty: Option<String>, // The seller of a buy, the buyer of a sell
+    pub commitment: String, // "confirmed" or "finalized"
+}
+
+// One row of a CSV or JSON export, amounts in SOL
+#[derive(Serialize)]
+pub struct WalletActivityExportRow {
+    pub timestamp: String,
+    pub event_type: &'static str,
+    pub mint: String,
+    pub amount_sol: Option<f64>,
+    pub fee_sol: f64,
+    pub royalties_sol: f64,
+    pub counterparty: String,
+    pub signature: String,
+    pub commitment: String,
+}
+
+impl From<&WalletActivityEvent> for WalletActivityExportRow {
+    fn from(event: &WalletActivityEvent) -> Self {
+        WalletActivityExportRow {
+            timestamp: event.timestamp.clone().unwrap_or_default(),
+            event_type: event.event_type.as_str(),
+            mint: event.mint.clone(),
+            amount_sol: event.amount.map(lamports_to_sol),
+            fee_sol: lamports_to_sol(event.fee),
+            royalties_sol: lamports_to_sol(event.royalties),
+            counterparty: event.counterparty.clone().unwrap_or_default(),
+            signature: event.signature.clone(),
+            commitment: event.commitment.clone(),
+        }
+    }
+}
+
+/// Events as CSV with a header row; amounts keep all 9 decimals of SOL
+pub fn wallet_activity_csv(events: &[WalletActivityEvent]) -> String {
+    let sol = |lamports: u64| format!("{:.9}", lamports_to_sol(lamports));
+    let mut csv = String::from("timestamp,event_type,mint,amount_sol,fee_sol,royalties_sol,counterparty,signature,commitment\n");
+    // Every field is base58, RFC 3339, a number or a fixed word, so nothing needs quoting
+    for event in events {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{}\n",
+            event.timestamp.as_deref().unwrap_or_default(),
+            event.event_type.as_str(),
+            event.mint,
+            event.amount.map(sol).unwrap_or_default(),
+            sol(event.fee),
+            sol(event.royalties),
+            event.counterparty.as_deref().unwrap_or_default(),
+            event.signature,
+            event.commitment,
+        ));
+    }
+    csv
+}
+
+/// A wallet's mints, listings, buys and sells with a block time in `[from, to)`,
+/// newest first; `kinds` empty for every event type. Read from the index only:
+/// mints are those of NFTs the indexer has seen, i.e. traded on the marketplace.
+/// `offset` and `limit` page through the events in the database.
+pub async fn wallet_activity(
+    index: &IndexDb,
+    wallet: &Pubkey,
+    kinds: &[WalletActivityKind],
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<usize>,
+    offset: usize,
+) -> Result<Vec<WalletActivityEvent>, String> {
+    index.wallet_activity(&wallet.to_string(), kinds, from, to, limit, offset).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{CacheStore, MemoryStore};
+    use crate::indexer::IndexedTransaction;
+    use crate::memory_chain::MemoryChain;
+    use solana_client::rpc_config::RpcSendTransactionConfig;
+    use solana_sdk::{
+        instruction::Instruction,
+        native_token::LAMPORTS_PER_SOL,
+        program_pack::Pack,
+        rent::Rent,
+        signature::{Keypair, Signer},
+        transaction::Transaction,
+    };
+    use spl_token::state::Mint;
+    use std::{sync::Arc, time::Duration};
+
+    async fn send(chain: &MemoryChain, instructions: &[Instruction], signers: &[&Keypair]) {
+        let blockhash = chain.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(instructions, Some(&signers[0].pubkey()), signers, blockhash);
+        chain.send_transaction_with_config(&transaction, RpcSendTransactionConfig::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn chain_activity_decodes_each_transaction_once() {
+        let chain = Arc::new(MemoryChain::new());
+        let store = Arc::new(MemoryStore::default());
+        let cache = ChainCache::new(chain.clone(), Some(store.clone()), Duration::from_secs(60));
+        let seller = Keypair::new();
+        let mint = Keypair::new();
+        chain.airdrop(&seller.pubkey(), LAMPORTS_PER_SOL);
+        let data = mpl_token_metadata::types::DataV2 {
+            name: "NFT".to_string(),
+            symbol: "TST".to_string(),
+            uri: String::new(),
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: None,
+            uses: None,
+        };
+        let rent = Rent::default().minimum_balance(Mint::LEN);
+        let instructions = crate::nft::mint_instructions(&seller.pubkey(), &seller.pubkey(), &mint.pubkey(), rent, data).unwrap();
+        send(&chain, &instructions, &[&seller, &mint]).await;
+        send(&chain, &[marketplace_program::list_nft(&seller.pubkey(), &mint.pubkey(), 1000)], &[&seller]).await;
+
+        let page = nft_activity(chain.as_ref(), &cache, None, &mint.pubkey(), &[], 0, 10).await.unwrap();
+        let kinds: Vec<ActivityKind> = page.events.iter().map(|event| event.event_type).collect();
+        assert!(kinds == vec![ActivityKind::List, ActivityKind::Mint]);
+        assert_eq!(page.events[0].price, Some(1000));
+
+        // The next read takes the listing transaction from the cache
+        let listed = &page.events[0].signature;
+        let key = format!("chain:finalized:marketplace_transaction:{}", listed);
+        let mut decoded: IndexedTransaction = bincode::deserialize(&store.get(&key).await.unwrap()).unwrap();
+        decoded.events.clear();
+        store.set(&key, bincode::serialize(&decoded).unwrap(), Duration::from_secs(60)).await;
+        let page = nft_activity(chain.as_ref(), &cache, None, &mint.pubkey(), &[], 0, 10).await.unwrap();
+        assert_eq!(page.total_count, 1);
+        assert!(page.events[0].event_type == ActivityKind::Mint);
+    }
+}
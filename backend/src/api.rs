@@ -1,13 +1,16 @@
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    Json,
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use solana_sdk::pubkey::Pubkey;
+use std::{str::FromStr, sync::Arc};
 use utoipa::ToSchema;
 
-use crate::{nft, freepik_api::FreepikApiClient};
+use crate::{activity, auth, cache::ChainCache, fee_payments::FeePaymentLedger, index_db::IndexDb, marketplace, wallet, chain::ChainClient, nft, transactions, freepik_api::FreepikApiClient, rpc_pool::RpcEndpointHealth};
 
 // Import required crates
 extern crate md5;
@@ -15,10 +18,15 @@ extern crate md5;
 // Shared state for the API
 #[derive(Clone)]
 pub struct ApiState {
-    pub solana_client: Arc<solana_client::rpc_client::RpcClient>,
+    pub solana_client: Arc<dyn ChainClient>,
     pub freepik_client: Option<FreepikApiClient>,
     pub keypair: Arc<solana_sdk::signature::Keypair>,
     pub url_mappings: Arc<tokio::sync::RwLock<std::collections::HashMap<String, String>>>,
+    pub fee_payments: FeePaymentLedger,
+    pub auth: auth::AuthState,
+    pub transactions: transactions::TransactionTracker,
+    pub cache: ChainCache,
+    pub index: Option<IndexDb>,
 }
 
 // Standard API Response wrapper
@@ -49,18 +57,144 @@ fn success_response<T>(data: T) -> Json<ApiResponse<T>> {
 
 // Helper function to create error responses
 fn error_response<T>(code: &str, message: &str) -> Result<Json<ApiResponse<T>>, StatusCode> {
+    error_response_with_details(code, message, None)
+}
+
+fn error_response_with_details<T>(
+    code: &str,
+    message: &str,
+    details: Option<serde_json::Value>,
+) -> Result<Json<ApiResponse<T>>, StatusCode> {
     Ok(Json(ApiResponse {
         success: false,
         data: None,
         error: Some(ApiError {
             code: code.to_string(),
             message: message.to_string(),
-            details: None,
+            details,
         }),
         message: Some(message.to_string()),
     }))
 }
 
+// ==================== AUTH APIs ====================
+
+/// Request a single-use nonce for a signed message
+#[derive(Deserialize, ToSchema)]
+pub struct NonceRequest {
+    pub wallet_address: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/auth/nonce",
+    params(
+        ("wallet_address" = String, Query, description = "Wallet that will sign the message")
+    ),
+    responses(
+        (status = 200, description = "Nonce issued", body = ApiResponse<auth::NonceResponse>),
+        (status = 400, description = "Invalid request", body = ApiResponse<()>)
+    ),
+    tag = "auth"
+)]
+pub async fn issue_nonce(
+    State(state): State<super::AppState>,
+    Query(params): Query<NonceRequest>,
+) -> Result<Json<ApiResponse<auth::NonceResponse>>, StatusCode> {
+    match state.api_state.auth.issue_nonce(&params.wallet_address).await {
+        Ok(response) => Ok(success_response(response)),
+        Err(e) => error_response("INVALID_INPUT", &e),
+    }
+}
+
+/// Exchange a signed `login` message for a bearer token
+#[derive(Deserialize, ToSchema)]
+pub struct LoginRequest {
+    pub wallet_address: String,
+    pub signature: String,
+    pub message: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/login",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Session created", body = ApiResponse<auth::SessionResponse>),
+        (status = 401, description = "Invalid signed message", body = ApiResponse<()>)
+    ),
+    tag = "auth"
+)]
+pub async fn login(
+    State(state): State<super::AppState>,
+    Json(req): Json<LoginRequest>,
+) -> Result<Json<ApiResponse<auth::SessionResponse>>, StatusCode> {
+    match state.api_state.auth.login(&req.message, &req.signature, &req.wallet_address).await {
+        Ok(session) => Ok(success_response(session)),
+        Err(e) => error_response(e.code(), &e.to_string()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/logout",
+    responses(
+        (status = 200, description = "Session revoked", body = ApiResponse<()>),
+        (status = 401, description = "Unauthorized", body = ApiResponse<()>)
+    ),
+    security(("bearer_auth" = [])),
+    tag = "auth"
+)]
+pub async fn logout(
+    State(state): State<super::AppState>,
+    request: Request,
+) -> Result<Json<ApiResponse<()>>, StatusCode> {
+    if let Some(token) = bearer_token(&request) {
+        state.api_state.auth.logout(token).await;
+    }
+    Ok(success_response(()))
+}
+
+fn bearer_token(request: &Request) -> Option<&str> {
+    request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+// Middleware for routes that act on behalf of a wallet: resolves the bearer token
+// and exposes the wallet to handlers as an `Extension<auth::AuthenticatedWallet>`
+pub async fn require_session(
+    State(state): State<super::AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let session = match bearer_token(&request) {
+        Some(token) => state.api_state.auth.authenticate(token).await,
+        None => Err(auth::AuthError::InvalidSession),
+    };
+
+    match session {
+        Ok(wallet) => {
+            request.extensions_mut().insert(wallet);
+            next.run(request).await
+        }
+        Err(e) => {
+            let body = ApiResponse::<()> {
+                success: false,
+                data: None,
+                error: Some(ApiError {
+                    code: e.code().to_string(),
+                    message: e.to_string(),
+                    details: None,
+                }),
+                message: Some(e.to_string()),
+            };
+            (StatusCode::UNAUTHORIZED, Json(body)).into_response()
+        }
+    }
+}
+
 // ==================== IMAGE GENERATION APIs ====================
 
 /// Generate AI images from text prompts
@@ -154,12 +288,13 @@ pub async fn generate_images(
 pub struct MintNftRequest {
     pub name: String,
     pub symbol: String,
+    #[allow(dead_code)] // Not yet written to off-chain metadata
     pub description: Option<String>,
     pub image_url: String,
+    #[allow(dead_code)]
     pub attributes: Option<Vec<NftAttribute>>,
-    pub creator_address: String,
-    pub signature: String,
-    pub message: String,
+    pub fee_payment_signature: Option<String>, // Earlier fee transfer to the platform wallet
+    pub fee_quote: Option<String>, // `prepaid_mint_fee.quote` from the fee estimate the payment was made for
 }
 
 #[derive(Deserialize, Serialize, ToSchema)]
@@ -175,6 +310,7 @@ pub struct MintNftResponse {
     pub image_short_url: String,
     pub metadata_url: String,
     pub fee_breakdown: nft::FeeBreakdown,
+    pub transaction: Option<transactions::PreparedTransaction>, // Creator signs, then POST /api/v1/transactions/submit
     pub minted_at: String,
 }
 
@@ -188,10 +324,12 @@ pub struct MintNftResponse {
         (status = 401, description = "Unauthorized", body = ApiResponse<()>),
         (status = 500, description = "Internal server error", body = ApiResponse<()>)
     ),
+    security(("bearer_auth" = [])),
     tag = "nfts"
 )]
 pub async fn mint_nft(
     State(state): State<super::AppState>,
+    Extension(creator): Extension<auth::AuthenticatedWallet>,
     Json(req): Json<MintNftRequest>,
 ) -> Result<Json<ApiResponse<MintNftResponse>>, StatusCode> {
     // Validate input
@@ -212,14 +350,22 @@ pub async fn mint_nft(
         name: req.name,
         symbol: req.symbol,
         uri: image_short_url.clone(),
-        creator_pubkey: req.creator_address,
-        signature: req.signature,
-        message: req.message,
-        fee_payment_signature: None,
+        creator_pubkey: creator.0.to_string(),
+        signature: String::new(), // Authenticated by the session instead
+        message: String::new(),
+        fee_payment_signature: req.fee_payment_signature,
+        fee_quote: req.fee_quote,
     };
 
     // Call the existing mint_nft function
-    match nft::mint_nft(state.api_state.solana_client, &*state.api_state.keypair, backend_req, state.api_state.url_mappings).await {
+    match nft::mint_nft(
+        state.api_state.solana_client,
+        &state.api_state.keypair,
+        backend_req,
+        state.api_state.url_mappings,
+        &state.api_state.fee_payments,
+        &state.api_state.transactions,
+    ).await {
         Ok(result) => {
 
             let response = MintNftResponse {
@@ -228,12 +374,13 @@ pub async fn mint_nft(
                 image_short_url,
                 metadata_url: format!("http://localhost:3001/image/{}", short_id), // Same as image for now
                 fee_breakdown: result.fee_breakdown,
+                transaction: result.transaction,
                 minted_at: chrono::Utc::now().to_rfc3339(),
             };
 
             Ok(success_response(response))
         }
-        Err(e) => error_response("MINT_FAILED", &e),
+        Err(e) => error_response_with_details("MINT_FAILED", &e.to_string(), e.details()),
     }
 }
 
@@ -242,9 +389,9 @@ pub async fn mint_nft(
 /// Get NFTs owned by a wallet
 #[derive(Deserialize, ToSchema)]
 pub struct GetWalletNftsRequest {
-    pub wallet_address: String,
     pub limit: Option<u32>,
     pub offset: Option<u32>,
+    pub resolve_metadata: Option<bool>, // Fetch off-chain JSON for image URLs (default true)
 }
 
 #[derive(Serialize, ToSchema)]
@@ -262,17 +409,21 @@ pub struct NftInfo {
     pub symbol: String,
     pub image_url: String,
     pub metadata_url: String,
+    pub collection: Option<String>, // Verified collection mint
     pub owner: String,
     pub created_at: Option<String>,
 }
 
+const MAX_WALLET_NFTS_LIMIT: u32 = 100;
+
 #[utoipa::path(
     get,
     path = "/api/v1/wallet/{address}/nfts",
     params(
         ("address" = String, Path, description = "Wallet address"),
-        ("limit" = Option<u32>, Query, description = "Number of NFTs to return"),
-        ("offset" = Option<u32>, Query, description = "Offset for pagination")
+        ("limit" = Option<u32>, Query, description = "Number of NFTs to return (default 20, max 100)"),
+        ("offset" = Option<u32>, Query, description = "Offset for pagination"),
+        ("resolve_metadata" = Option<bool>, Query, description = "Fetch off-chain metadata JSON for image URLs (default true)")
     ),
     responses(
         (status = 200, description = "NFTs retrieved successfully", body = ApiResponse<GetWalletNftsResponse>),
@@ -286,15 +437,44 @@ pub async fn get_wallet_nfts(
     Path(wallet_address): Path<String>,
     Query(params): Query<GetWalletNftsRequest>,
 ) -> Result<Json<ApiResponse<GetWalletNftsResponse>>, StatusCode> {
-    // For now, return empty list as we don't have on-chain NFT querying implemented
-    let response = GetWalletNftsResponse {
-        nfts: vec![],
-        total_count: 0,
-        limit: params.limit.unwrap_or(20),
-        offset: params.offset.unwrap_or(0),
+    let owner = match Pubkey::from_str(&wallet_address) {
+        Ok(owner) => owner,
+        Err(_) => return error_response("INVALID_INPUT", "Invalid wallet address"),
     };
+    let limit = params.limit.unwrap_or(20).min(MAX_WALLET_NFTS_LIMIT);
+    let offset = params.offset.unwrap_or(0);
 
-    Ok(success_response(response))
+    let page = match wallet::find_wallet_nfts(
+        state.api_state.solana_client.as_ref(),
+        &state.api_state.cache,
+        &owner,
+        offset as usize,
+        Some(limit as usize),
+        params.resolve_metadata.unwrap_or(true),
+    ).await {
+        Ok(page) => page,
+        Err(e) => return error_response("WALLET_NFTS_FAILED", &e),
+    };
+
+    let nfts = page.nfts.into_iter()
+        .map(|nft| NftInfo {
+            image_url: nft.image_url.unwrap_or_default(),
+            metadata_url: nft.uri,
+            collection: nft.collection.filter(|_| nft.collection_verified),
+            address: nft.mint,
+            name: nft.name,
+            symbol: nft.symbol,
+            owner: wallet_address.clone(),
+            created_at: None,
+        })
+        .collect();
+
+    Ok(success_response(GetWalletNftsResponse {
+        nfts,
+        total_count: page.total_count as u32,
+        limit,
+        offset,
+    }))
 }
 
 // ==================== MARKETPLACE APIs ====================
@@ -304,15 +484,12 @@ pub async fn get_wallet_nfts(
 pub struct ListNftRequest {
     pub nft_address: String,
     pub price: u64, // Price in lamports
-    pub seller_address: String,
-    pub signature: String,
-    pub message: String,
 }
 
 #[derive(Serialize, ToSchema)]
 pub struct ListNftResponse {
     pub listing_address: String,
-    pub transaction_signature: String,
+    pub transaction: transactions::PreparedTransaction, // Seller signs, then POST /api/v1/transactions/submit
     pub listed_at: String,
 }
 
@@ -321,33 +498,84 @@ pub struct ListNftResponse {
     path = "/api/v1/marketplace/list",
     request_body = ListNftRequest,
     responses(
-        (status = 200, description = "NFT listed successfully", body = ApiResponse<ListNftResponse>),
+        (status = 200, description = "Listing transaction prepared", body = ApiResponse<ListNftResponse>),
         (status = 400, description = "Invalid request", body = ApiResponse<()>),
         (status = 401, description = "Unauthorized", body = ApiResponse<()>),
         (status = 500, description = "Internal server error", body = ApiResponse<()>)
     ),
+    security(("bearer_auth" = [])),
     tag = "marketplace"
 )]
 pub async fn list_nft(
     State(state): State<super::AppState>,
+    Extension(seller): Extension<auth::AuthenticatedWallet>,
     Json(req): Json<ListNftRequest>,
 ) -> Result<Json<ApiResponse<ListNftResponse>>, StatusCode> {
     let nft_req = nft::ListNftRequest {
         nft_address: req.nft_address,
         price: req.price,
-        seller_pubkey: req.seller_address,
+        seller_pubkey: seller.0.to_string(),
     };
 
-    match nft::list_nft(state.api_state.solana_client, &*state.api_state.keypair, nft_req).await {
+    match nft::list_nft(state.api_state.solana_client, &state.api_state.transactions, nft_req).await {
         Ok(result) => {
             let response = ListNftResponse {
-                listing_address: result["listing_address"].as_str().unwrap_or("").to_string(),
-                transaction_signature: result["transaction_signature"].as_str().unwrap_or("").to_string(),
+                listing_address: result.listing_address,
+                transaction: result.transaction,
                 listed_at: chrono::Utc::now().to_rfc3339(),
             };
             Ok(success_response(response))
         }
-        Err(e) => error_response("LIST_FAILED", &e),
+        Err(e) => error_response_with_details(e.code(), &e.to_string(), e.details()),
+    }
+}
+
+/// Buy a listed NFT
+#[derive(Deserialize, ToSchema)]
+pub struct BuyNftRequest {
+    pub listing_address: String,
+    pub nft_address: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BuyNftResponse {
+    pub listing_address: String,
+    pub transaction: transactions::PreparedTransaction, // Buyer signs, then POST /api/v1/transactions/submit
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/marketplace/buy",
+    request_body = BuyNftRequest,
+    responses(
+        (status = 200, description = "Purchase transaction prepared", body = ApiResponse<BuyNftResponse>),
+        (status = 400, description = "Invalid request", body = ApiResponse<()>),
+        (status = 401, description = "Unauthorized", body = ApiResponse<()>),
+        (status = 500, description = "Internal server error", body = ApiResponse<()>)
+    ),
+    security(("bearer_auth" = [])),
+    tag = "marketplace"
+)]
+pub async fn buy_nft(
+    State(state): State<super::AppState>,
+    Extension(buyer): Extension<auth::AuthenticatedWallet>,
+    Json(req): Json<BuyNftRequest>,
+) -> Result<Json<ApiResponse<BuyNftResponse>>, StatusCode> {
+    let nft_req = nft::BuyNftRequest {
+        listing_address: req.listing_address.clone(),
+        nft_address: req.nft_address,
+        buyer_pubkey: buyer.0.to_string(),
+    };
+
+    match nft::buy_nft(state.api_state.solana_client, &state.api_state.transactions, nft_req).await {
+        Ok(result) => {
+            let response = BuyNftResponse {
+                listing_address: req.listing_address,
+                transaction: result.transaction,
+            };
+            Ok(success_response(response))
+        }
+        Err(e) => error_response_with_details("BUY_FAILED", &e.to_string(), e.details()),
     }
 }
 
@@ -357,6 +585,8 @@ pub struct GetListingsRequest {
     pub limit: Option<u32>,
     pub offset: Option<u32>,
     pub sort_by: Option<String>, // "price_asc", "price_desc", "recent"
+    pub min_price: Option<u64>, // Lamports, inclusive
+    pub max_price: Option<u64>,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -378,33 +608,449 @@ pub struct NftListing {
     pub listed_at: String,
 }
 
+const MAX_LISTINGS_LIMIT: u32 = 100;
+
 #[utoipa::path(
     get,
     path = "/api/v1/marketplace/listings",
     params(
-        ("limit" = Option<u32>, Query, description = "Number of listings to return"),
+        ("limit" = Option<u32>, Query, description = "Number of listings to return (default 20, max 100)"),
         ("offset" = Option<u32>, Query, description = "Offset for pagination"),
-        ("sort_by" = Option<String>, Query, description = "Sort order")
+        ("sort_by" = Option<String>, Query, description = "Sort order: price_asc, price_desc or recent (default)"),
+        ("min_price" = Option<u64>, Query, description = "Minimum price in lamports"),
+        ("max_price" = Option<u64>, Query, description = "Maximum price in lamports")
     ),
     responses(
         (status = 200, description = "Listings retrieved successfully", body = ApiResponse<GetListingsResponse>),
+        (status = 400, description = "Invalid request", body = ApiResponse<()>),
         (status = 500, description = "Internal server error", body = ApiResponse<()>)
     ),
     tag = "marketplace"
 )]
 pub async fn get_listings(
-    State(_state): State<super::AppState>,
+    State(state): State<super::AppState>,
     Query(params): Query<GetListingsRequest>,
 ) -> Result<Json<ApiResponse<GetListingsResponse>>, StatusCode> {
-    // For now, return empty list as we don't have on-chain listing querying implemented
-    let response = GetListingsResponse {
-        listings: vec![],
-        total_count: 0,
-        limit: params.limit.unwrap_or(20),
-        offset: params.offset.unwrap_or(0),
+    let sort = match params.sort_by.as_deref().map(marketplace::ListingSort::from_str).transpose() {
+        Ok(sort) => sort.unwrap_or(marketplace::ListingSort::Recent),
+        Err(e) => return error_response("INVALID_INPUT", &e),
     };
+    let limit = params.limit.unwrap_or(20).min(MAX_LISTINGS_LIMIT);
+    let offset = params.offset.unwrap_or(0);
 
-    Ok(success_response(response))
+    let query = marketplace::ListingQuery {
+        text: None,
+        min_price: params.min_price,
+        max_price: params.max_price,
+        sort,
+        offset: offset as usize,
+        limit: limit as usize,
+    };
+    let page = match marketplace::query_listings(&state.api_state.cache, &query).await {
+        Ok(page) => page,
+        Err(e) => return error_response("LISTINGS_FAILED", &e),
+    };
+
+    let listings = page.listings.iter()
+        .map(|listing| NftListing {
+            listing_address: listing.address.to_string(),
+            nft_address: listing.listing.nft_mint.to_string(),
+            name: listing.name(),
+            image_url: listing.image_url(),
+            price: listing.listing.price,
+            seller: listing.listing.seller.to_string(),
+            listed_at: listing.listed_at_rfc3339(),
+        })
+        .collect();
+
+    Ok(success_response(GetListingsResponse {
+        listings,
+        total_count: page.total_count as u32,
+        limit,
+        offset,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/marketplace/stats",
+    responses(
+        (status = 200, description = "Marketplace stats retrieved successfully", body = ApiResponse<marketplace::MarketplaceStatsResponse>),
+        (status = 500, description = "Internal server error", body = ApiResponse<()>)
+    ),
+    tag = "marketplace"
+)]
+pub async fn get_marketplace_stats(
+    State(state): State<super::AppState>,
+) -> Result<Json<ApiResponse<marketplace::MarketplaceStatsResponse>>, StatusCode> {
+    marketplace_stats_response(&state.api_state, None).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/collections/{address}/stats",
+    params(
+        ("address" = String, Path, description = "Verified collection mint address")
+    ),
+    responses(
+        (status = 200, description = "Collection stats retrieved successfully", body = ApiResponse<marketplace::MarketplaceStatsResponse>),
+        (status = 400, description = "Invalid collection address", body = ApiResponse<()>),
+        (status = 500, description = "Internal server error", body = ApiResponse<()>)
+    ),
+    tag = "marketplace"
+)]
+pub async fn get_collection_stats(
+    State(state): State<super::AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<ApiResponse<marketplace::MarketplaceStatsResponse>>, StatusCode> {
+    let collection = match Pubkey::from_str(&address) {
+        Ok(collection) => collection,
+        Err(_) => return error_response("INVALID_INPUT", "Invalid collection address"),
+    };
+    marketplace_stats_response(&state.api_state, Some(&collection)).await
+}
+
+async fn marketplace_stats_response(
+    state: &ApiState,
+    collection: Option<&Pubkey>,
+) -> Result<Json<ApiResponse<marketplace::MarketplaceStatsResponse>>, StatusCode> {
+    if state.index.is_none() {
+        return error_response("INDEX_UNAVAILABLE", marketplace::INDEX_UNAVAILABLE);
+    }
+    match marketplace::get_marketplace_stats(state.index.as_ref(), collection).await {
+        Ok(stats) => Ok(success_response(stats)),
+        Err(e) => error_response("STATS_FAILED", &e),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CollectionHistoryRequest {
+    pub interval: Option<String>, // "1h" or "1d" (default)
+    pub from: Option<String>, // RFC 3339
+    pub to: Option<String>, // RFC 3339, default now
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/collections/{address}/history",
+    params(
+        ("address" = String, Path, description = "Verified collection mint address"),
+        ("interval" = Option<String>, Query, description = "Candle interval: 1h or 1d (default)"),
+        ("from" = Option<String>, Query, description = "RFC 3339 start (default 24 hourly or 30 daily candles before to)"),
+        ("to" = Option<String>, Query, description = "RFC 3339 end (default now)")
+    ),
+    responses(
+        (status = 200, description = "Collection price history retrieved successfully", body = ApiResponse<marketplace::CollectionHistoryResponse>),
+        (status = 400, description = "Invalid request", body = ApiResponse<()>),
+        (status = 500, description = "Internal server error", body = ApiResponse<()>)
+    ),
+    tag = "marketplace"
+)]
+pub async fn get_collection_history(
+    State(state): State<super::AppState>,
+    Path(address): Path<String>,
+    Query(params): Query<CollectionHistoryRequest>,
+) -> Result<Json<ApiResponse<marketplace::CollectionHistoryResponse>>, StatusCode> {
+    let collection = match Pubkey::from_str(&address) {
+        Ok(collection) => collection,
+        Err(_) => return error_response("INVALID_INPUT", "Invalid collection address"),
+    };
+    let interval = match params.interval.as_deref().map(str::parse).transpose() {
+        Ok(interval) => interval.unwrap_or(marketplace::CandleInterval::Day),
+        Err(e) => return error_response("INVALID_INPUT", &e),
+    };
+    let parse_time = |time: &Option<String>| time.as_deref()
+        .map(|time| chrono::DateTime::parse_from_rfc3339(time).map(|time| time.timestamp()))
+        .transpose();
+    let (from, to) = match (parse_time(&params.from), parse_time(&params.to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        _ => return error_response("INVALID_INPUT", "from and to must be RFC 3339 times"),
+    };
+    let now = chrono::Utc::now().timestamp();
+    let range = match marketplace::history_range(interval, from, to, now) {
+        Ok(range) => range,
+        Err(e) => return error_response("INVALID_INPUT", &e),
+    };
+
+    if state.api_state.index.is_none() {
+        return error_response("INDEX_UNAVAILABLE", marketplace::INDEX_UNAVAILABLE);
+    }
+    match marketplace::get_collection_history(state.api_state.index.as_ref(), &collection, interval, range, now).await {
+        Ok(history) => Ok(success_response(history)),
+        Err(e) => error_response("STATS_FAILED", &e),
+    }
+}
+
+// ==================== ACTIVITY APIs ====================
+
+#[derive(Deserialize, ToSchema)]
+pub struct NftActivityRequest {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    pub event_type: Option<String>, // Comma-separated: "mint", "list", "sale"
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NftActivityResponse {
+    pub events: Vec<activity::ActivityEvent>,
+    pub total_count: u32,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+const MAX_ACTIVITY_LIMIT: u32 = 100;
+
+// Events one wallet activity export can hold
+const MAX_EXPORT_EVENTS: usize = 10_000;
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/nfts/{address}/activity",
+    params(
+        ("address" = String, Path, description = "NFT mint address"),
+        ("limit" = Option<u32>, Query, description = "Number of events to return (default 20, max 100)"),
+        ("offset" = Option<u32>, Query, description = "Offset for pagination"),
+        ("event_type" = Option<String>, Query, description = "Comma-separated event types to include: mint, list, sale")
+    ),
+    responses(
+        (status = 200, description = "NFT activity retrieved successfully", body = ApiResponse<NftActivityResponse>),
+        (status = 400, description = "Invalid request", body = ApiResponse<()>),
+        (status = 500, description = "Internal server error", body = ApiResponse<()>)
+    ),
+    tag = "nft"
+)]
+pub async fn get_nft_activity(
+    State(state): State<super::AppState>,
+    Path(address): Path<String>,
+    Query(params): Query<NftActivityRequest>,
+) -> Result<Json<ApiResponse<NftActivityResponse>>, StatusCode> {
+    let mint = match Pubkey::from_str(&address) {
+        Ok(mint) => mint,
+        Err(_) => return error_response("INVALID_INPUT", "Invalid mint address"),
+    };
+    let kinds = match params.event_type.as_deref().map(activity::parse_kinds).transpose() {
+        Ok(kinds) => kinds.unwrap_or_default(),
+        Err(e) => return error_response("INVALID_INPUT", &e),
+    };
+    let limit = params.limit.unwrap_or(20).min(MAX_ACTIVITY_LIMIT);
+    let offset = params.offset.unwrap_or(0);
+
+    let state = &state.api_state;
+    let page = activity::nft_activity(
+        state.solana_client.as_ref(),
+        &state.cache,
+        state.index.as_ref(),
+        &mint,
+        &kinds,
+        offset as usize,
+        limit as usize,
+    )
+    .await;
+    match page {
+        Ok(page) => Ok(success_response(NftActivityResponse {
+            events: page.events,
+            total_count: page.total_count as u32,
+            limit,
+            offset,
+        })),
+        Err(e) => error_response("ACTIVITY_FAILED", &e),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct WalletActivityRequest {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    pub event_type: Option<String>, // Comma-separated: "mint", "list", "buy", "sell"
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WalletActivityResponse {
+    pub events: Vec<activity::WalletActivityEvent>,
+    pub total_count: u32,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ExportWalletActivityRequest {
+    pub format: Option<String>, // "csv" (default) or "json"
+    pub event_type: Option<String>,
+    pub from: Option<String>, // RFC 3339, inclusive
+    pub to: Option<String>, // RFC 3339, exclusive
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/wallet/{address}/activity",
+    params(
+        ("address" = String, Path, description = "Wallet address"),
+        ("limit" = Option<u32>, Query, description = "Number of events to return (default 20, max 100)"),
+        ("offset" = Option<u32>, Query, description = "Offset for pagination"),
+        ("event_type" = Option<String>, Query, description = "Comma-separated event types to include: mint, list, buy, sell")
+    ),
+    responses(
+        (status = 200, description = "Wallet activity retrieved successfully", body = ApiResponse<WalletActivityResponse>),
+        (status = 400, description = "Invalid request", body = ApiResponse<()>),
+        (status = 500, description = "Internal server error", body = ApiResponse<()>)
+    ),
+    tag = "wallet"
+)]
+pub async fn get_wallet_activity(
+    State(state): State<super::AppState>,
+    Path(address): Path<String>,
+    Query(params): Query<WalletActivityRequest>,
+) -> Result<Json<ApiResponse<WalletActivityResponse>>, StatusCode> {
+    let limit = params.limit.unwrap_or(20).min(MAX_ACTIVITY_LIMIT);
+    let offset = params.offset.unwrap_or(0);
+    let (index, wallet, kinds) = match wallet_activity_query(&state.api_state, &address, params.event_type.as_deref()) {
+        Ok(query) => query,
+        Err((code, message)) => return error_response(code, &message),
+    };
+    let total_count = match index.wallet_activity_count(&address, &kinds, None, None).await {
+        Ok(count) => count as u32,
+        Err(e) => return error_response("ACTIVITY_FAILED", &e),
+    };
+    match activity::wallet_activity(index, &wallet, &kinds, None, None, Some(limit as usize), offset as usize).await {
+        Ok(events) => Ok(success_response(WalletActivityResponse { events, total_count, limit, offset })),
+        Err(e) => error_response("ACTIVITY_FAILED", &e),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/wallet/{address}/activity/export",
+    params(
+        ("address" = String, Path, description = "Wallet address"),
+        ("format" = Option<String>, Query, description = "csv (default) or json"),
+        ("event_type" = Option<String>, Query, description = "Comma-separated event types to include: mint, list, buy, sell"),
+        ("from" = Option<String>, Query, description = "Only events at or after this RFC 3339 time"),
+        ("to" = Option<String>, Query, description = "Only events before this RFC 3339 time")
+    ),
+    responses(
+        (status = 200, description = "Wallet activity as a CSV or JSON attachment, oldest first"),
+        (status = 400, description = "Invalid request", body = ApiResponse<()>),
+        (status = 500, description = "Internal server error", body = ApiResponse<()>)
+    ),
+    tag = "wallet"
+)]
+pub async fn export_wallet_activity(
+    State(state): State<super::AppState>,
+    Path(address): Path<String>,
+    Query(params): Query<ExportWalletActivityRequest>,
+) -> Response {
+    let csv = match params.format.as_deref() {
+        None | Some("csv") => true,
+        Some("json") => false,
+        Some(_) => return error_response::<()>("INVALID_INPUT", "format must be csv or json").into_response(),
+    };
+    let parse_time = |time: &Option<String>| time.as_deref()
+        .map(|time| chrono::DateTime::parse_from_rfc3339(time).map(|time| time.timestamp()))
+        .transpose();
+    let (from, to) = match (parse_time(&params.from), parse_time(&params.to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        _ => return error_response::<()>("INVALID_INPUT", "from and to must be RFC 3339 times").into_response(),
+    };
+
+    let (index, wallet, kinds) = match wallet_activity_query(&state.api_state, &address, params.event_type.as_deref()) {
+        Ok(query) => query,
+        Err((code, message)) => return error_response::<()>(code, &message).into_response(),
+    };
+    // More events than an export holds is an error rather than a truncated history
+    match index.wallet_activity_count(&address, &kinds, from, to).await {
+        Ok(count) if count > MAX_EXPORT_EVENTS as u64 => {
+            let message = format!(
+                "{} events in range, more than the {} an export can hold; narrow it with from and to",
+                count, MAX_EXPORT_EVENTS,
+            );
+            return error_response::<()>("EXPORT_TOO_LARGE", &message).into_response();
+        }
+        Ok(_) => {}
+        Err(e) => return error_response::<()>("ACTIVITY_FAILED", &e).into_response(),
+    }
+    let mut events = match activity::wallet_activity(index, &wallet, &kinds, from, to, Some(MAX_EXPORT_EVENTS), 0).await {
+        Ok(events) => events,
+        Err(e) => return error_response::<()>("ACTIVITY_FAILED", &e).into_response(),
+    };
+    // Ledgers read oldest first
+    events.reverse();
+
+    let (content_type, extension, body) = if csv {
+        ("text/csv; charset=utf-8", "csv", activity::wallet_activity_csv(&events))
+    } else {
+        let rows: Vec<activity::WalletActivityExportRow> = events.iter().map(Into::into).collect();
+        ("application/json", "json", serde_json::to_string(&rows).unwrap_or_default())
+    };
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"wallet-activity-{}.{}\"", address, extension)),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+// The index, wallet and event types of a wallet activity request, or an error
+// code and message
+fn wallet_activity_query<'a>(
+    state: &'a ApiState,
+    address: &str,
+    event_type: Option<&str>,
+) -> Result<(&'a IndexDb, Pubkey, Vec<activity::WalletActivityKind>), (&'static str, String)> {
+    let wallet = Pubkey::from_str(address)
+        .map_err(|_| ("INVALID_INPUT", "Invalid wallet address".to_string()))?;
+    let kinds = event_type.map(activity::parse_wallet_kinds).transpose()
+        .map_err(|e| ("INVALID_INPUT", e))?
+        .unwrap_or_default();
+    let index = state.index.as_ref()
+        .ok_or(("INDEX_UNAVAILABLE", marketplace::INDEX_UNAVAILABLE.to_string()))?;
+    Ok((index, wallet, kinds))
+}
+
+// ==================== TRANSACTION APIs ====================
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/transactions/submit",
+    request_body = transactions::SubmitTransactionRequest,
+    responses(
+        (status = 200, description = "Transaction sent", body = ApiResponse<transactions::SubmitTransactionResponse>),
+        (status = 400, description = "Invalid or failing transaction", body = ApiResponse<()>)
+    ),
+    tag = "transactions"
+)]
+pub async fn submit_transaction(
+    State(state): State<super::AppState>,
+    Json(req): Json<transactions::SubmitTransactionRequest>,
+) -> Result<Json<ApiResponse<transactions::SubmitTransactionResponse>>, StatusCode> {
+    match transactions::submit(state.api_state.solana_client, &state.api_state.transactions, &req.transaction).await {
+        Ok(response) => Ok(success_response(response)),
+        Err(e) => error_response_with_details("SUBMIT_FAILED", &e.to_string(), e.details()),
+    }
+}
+
+/// Poll the status of a transaction sent by the API
+#[utoipa::path(
+    get,
+    path = "/api/v1/transactions/{signature}",
+    params(
+        ("signature" = String, Path, description = "Transaction signature returned by a mint or submit request")
+    ),
+    responses(
+        (status = 200, description = "Transaction status", body = ApiResponse<transactions::TransactionStatusResponse>),
+        (status = 404, description = "Transaction not sent by this API", body = ApiResponse<()>)
+    ),
+    tag = "transactions"
+)]
+pub async fn get_transaction_status(
+    State(state): State<super::AppState>,
+    Path(signature): Path<String>,
+) -> Result<Json<ApiResponse<transactions::TransactionStatusResponse>>, StatusCode> {
+    match state.api_state.transactions.get(&signature).await {
+        Some(status) => Ok(success_response(status)),
+        None => error_response("TRANSACTION_NOT_FOUND", "No transaction with this signature was sent by this API"),
+    }
 }
 
 // ==================== UTILITY APIs ====================
@@ -412,9 +1058,11 @@ pub async fn get_listings(
 /// Get fee estimates for operations
 #[derive(Serialize, ToSchema)]
 pub struct FeeEstimateResponse {
-    pub mint_fee: nft::FeeBreakdown,
-    pub list_fee: u64, // Estimated listing fee in lamports
-    pub buy_fee: u64,  // Estimated buy fee in lamports
+    pub mint_fee: nft::FeeBreakdown, // Co-signed mint
+    pub prepaid_mint_fee: nft::FeeQuote, // Mint funded by an earlier `fee_payment_signature`
+    pub list_fee: u64, // Estimated listing cost in lamports, including rent
+    pub buy_fee: u64,  // Estimated buy cost in lamports, excluding the price
+    pub actions: Vec<nft::ActionFeeEstimate>,
 }
 
 #[utoipa::path(
@@ -429,12 +1077,17 @@ pub struct FeeEstimateResponse {
 pub async fn get_fee_estimates(
     State(state): State<super::AppState>,
 ) -> Result<Json<ApiResponse<FeeEstimateResponse>>, StatusCode> {
-    match nft::get_fee_estimate(state.api_state.solana_client, &*state.api_state.keypair).await {
+    match nft::get_fee_estimate(state.api_state.solana_client, &state.api_state.keypair).await {
         Ok(fee_estimate) => {
+            let total = |action: &str| fee_estimate.actions.iter()
+                .find(|estimate| estimate.action == action)
+                .map_or(0, |estimate| estimate.total);
             let response = FeeEstimateResponse {
                 mint_fee: fee_estimate.fee_breakdown,
-                list_fee: 5000, // Estimated transaction fee
-                buy_fee: 5000,  // Estimated transaction fee
+                prepaid_mint_fee: fee_estimate.prepaid_fee,
+                list_fee: total("list_nft"),
+                buy_fee: total("buy_nft"),
+                actions: fee_estimate.actions,
             };
             Ok(success_response(response))
         }
@@ -451,18 +1104,33 @@ pub async fn get_fee_estimates(
     ),
     tag = "utilities"
 )]
-pub async fn health_check() -> Json<ApiResponse<HealthResponse>> {
+pub async fn health_check(
+    State(state): State<super::AppState>,
+) -> Json<ApiResponse<HealthResponse>> {
+    let rpc_endpoints = state.api_state.solana_client.endpoint_health();
+    let healthy_endpoints = rpc_endpoints.iter().filter(|endpoint| endpoint.healthy).count();
+    // Degraded while failing over, unhealthy once no endpoint is usable
+    let status = if healthy_endpoints == rpc_endpoints.len() {
+        "healthy"
+    } else if healthy_endpoints > 0 {
+        "degraded"
+    } else {
+        "unhealthy"
+    };
+
     let response = HealthResponse {
-        status: "healthy".to_string(),
+        status: status.to_string(),
         timestamp: chrono::Utc::now().to_rfc3339(),
         version: env!("CARGO_PKG_VERSION").to_string(),
+        rpc_endpoints,
     };
     success_response(response)
 }
 
 #[derive(Serialize, ToSchema)]
 pub struct HealthResponse {
-    pub status: String,
+    pub status: String, // healthy, degraded or unhealthy
     pub timestamp: String,
     pub version: String,
+    pub rpc_endpoints: Vec<RpcEndpointHealth>,
 }
\ No newline at end of file
@@ -0,0 +1,410 @@
+// Structured wallet authentication (Sign-In-With-Solana style messages with nonces)
+use rand::RngCore;
+use serde::Serialize;
+use solana_sdk::{hash::hashv, pubkey::Pubkey};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+use utoipa::ToSchema;
+
+// How long an issued nonce can wait before being used
+const NONCE_TTL_SECS: i64 = 300;
+
+// Longest validity window a signed message may declare
+const MAX_MESSAGE_LIFETIME_SECS: i64 = 600;
+
+// Tolerated clock difference between the wallet and the backend
+const CLOCK_SKEW_SECS: i64 = 60;
+
+// Lifetime of a bearer token issued by a wallet login
+const SESSION_TTL_SECS: i64 = 3600;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Invalid signature - message not signed by the provided wallet")]
+    InvalidSignature,
+    #[error("Malformed sign-in message: {0}")]
+    MalformedMessage(String),
+    #[error("Sign-in message does not match this request: {0}")]
+    MessageMismatch(String),
+    #[error("Sign-in message has expired or is not yet valid")]
+    MessageExpired,
+    #[error("Nonce is unknown, expired or already used")]
+    InvalidNonce,
+    #[error("Missing, invalid or expired session token")]
+    InvalidSession,
+}
+
+impl AuthError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthError::InvalidSignature => "INVALID_SIGNATURE",
+            AuthError::MalformedMessage(_) => "MALFORMED_MESSAGE",
+            AuthError::MessageMismatch(_) => "MESSAGE_MISMATCH",
+            AuthError::MessageExpired => "MESSAGE_EXPIRED",
+            AuthError::InvalidNonce => "INVALID_NONCE",
+            AuthError::InvalidSession => "UNAUTHORIZED",
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NonceResponse {
+    pub nonce: String,
+    pub domain: String,
+    pub wallet_address: String,
+    pub issued_at: String,
+    pub expiration_time: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SessionResponse {
+    pub token: String,
+    pub wallet_address: String,
+    pub expires_at: String,
+}
+
+// Wallet proven by the bearer token, inserted into request extensions by the session middleware
+#[derive(Clone)]
+pub struct AuthenticatedWallet(pub Pubkey);
+
+struct Session {
+    wallet: Pubkey,
+    expires_at: i64,
+}
+
+/// A parsed sign-in message:
+///
+/// ```text
+/// {domain} wants you to sign in with your Solana account:
+/// {wallet}
+///
+/// Action: {action}
+/// Request Hash: {request_hash}
+/// Nonce: {nonce}
+/// Issued At: {issued_at}
+/// Expiration Time: {expiration_time}
+/// ```
+pub struct SignInMessage {
+    pub domain: String,
+    pub wallet: Pubkey,
+    pub action: String,
+    pub request_hash: String,
+    pub nonce: String,
+    pub issued_at: chrono::DateTime<chrono::Utc>,
+    pub expiration_time: chrono::DateTime<chrono::Utc>,
+}
+
+impl SignInMessage {
+    pub fn parse(message: &str) -> Result<Self, AuthError> {
+        let malformed = |reason: &str| AuthError::MalformedMessage(reason.to_string());
+
+        let mut lines = message.lines();
+        let domain = lines.next()
+            .and_then(|line| line.strip_suffix(" wants you to sign in with your Solana account:"))
+            .ok_or_else(|| malformed("missing domain header"))?
+            .to_string();
+        let wallet = lines.next()
+            .and_then(|line| Pubkey::from_str(line.trim()).ok())
+            .ok_or_else(|| malformed("missing or invalid wallet address"))?;
+        if lines.next() != Some("") {
+            return Err(malformed("expected a blank line after the wallet address"));
+        }
+
+        let mut fields = HashMap::new();
+        for line in lines {
+            let (key, value) = line.split_once(": ")
+                .ok_or_else(|| malformed("fields must be formatted as `Key: value`"))?;
+            fields.insert(key, value.trim().to_string());
+        }
+
+        let mut field = |name: &str| fields.remove(name)
+            .ok_or_else(|| AuthError::MalformedMessage(format!("missing `{}`", name)));
+        let timestamp = |value: String| chrono::DateTime::parse_from_rfc3339(&value)
+            .map(|t| t.with_timezone(&chrono::Utc))
+            .map_err(|_| AuthError::MalformedMessage(format!("invalid timestamp `{}`", value)));
+
+        Ok(SignInMessage {
+            domain,
+            wallet,
+            action: field("Action")?,
+            request_hash: field("Request Hash")?,
+            nonce: field("Nonce")?,
+            issued_at: timestamp(field("Issued At")?)?,
+            expiration_time: timestamp(field("Expiration Time")?)?,
+        })
+    }
+}
+
+/// Hash of the request fields a signed message commits to: SHA-256 over the
+/// fields joined with `\n`, base58 encoded
+pub fn request_hash(fields: &[&str]) -> String {
+    solana_sdk::hash::hash(fields.join("\n").as_bytes()).to_string()
+}
+
+// Issues single-use nonces and checks signed messages against them. Nonces are
+// `{expires_at}.{random}.{mac}`, authenticated with a per-process key, so issuing
+// one stores nothing; only nonces that were used are kept, until they expire.
+#[derive(Clone)]
+pub struct AuthState {
+    domain: String,
+    nonce_key: [u8; 32],
+    used_nonces: Arc<tokio::sync::RwLock<HashMap<String, i64>>>, // Nonce to expiry
+    sessions: Arc<tokio::sync::RwLock<HashMap<String, Session>>>,
+}
+
+impl AuthState {
+    pub fn new(domain: String) -> Self {
+        let mut nonce_key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut nonce_key);
+        Self {
+            domain,
+            nonce_key,
+            used_nonces: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
+            sessions: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub async fn issue_nonce(&self, wallet_address: &str) -> Result<NonceResponse, String> {
+        let wallet = Pubkey::from_str(wallet_address)
+            .map_err(|_| "Invalid wallet address".to_string())?;
+
+        let issued_at = chrono::Utc::now();
+        let expiration_time = issued_at + chrono::Duration::seconds(NONCE_TTL_SECS);
+        let expires_at = expiration_time.timestamp();
+        let random = random_token::<16>();
+        let nonce = format!("{}.{}.{}", expires_at, random, self.nonce_mac(&wallet, expires_at, &random));
+
+        Ok(NonceResponse {
+            nonce,
+            domain: self.domain.clone(),
+            wallet_address: wallet.to_string(),
+            issued_at: issued_at.to_rfc3339(),
+            expiration_time: expiration_time.to_rfc3339(),
+        })
+    }
+
+    /// Verify a signed sign-in message for `action` on behalf of `wallet_address`,
+    /// consuming its nonce so the same message cannot be replayed
+    pub async fn verify(
+        &self,
+        message: &str,
+        signature: &str,
+        wallet_address: &str,
+        action: &str,
+        request_hash: &str,
+    ) -> Result<SignInMessage, AuthError> {
+        if !crate::wallet::validate_signature(message, signature, wallet_address) {
+            return Err(AuthError::InvalidSignature);
+        }
+
+        let parsed = SignInMessage::parse(message)?;
+        let mismatch = |field: &str| AuthError::MessageMismatch(field.to_string());
+
+        if parsed.domain != self.domain {
+            return Err(mismatch("domain"));
+        }
+        if parsed.wallet.to_string() != wallet_address {
+            return Err(mismatch("wallet"));
+        }
+        if parsed.action != action {
+            return Err(mismatch("action"));
+        }
+        if parsed.request_hash != request_hash {
+            return Err(mismatch("request hash"));
+        }
+
+        let now = chrono::Utc::now();
+        let lifetime = parsed.expiration_time - parsed.issued_at;
+        if parsed.issued_at > now + chrono::Duration::seconds(CLOCK_SKEW_SECS)
+            || parsed.expiration_time <= now
+            || lifetime > chrono::Duration::seconds(MAX_MESSAGE_LIFETIME_SECS)
+        {
+            return Err(AuthError::MessageExpired);
+        }
+
+        // Used nonces are remembered until they expire, so a replayed message is refused
+        let expires_at = self.check_nonce(&parsed.nonce, &parsed.wallet, now.timestamp())?;
+        let mut used = self.used_nonces.write().await;
+        if used.contains_key(&parsed.nonce) {
+            return Err(AuthError::InvalidNonce);
+        }
+        used.retain(|_, expires_at| *expires_at > now.timestamp());
+        used.insert(parsed.nonce.clone(), expires_at);
+
+        Ok(parsed)
+    }
+
+    // HMAC-SHA256 of a nonce's wallet, expiry and random part, base58 encoded
+    fn nonce_mac(&self, wallet: &Pubkey, expires_at: i64, random: &str) -> String {
+        let mut key = [0u8; 64];
+        key[..32].copy_from_slice(&self.nonce_key);
+        let inner_key: Vec<u8> = key.iter().map(|byte| byte ^ 0x36).collect();
+        let outer_key: Vec<u8> = key.iter().map(|byte| byte ^ 0x5c).collect();
+        let inner = hashv(&[&inner_key, wallet.as_ref(), &expires_at.to_le_bytes(), random.as_bytes()]);
+        hashv(&[&outer_key, inner.as_ref()]).to_string()
+    }
+
+    // Expiry of a nonce this server issued to `wallet`, if it hasn't expired
+    fn check_nonce(&self, nonce: &str, wallet: &Pubkey, now: i64) -> Result<i64, AuthError> {
+        let mut parts = nonce.splitn(3, '.');
+        let (Some(expires_at), Some(random), Some(mac)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(AuthError::InvalidNonce);
+        };
+        let expires_at: i64 = expires_at.parse().map_err(|_| AuthError::InvalidNonce)?;
+        let expected = self.nonce_mac(wallet, expires_at, random);
+        // Compared in full, so the time taken doesn't reveal a matching prefix
+        let matches = expected.len() == mac.len()
+            && expected.bytes().zip(mac.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0;
+        if !matches || expires_at <= now {
+            return Err(AuthError::InvalidNonce);
+        }
+        Ok(expires_at)
+    }
+
+    /// Exchange a signed `login` message for a short-lived bearer token
+    pub async fn login(
+        &self,
+        message: &str,
+        signature: &str,
+        wallet_address: &str,
+    ) -> Result<SessionResponse, AuthError> {
+        let request_hash = request_hash(&[wallet_address]);
+        let signed = self.verify(message, signature, wallet_address, "login", &request_hash).await?;
+
+        let token = random_token::<32>();
+        let expires_at = chrono::Utc::now() + chrono::Duration::seconds(SESSION_TTL_SECS);
+
+        let mut sessions = self.sessions.write().await;
+        let now = chrono::Utc::now().timestamp();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(token.clone(), Session {
+            wallet: signed.wallet,
+            expires_at: expires_at.timestamp(),
+        });
+
+        Ok(SessionResponse {
+            token,
+            wallet_address: signed.wallet.to_string(),
+            expires_at: expires_at.to_rfc3339(),
+        })
+    }
+
+    pub async fn authenticate(&self, token: &str) -> Result<AuthenticatedWallet, AuthError> {
+        let sessions = self.sessions.read().await;
+        match sessions.get(token) {
+            Some(session) if session.expires_at > chrono::Utc::now().timestamp() => {
+                Ok(AuthenticatedWallet(session.wallet))
+            }
+            _ => Err(AuthError::InvalidSession),
+        }
+    }
+
+    pub async fn logout(&self, token: &str) {
+        self.sessions.write().await.remove(token);
+    }
+}
+
+// Random bs58 string built from N bytes of OS-seeded randomness
+fn random_token<const N: usize>() -> String {
+    let mut bytes = [0u8; N];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bs58::encode(bytes).into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::{Keypair, Signer};
+
+    const DOMAIN: &str = "marketplace.test";
+
+    fn message(wallet: &Pubkey, action: &str, request_hash: &str, nonce: &str) -> String {
+        let issued_at = chrono::Utc::now();
+        format!(
+            "{} wants you to sign in with your Solana account:\n{}\n\nAction: {}\nRequest Hash: {}\nNonce: {}\nIssued At: {}\nExpiration Time: {}",
+            DOMAIN,
+            wallet,
+            action,
+            request_hash,
+            nonce,
+            issued_at.to_rfc3339(),
+            (issued_at + chrono::Duration::seconds(NONCE_TTL_SECS)).to_rfc3339(),
+        )
+    }
+
+    #[test]
+    fn messages_are_parsed_into_their_fields() {
+        let wallet = Pubkey::new_unique();
+        let parsed = SignInMessage::parse(&message(&wallet, "login", "hash", "nonce")).unwrap();
+        assert_eq!(parsed.domain, DOMAIN);
+        assert_eq!(parsed.wallet, wallet);
+        assert_eq!((parsed.action.as_str(), parsed.request_hash.as_str(), parsed.nonce.as_str()), ("login", "hash", "nonce"));
+        assert_eq!(parsed.expiration_time - parsed.issued_at, chrono::Duration::seconds(NONCE_TTL_SECS));
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let message = message(&Pubkey::new_unique(), "login", "hash", "nonce");
+        let malformed = [
+            message.replacen(" wants you to sign in", " would like you to sign in", 1),
+            message.replacen("\n\n", "\n", 1),
+            message.replacen("Nonce: nonce\n", "", 1),
+            message.replacen("Request Hash: ", "Request Hash=", 1),
+            message.replacen("Issued At: ", "Issued At: yesterday ", 1),
+        ];
+        for message in malformed {
+            assert!(matches!(SignInMessage::parse(&message), Err(AuthError::MalformedMessage(_))), "{}", message);
+        }
+    }
+
+    #[test]
+    fn request_hashes_depend_on_every_field_and_their_order() {
+        assert_eq!(request_hash(&["a", "b"]), request_hash(&["a", "b"]));
+        assert_ne!(request_hash(&["a", "b"]), request_hash(&["b", "a"]));
+        assert_ne!(request_hash(&["a", "b"]), request_hash(&["a", "c"]));
+        assert_ne!(request_hash(&["a\nb"]), request_hash(&["ab"]));
+    }
+
+    #[tokio::test]
+    async fn a_signed_message_is_accepted_once_for_its_request() {
+        let auth = AuthState::new(DOMAIN.to_string());
+        let wallet = Keypair::new();
+        let address = wallet.pubkey().to_string();
+        let nonce = auth.issue_nonce(&address).await.unwrap().nonce;
+        let hash = request_hash(&["listing"]);
+        let message = message(&wallet.pubkey(), "list", &hash, &nonce);
+        let signature = wallet.sign_message(message.as_bytes()).to_string();
+
+        let wrong_hash = auth.verify(&message, &signature, &address, "list", &request_hash(&["other"])).await;
+        assert!(matches!(wrong_hash, Err(AuthError::MessageMismatch(_))));
+        let wrong_action = auth.verify(&message, &signature, &address, "buy", &hash).await;
+        assert!(matches!(wrong_action, Err(AuthError::MessageMismatch(_))));
+        let other_wallet = Keypair::new().pubkey().to_string();
+        assert!(matches!(auth.verify(&message, &signature, &other_wallet, "list", &hash).await, Err(AuthError::InvalidSignature)));
+
+        auth.verify(&message, &signature, &address, "list", &hash).await.unwrap();
+        assert!(matches!(auth.verify(&message, &signature, &address, "list", &hash).await, Err(AuthError::InvalidNonce)));
+    }
+
+    #[tokio::test]
+    async fn nonces_are_checked_without_storing_issued_ones() {
+        let auth = AuthState::new(DOMAIN.to_string());
+        let wallet = Pubkey::new_unique();
+        let now = chrono::Utc::now().timestamp();
+        for _ in 0..100 {
+            auth.issue_nonce(&wallet.to_string()).await.unwrap();
+        }
+        assert!(auth.used_nonces.read().await.is_empty());
+
+        let nonce = auth.issue_nonce(&wallet.to_string()).await.unwrap().nonce;
+        let expires_at = auth.check_nonce(&nonce, &wallet, now).unwrap();
+        assert!((now + NONCE_TTL_SECS..=now + NONCE_TTL_SECS + 1).contains(&expires_at));
+        assert!(auth.check_nonce(&nonce, &wallet, expires_at).is_err());
+        assert!(auth.check_nonce(&nonce, &Pubkey::new_unique(), now).is_err());
+        // A later expiry or a nonce from another server doesn't carry a valid MAC
+        let extended = nonce.replacen(&expires_at.to_string(), &(expires_at + 3600).to_string(), 1);
+        assert!(auth.check_nonce(&extended, &wallet, now).is_err());
+        let other = AuthState::new(DOMAIN.to_string()).issue_nonce(&wallet.to_string()).await.unwrap().nonce;
+        assert!(auth.check_nonce(&other, &wallet, now).is_err());
+        assert!(auth.check_nonce("not-a-nonce", &wallet, now).is_err());
+    }
+}
@@ -0,0 +1,505 @@
+// Read-through TTL cache for hot account reads (wallet balances, NFT metadata,
+// listings). Entries live in process memory, or in Redis when the `redis-cache`
+// feature is enabled and REDIS_URL is set. Transactions we send evict the accounts
+// they write, once when sent and again when they land.
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use solana_client::{
+    client_error::Result as ClientResult, rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::RpcProgramAccountsConfig, rpc_response::RpcConfirmedTransactionStatusWithSignature,
+};
+use solana_sdk::{account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey, transaction::Transaction};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::chain::ChainClient;
+
+const DEFAULT_TTL_SECS: u64 = 10;
+
+// getMultipleAccounts accepts at most this many keys
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+// Finalized chain data, like an address's first transaction, never changes, so
+// it is kept much longer
+const FINALIZED_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// getSignaturesForAddress returns at most this many signatures per call
+const MAX_SIGNATURES_PER_PAGE: usize = 1000;
+
+// Addresses with a longer history than this many pages of signatures are not
+// searched for their first transaction
+const MAX_FIRST_TRANSACTION_PAGES: usize = 5;
+
+// In-memory entries are swept for expired ones once the map grows past this
+const MEMORY_SWEEP_THRESHOLD: usize = 10_000;
+
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    async fn get(&self, key: &str) -> Option<Vec<u8>>;
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration);
+    async fn delete(&self, keys: &[String]);
+    async fn delete_prefix(&self, prefix: &str);
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, (Instant, Vec<u8>)>>,
+}
+
+#[async_trait]
+impl CacheStore for MemoryStore {
+    async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((expires_at, value)) if *expires_at > Instant::now() => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MEMORY_SWEEP_THRESHOLD {
+            let now = Instant::now();
+            entries.retain(|_, (expires_at, _)| *expires_at > now);
+        }
+        entries.insert(key.to_string(), (Instant::now() + ttl, value));
+    }
+
+    async fn delete(&self, keys: &[String]) {
+        let mut entries = self.entries.lock().unwrap();
+        for key in keys {
+            entries.remove(key);
+        }
+    }
+
+    async fn delete_prefix(&self, prefix: &str) {
+        self.entries.lock().unwrap().retain(|key, _| !key.starts_with(prefix));
+    }
+}
+
+#[cfg(feature = "redis-cache")]
+pub struct RedisStore {
+    connection: redis::aio::ConnectionManager,
+}
+
+#[cfg(feature = "redis-cache")]
+impl RedisStore {
+    pub async fn connect(url: &str) -> Result<Self, String> {
+        let client = redis::Client::open(url)
+            .map_err(|e| format!("Invalid REDIS_URL: {}", e))?;
+        let connection = client.get_connection_manager().await
+            .map_err(|e| format!("Failed to connect to Redis: {}", e))?;
+        Ok(RedisStore { connection })
+    }
+}
+
+// Redis errors are logged and treated as misses so the cache never fails a read
+#[cfg(feature = "redis-cache")]
+#[async_trait]
+impl CacheStore for RedisStore {
+    async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        use redis::AsyncCommands;
+        let mut connection = self.connection.clone();
+        connection.get::<_, Option<Vec<u8>>>(key).await.unwrap_or_else(|e| {
+            println!("Redis GET {} failed: {}", key, e);
+            None
+        })
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) {
+        use redis::AsyncCommands;
+        let mut connection = self.connection.clone();
+        if let Err(e) = connection.set_ex::<_, _, ()>(key, value, ttl.as_secs().max(1)).await {
+            println!("Redis SET {} failed: {}", key, e);
+        }
+    }
+
+    async fn delete(&self, keys: &[String]) {
+        use redis::AsyncCommands;
+        if keys.is_empty() {
+            return;
+        }
+        let mut connection = self.connection.clone();
+        if let Err(e) = connection.del::<_, ()>(keys).await {
+            println!("Redis DEL failed: {}", e);
+        }
+    }
+
+    async fn delete_prefix(&self, prefix: &str) {
+        use redis::AsyncCommands;
+        let mut connection = self.connection.clone();
+        let keys: Vec<String> = match connection.scan_match::<_, String>(format!("{}*", prefix)).await {
+            Ok(mut iter) => {
+                let mut keys = Vec::new();
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+                keys
+            }
+            Err(e) => {
+                println!("Redis SCAN {}* failed: {}", prefix, e);
+                return;
+            }
+        };
+        self.delete(&keys).await;
+    }
+}
+
+fn account_key(pubkey: &Pubkey) -> String {
+    format!("chain:account:{}", pubkey)
+}
+
+fn first_transaction_key(address: &Pubkey) -> String {
+    format!("chain:first_transaction:{}", address)
+}
+
+fn finalized_key(key: &str) -> String {
+    format!("chain:finalized:{}", key)
+}
+
+fn program_prefix(program_id: &Pubkey) -> String {
+    format!("chain:program:{}:", program_id)
+}
+
+/// Oldest successful transaction mentioning `address`, at `commitment` (the
+/// client's own when None). Pages back at most MAX_FIRST_TRANSACTION_PAGES
+/// times; None when there is none or the history goes back further.
+pub async fn first_signature(
+    client: &dyn ChainClient,
+    address: &Pubkey,
+    commitment: Option<CommitmentConfig>,
+) -> ClientResult<Option<RpcConfirmedTransactionStatusWithSignature>> {
+    let mut before = None;
+    let mut first = None;
+    for _ in 0..MAX_FIRST_TRANSACTION_PAGES {
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before,
+            limit: Some(MAX_SIGNATURES_PER_PAGE),
+            commitment,
+            ..GetConfirmedSignaturesForAddress2Config::default()
+        };
+        let mut signatures = client.get_signatures_for_address_with_config(address, config).await?;
+        if signatures.len() < MAX_SIGNATURES_PER_PAGE {
+            return Ok(signatures.into_iter().rev().find(|status| status.err.is_none()).or(first));
+        }
+        before = signatures.last().and_then(|status| status.signature.parse().ok());
+        if before.is_none() {
+            return Ok(first);
+        }
+        if let Some(index) = signatures.iter().rposition(|status| status.err.is_none()) {
+            first = Some(signatures.swap_remove(index));
+        }
+    }
+    Ok(None)
+}
+
+// Oldest successful transaction mentioning an address
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FirstTransaction {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+}
+
+#[derive(Clone)]
+pub struct ChainCache {
+    client: Arc<dyn ChainClient>,
+    store: Option<Arc<dyn CacheStore>>, // None when caching is disabled
+    ttl: Duration,
+}
+
+impl ChainCache {
+    pub fn new(client: Arc<dyn ChainClient>, store: Option<Arc<dyn CacheStore>>, ttl: Duration) -> Self {
+        ChainCache { client, store, ttl }
+    }
+
+    /// Cache configured by CHAIN_CACHE_TTL_SECS (0 disables it) and, with the
+    /// `redis-cache` feature, REDIS_URL
+    pub async fn from_env(client: Arc<dyn ChainClient>) -> Self {
+        let ttl_secs = std::env::var("CHAIN_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|ttl| ttl.parse().ok())
+            .unwrap_or(DEFAULT_TTL_SECS);
+        if ttl_secs == 0 {
+            return ChainCache::new(client, None, Duration::ZERO);
+        }
+
+        let store: Arc<dyn CacheStore> = match std::env::var("REDIS_URL").ok().filter(|url| !url.is_empty()) {
+            #[cfg(feature = "redis-cache")]
+            Some(url) => match RedisStore::connect(&url).await {
+                Ok(store) => Arc::new(store),
+                Err(e) => {
+                    println!("{}; caching in memory instead", e);
+                    Arc::new(MemoryStore::default())
+                }
+            },
+            #[cfg(not(feature = "redis-cache"))]
+            Some(_) => {
+                println!("REDIS_URL is set but the redis-cache feature is disabled; caching in memory");
+                Arc::new(MemoryStore::default())
+            }
+            None => Arc::new(MemoryStore::default()),
+        };
+        ChainCache::new(client, Some(store), Duration::from_secs(ttl_secs))
+    }
+
+    pub async fn balance(&self, pubkey: &Pubkey) -> ClientResult<u64> {
+        Ok(self.account(pubkey).await?.map_or(0, |account| account.lamports))
+    }
+
+    pub async fn account(&self, pubkey: &Pubkey) -> ClientResult<Option<Account>> {
+        Ok(self.accounts(std::slice::from_ref(pubkey)).await?.pop().flatten())
+    }
+
+    /// Like `get_multiple_accounts`, fetching only the accounts not cached.
+    /// Missing accounts are cached too.
+    pub async fn accounts(&self, pubkeys: &[Pubkey]) -> ClientResult<Vec<Option<Account>>> {
+        let Some(store) = &self.store else {
+            return self.client.get_multiple_accounts(pubkeys).await;
+        };
+
+        let mut accounts: Vec<Option<Option<Account>>> = Vec::with_capacity(pubkeys.len());
+        for pubkey in pubkeys {
+            let cached = store.get(&account_key(pubkey)).await
+                .and_then(|bytes| bincode::deserialize::<Option<Account>>(&bytes).ok());
+            accounts.push(cached);
+        }
+
+        let missing: Vec<Pubkey> = pubkeys.iter().zip(&accounts)
+            .filter(|(_, cached)| cached.is_none())
+            .map(|(pubkey, _)| *pubkey)
+            .collect();
+        let mut fetched = HashMap::new();
+        for chunk in missing.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let chunk_accounts = self.client.get_multiple_accounts(chunk).await?;
+            for (pubkey, account) in chunk.iter().zip(chunk_accounts) {
+                if let Ok(bytes) = bincode::serialize(&account) {
+                    store.set(&account_key(pubkey), bytes, self.ttl).await;
+                }
+                fetched.insert(*pubkey, account);
+            }
+        }
+
+        Ok(pubkeys.iter().zip(accounts)
+            .map(|(pubkey, cached)| cached.unwrap_or_else(|| fetched.get(pubkey).cloned().flatten()))
+            .collect())
+    }
+
+    /// `get_program_accounts_with_config`, cached per program and query
+    pub async fn program_accounts(
+        &self,
+        program_id: &Pubkey,
+        config: RpcProgramAccountsConfig,
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        let Some(store) = &self.store else {
+            return self.client.get_program_accounts_with_config(program_id, config).await;
+        };
+
+        let query = serde_json::to_string(&config).unwrap_or_default();
+        let key = format!("{}{:x}", program_prefix(program_id), md5::compute(query));
+        if let Some(accounts) = store.get(&key).await.and_then(|bytes| bincode::deserialize(&bytes).ok()) {
+            return Ok(accounts);
+        }
+
+        let accounts = self.client.get_program_accounts_with_config(program_id, config).await?;
+        if let Ok(bytes) = bincode::serialize(&accounts) {
+            store.set(&key, bytes, self.ttl).await;
+        }
+        Ok(accounts)
+    }
+
+    /// Block time of the oldest successful transaction that mentions `address`
+    pub async fn first_block_time(&self, address: &Pubkey) -> ClientResult<Option<i64>> {
+        Ok(self.first_transaction(address).await?.and_then(|first| first.block_time))
+    }
+
+    /// Oldest successful transaction that mentions `address`, paging back
+    /// through its signatures; None past MAX_FIRST_TRANSACTION_PAGES. Only found
+    /// transactions are cached.
+    pub async fn first_transaction(&self, address: &Pubkey) -> ClientResult<Option<FirstTransaction>> {
+        let key = first_transaction_key(address);
+        if let Some(store) = &self.store {
+            if let Some(first) = store.get(&key).await.and_then(|bytes| bincode::deserialize(&bytes).ok()) {
+                return Ok(Some(first));
+            }
+        }
+
+        let first = first_signature(self.client.as_ref(), address, None).await?
+            .map(|oldest| FirstTransaction {
+                signature: oldest.signature,
+                slot: oldest.slot,
+                block_time: oldest.block_time,
+            });
+        if let (Some(store), Some(first)) = (&self.store, &first) {
+            if let Ok(bytes) = bincode::serialize(first) {
+                store.set(&key, bytes, FINALIZED_TTL).await;
+            }
+        }
+        Ok(first)
+    }
+
+    /// A value computed from finalized chain data only, so it never changes:
+    /// `compute` runs on a miss and only successful results are cached
+    pub async fn finalized<T, E>(&self, key: &str, compute: impl Future<Output = Result<T, E>>) -> Result<T, E>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let Some(store) = &self.store else {
+            return compute.await;
+        };
+        let key = finalized_key(key);
+        if let Some(value) = store.get(&key).await.and_then(|bytes| bincode::deserialize(&bytes).ok()) {
+            return Ok(value);
+        }
+        let value = compute.await?;
+        if let Ok(bytes) = bincode::serialize(&value) {
+            store.set(&key, bytes, FINALIZED_TTL).await;
+        }
+        Ok(value)
+    }
+
+    /// Evict every account `transaction` may write and every program query of
+    /// the programs it calls
+    pub async fn invalidate(&self, transaction: &Transaction) {
+        let Some(store) = &self.store else {
+            return;
+        };
+
+        let message = &transaction.message;
+        let writable: Vec<String> = message.account_keys.iter().enumerate()
+            .filter(|(index, _)| message.is_writable(*index))
+            .map(|(_, pubkey)| account_key(pubkey))
+            .collect();
+        store.delete(&writable).await;
+
+        for program_id in message.instructions.iter().map(|ix| ix.program_id(&message.account_keys)) {
+            store.delete_prefix(&program_prefix(program_id)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_chain::MemoryChain;
+    use solana_client::rpc_config::RpcSendTransactionConfig;
+    use solana_sdk::{
+        native_token::LAMPORTS_PER_SOL,
+        signature::{Keypair, Signer},
+        system_instruction,
+    };
+
+    async fn transfer(chain: &MemoryChain, from: &Keypair, to: &Pubkey, lamports: u64) -> String {
+        let blockhash = chain.get_latest_blockhash().await.unwrap();
+        let instruction = system_instruction::transfer(&from.pubkey(), to, lamports);
+        let transaction = Transaction::new_signed_with_payer(&[instruction], Some(&from.pubkey()), &[from], blockhash);
+        chain.send_transaction_with_config(&transaction, RpcSendTransactionConfig::default()).await.unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn first_transaction_is_not_searched_for_past_the_page_cap() {
+        let chain = Arc::new(MemoryChain::new());
+        let cache = ChainCache::new(chain.clone(), None, Duration::ZERO);
+        let payer = Keypair::new();
+        chain.airdrop(&payer.pubkey(), LAMPORTS_PER_SOL);
+
+        let (short, long) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let first = transfer(&chain, &payer, &short, 1_000_000).await;
+        transfer(&chain, &payer, &short, 1_000_001).await;
+        transfer(&chain, &payer, &long, 1_000_000).await;
+        // Distinct amounts, so no two transfers share a signature
+        for lamports in 1..(MAX_FIRST_TRANSACTION_PAGES * MAX_SIGNATURES_PER_PAGE) as u64 {
+            transfer(&chain, &payer, &long, lamports).await;
+        }
+
+        assert_eq!(cache.first_transaction(&short).await.unwrap().unwrap().signature, first);
+        assert!(cache.first_transaction(&long).await.unwrap().is_none());
+    }
+
+    fn cached_keys(store: &MemoryStore) -> Vec<String> {
+        let mut keys: Vec<String> = store.entries.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn memory_entries_expire_after_their_ttl() {
+        let store = MemoryStore::default();
+        store.set("short", vec![1], Duration::from_millis(20)).await;
+        store.set("long", vec![2], Duration::from_secs(60)).await;
+        assert_eq!(store.get("short").await, Some(vec![1]));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(store.get("short").await, None);
+        assert_eq!(store.get("long").await, Some(vec![2]));
+        // Reading an expired entry drops it
+        assert_eq!(cached_keys(&store), vec!["long"]);
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_swept_once_the_map_is_full() {
+        let store = MemoryStore::default();
+        store.set("live", vec![], Duration::from_secs(60)).await;
+        for index in 1..MEMORY_SWEEP_THRESHOLD {
+            store.set(&format!("expired:{}", index), vec![], Duration::ZERO).await;
+        }
+        assert_eq!(store.entries.lock().unwrap().len(), MEMORY_SWEEP_THRESHOLD);
+
+        store.set("new", vec![], Duration::from_secs(60)).await;
+        assert_eq!(cached_keys(&store), vec!["live", "new"]);
+    }
+
+    #[tokio::test]
+    async fn accounts_are_cached_until_a_transaction_writes_them() {
+        let chain = Arc::new(MemoryChain::new());
+        let store = Arc::new(MemoryStore::default());
+        let cache = ChainCache::new(chain.clone(), Some(store.clone()), Duration::from_secs(60));
+        let (payer, untouched, missing) = (Keypair::new(), Pubkey::new_unique(), Pubkey::new_unique());
+        chain.airdrop(&payer.pubkey(), LAMPORTS_PER_SOL);
+        chain.airdrop(&untouched, LAMPORTS_PER_SOL);
+
+        assert_eq!(cache.balance(&payer.pubkey()).await.unwrap(), LAMPORTS_PER_SOL);
+        assert_eq!(cache.balance(&untouched).await.unwrap(), LAMPORTS_PER_SOL);
+        assert!(cache.account(&missing).await.unwrap().is_none());
+        chain.airdrop(&payer.pubkey(), LAMPORTS_PER_SOL);
+        chain.airdrop(&untouched, LAMPORTS_PER_SOL);
+        chain.airdrop(&missing, LAMPORTS_PER_SOL);
+        // Stale until evicted, missing accounts included
+        assert_eq!(cache.balance(&payer.pubkey()).await.unwrap(), LAMPORTS_PER_SOL);
+        assert!(cache.account(&missing).await.unwrap().is_none());
+
+        let instruction = system_instruction::transfer(&payer.pubkey(), &missing, 1);
+        cache.invalidate(&Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()))).await;
+        assert_eq!(cache.balance(&payer.pubkey()).await.unwrap(), 2 * LAMPORTS_PER_SOL);
+        assert_eq!(cache.balance(&missing).await.unwrap(), LAMPORTS_PER_SOL);
+        assert_eq!(cache.balance(&untouched).await.unwrap(), LAMPORTS_PER_SOL);
+    }
+
+    #[tokio::test]
+    async fn program_queries_are_evicted_by_transactions_calling_the_program() {
+        let chain = Arc::new(MemoryChain::new());
+        let store = Arc::new(MemoryStore::default());
+        let cache = ChainCache::new(chain.clone(), Some(store.clone()), Duration::from_secs(60));
+        let program_id = crate::marketplace_program::program_id();
+        cache.program_accounts(&program_id, RpcProgramAccountsConfig::default()).await.unwrap();
+        let cached = |keys: Vec<String>| keys.iter().any(|key| key.starts_with(&program_prefix(&program_id)));
+        assert!(cached(cached_keys(&store)));
+
+        let seller = Pubkey::new_unique();
+        let transfer = system_instruction::transfer(&seller, &Pubkey::new_unique(), 1);
+        cache.invalidate(&Transaction::new_with_payer(&[transfer], Some(&seller))).await;
+        assert!(cached(cached_keys(&store)));
+
+        let list = crate::marketplace_program::list_nft(&seller, &Pubkey::new_unique(), 1000);
+        cache.invalidate(&Transaction::new_with_payer(&[list], Some(&seller))).await;
+        assert!(!cached(cached_keys(&store)));
+    }
+}
@@ -0,0 +1,262 @@
+// Chain access behind a trait, so handlers run against a Solana RPC node or, for
+// offline tests and local development, against the in-memory ledger in
+// `memory_chain`. Method names and types mirror the RpcClient calls they wrap.
+use async_trait::async_trait;
+use serde_json::json;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    client_error::Result as ClientResult,
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::{
+        RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcSendTransactionConfig,
+        RpcSimulateTransactionConfig, RpcTokenAccountsFilter, RpcTransactionConfig,
+    },
+    rpc_request::{RpcRequest, TokenAccountsFilter},
+    rpc_response::{
+        Response, RpcConfirmedTransactionStatusWithSignature, RpcKeyedAccount, RpcPrioritizationFee,
+        RpcResult, RpcSimulateTransactionResult, RpcTokenAccountBalance,
+    },
+};
+use solana_sdk::{
+    account::Account,
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    message::Message,
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::Transaction,
+};
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, TransactionStatus};
+use std::{str::FromStr, sync::Arc};
+
+use crate::{memory_chain::MemoryChain, rpc_pool::{RpcEndpointHealth, RpcPool}};
+
+#[async_trait]
+pub trait ChainClient: Send + Sync {
+    // Per-endpoint health, for clients backed by RPC endpoints
+    fn endpoint_health(&self) -> Vec<RpcEndpointHealth> {
+        Vec::new()
+    }
+
+    // Balances and accounts
+    async fn get_balance(&self, pubkey: &Pubkey) -> ClientResult<u64>;
+    async fn get_account(&self, pubkey: &Pubkey) -> ClientResult<Account>;
+    async fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> ClientResult<Vec<Option<Account>>>;
+    async fn get_program_accounts_with_config(
+        &self,
+        program_id: &Pubkey,
+        config: RpcProgramAccountsConfig,
+    ) -> ClientResult<Vec<(Pubkey, Account)>>;
+    // Raw token accounts, unlike RpcClient's jsonParsed `get_token_accounts_by_owner`
+    async fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        filter: TokenAccountsFilter,
+    ) -> ClientResult<Vec<(Pubkey, Account)>>;
+    async fn get_token_largest_accounts(&self, mint: &Pubkey) -> ClientResult<Vec<RpcTokenAccountBalance>>;
+
+    // Blockhashes and fees
+    async fn get_latest_blockhash(&self) -> ClientResult<Hash>;
+    async fn get_latest_blockhash_with_commitment(
+        &self,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<(Hash, u64)>;
+    async fn get_block_height_with_commitment(&self, commitment: CommitmentConfig) -> ClientResult<u64>;
+    async fn get_slot_with_commitment(&self, commitment: CommitmentConfig) -> ClientResult<u64>;
+    async fn get_fee_for_message(&self, message: &Message) -> ClientResult<u64>;
+    async fn get_recent_prioritization_fees(
+        &self,
+        addresses: &[Pubkey],
+    ) -> ClientResult<Vec<RpcPrioritizationFee>>;
+
+    // Transactions
+    async fn simulate_transaction_with_config(
+        &self,
+        transaction: &Transaction,
+        config: RpcSimulateTransactionConfig,
+    ) -> RpcResult<RpcSimulateTransactionResult>;
+    async fn send_transaction_with_config(
+        &self,
+        transaction: &Transaction,
+        config: RpcSendTransactionConfig,
+    ) -> ClientResult<Signature>;
+
+    // Signatures
+    async fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+    ) -> RpcResult<Vec<Option<TransactionStatus>>>;
+    async fn get_signature_statuses_with_history(
+        &self,
+        signatures: &[Signature],
+    ) -> RpcResult<Vec<Option<TransactionStatus>>>;
+    async fn get_signatures_for_address_with_config(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> ClientResult<Vec<RpcConfirmedTransactionStatusWithSignature>>;
+    async fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> ClientResult<EncodedConfirmedTransactionWithStatusMeta>;
+}
+
+#[async_trait]
+impl ChainClient for RpcClient {
+    async fn get_balance(&self, pubkey: &Pubkey) -> ClientResult<u64> {
+        RpcClient::get_balance(self, pubkey).await
+    }
+
+    async fn get_account(&self, pubkey: &Pubkey) -> ClientResult<Account> {
+        RpcClient::get_account(self, pubkey).await
+    }
+
+    async fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> ClientResult<Vec<Option<Account>>> {
+        RpcClient::get_multiple_accounts(self, pubkeys).await
+    }
+
+    async fn get_program_accounts_with_config(
+        &self,
+        program_id: &Pubkey,
+        config: RpcProgramAccountsConfig,
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        RpcClient::get_program_accounts_with_config(self, program_id, config).await
+    }
+
+    async fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        filter: TokenAccountsFilter,
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        let filter = match filter {
+            TokenAccountsFilter::Mint(mint) => RpcTokenAccountsFilter::Mint(mint.to_string()),
+            TokenAccountsFilter::ProgramId(program_id) => RpcTokenAccountsFilter::ProgramId(program_id.to_string()),
+        };
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(self.commitment()),
+            ..RpcAccountInfoConfig::default()
+        };
+        let response: Response<Vec<RpcKeyedAccount>> = self
+            .send(RpcRequest::GetTokenAccountsByOwner, json!([owner.to_string(), filter, config]))
+            .await?;
+        Ok(response.value.into_iter()
+            .filter_map(|keyed| Some((Pubkey::from_str(&keyed.pubkey).ok()?, keyed.account.decode()?)))
+            .collect())
+    }
+
+    async fn get_token_largest_accounts(&self, mint: &Pubkey) -> ClientResult<Vec<RpcTokenAccountBalance>> {
+        RpcClient::get_token_largest_accounts(self, mint).await
+    }
+
+    async fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        RpcClient::get_latest_blockhash(self).await
+    }
+
+    async fn get_latest_blockhash_with_commitment(
+        &self,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<(Hash, u64)> {
+        RpcClient::get_latest_blockhash_with_commitment(self, commitment).await
+    }
+
+    async fn get_block_height_with_commitment(&self, commitment: CommitmentConfig) -> ClientResult<u64> {
+        RpcClient::get_block_height_with_commitment(self, commitment).await
+    }
+
+    async fn get_slot_with_commitment(&self, commitment: CommitmentConfig) -> ClientResult<u64> {
+        RpcClient::get_slot_with_commitment(self, commitment).await
+    }
+
+    async fn get_fee_for_message(&self, message: &Message) -> ClientResult<u64> {
+        RpcClient::get_fee_for_message(self, message).await
+    }
+
+    async fn get_recent_prioritization_fees(
+        &self,
+        addresses: &[Pubkey],
+    ) -> ClientResult<Vec<RpcPrioritizationFee>> {
+        RpcClient::get_recent_prioritization_fees(self, addresses).await
+    }
+
+    async fn simulate_transaction_with_config(
+        &self,
+        transaction: &Transaction,
+        config: RpcSimulateTransactionConfig,
+    ) -> RpcResult<RpcSimulateTransactionResult> {
+        RpcClient::simulate_transaction_with_config(self, transaction, config).await
+    }
+
+    async fn send_transaction_with_config(
+        &self,
+        transaction: &Transaction,
+        config: RpcSendTransactionConfig,
+    ) -> ClientResult<Signature> {
+        RpcClient::send_transaction_with_config(self, transaction, config).await
+    }
+
+    async fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+    ) -> RpcResult<Vec<Option<TransactionStatus>>> {
+        RpcClient::get_signature_statuses(self, signatures).await
+    }
+
+    async fn get_signature_statuses_with_history(
+        &self,
+        signatures: &[Signature],
+    ) -> RpcResult<Vec<Option<TransactionStatus>>> {
+        RpcClient::get_signature_statuses_with_history(self, signatures).await
+    }
+
+    async fn get_signatures_for_address_with_config(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> ClientResult<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+        RpcClient::get_signatures_for_address_with_config(self, address, config).await
+    }
+
+    async fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> ClientResult<EncodedConfirmedTransactionWithStatusMeta> {
+        RpcClient::get_transaction_with_config(self, signature, config).await
+    }
+}
+
+// Lamports given to each wallet the in-memory ledger starts with
+const MEMORY_CHAIN_AIRDROP_LAMPORTS: u64 = 1_000 * LAMPORTS_PER_SOL;
+
+/// A block time or other Unix timestamp as RFC 3339, as the API returns times
+pub fn rfc3339(unix_time: Option<i64>) -> Option<String> {
+    unix_time
+        .and_then(|time| chrono::DateTime::from_timestamp(time, 0))
+        .map(|time| time.to_rfc3339())
+}
+
+/// Chain selected by CHAIN_BACKEND: `rpc` (default) pools `rpc_urls`, `memory`
+/// starts an empty in-memory ledger funding the backend wallet and any wallets
+/// listed in MEMORY_CHAIN_AIRDROP
+pub fn connect(rpc_urls: Vec<String>, backend_wallet: &Pubkey) -> Arc<dyn ChainClient> {
+    let backend = std::env::var("CHAIN_BACKEND").unwrap_or_else(|_| "rpc".to_string());
+    if backend != "memory" {
+        return Arc::new(RpcPool::new(rpc_urls));
+    }
+
+    let chain = MemoryChain::new();
+    chain.airdrop(backend_wallet, MEMORY_CHAIN_AIRDROP_LAMPORTS);
+    let wallets = std::env::var("MEMORY_CHAIN_AIRDROP").unwrap_or_default();
+    for wallet in wallets.split(',').map(str::trim).filter(|w| !w.is_empty()) {
+        match Pubkey::from_str(wallet) {
+            Ok(pubkey) => chain.airdrop(&pubkey, MEMORY_CHAIN_AIRDROP_LAMPORTS),
+            Err(_) => println!("Ignoring invalid MEMORY_CHAIN_AIRDROP wallet: {}", wallet),
+        }
+    }
+    println!("Using in-memory chain instead of RPC");
+    Arc::new(chain)
+}
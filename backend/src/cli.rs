@@ -0,0 +1,65 @@
+// Index maintenance commands, run by the backend binary in place of the server.
+// They write to DATABASE_URL with the same idempotent writes as the live
+// indexer and move none of its checkpoints, so they are safe to run while a
+// server is indexing into the same database.
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::{str::FromStr, sync::Arc};
+
+use crate::chain::ChainClient;
+use crate::index_db::IndexDb;
+use crate::indexer;
+
+pub const USAGE: &str = "Usage:
+  nft-marketplace-backend                                    run the API server
+  nft-marketplace-backend backfill --from-slot <SLOT>        re-index marketplace transactions from a slot
+  nft-marketplace-backend backfill --from-signature <SIG>    re-index marketplace transactions from a transaction
+  nft-marketplace-backend reindex-mint <MINT>                rebuild one NFT's listings and sales";
+
+pub enum Command {
+    BackfillFromSlot(u64),
+    BackfillFromSignature(Signature),
+    ReindexMint(Pubkey),
+}
+
+/// Command named by the arguments after the program name, `None` to run the server
+pub fn parse(args: &[String]) -> Result<Option<Command>, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] => Ok(None),
+        ["backfill", "--from-slot", slot] => slot.parse()
+            .map(|slot| Some(Command::BackfillFromSlot(slot)))
+            .map_err(|_| format!("Invalid slot: {}", slot)),
+        ["backfill", "--from-signature", signature] => Signature::from_str(signature)
+            .map(|signature| Some(Command::BackfillFromSignature(signature)))
+            .map_err(|_| format!("Invalid signature: {}", signature)),
+        ["reindex-mint", mint] => Pubkey::from_str(mint)
+            .map(|mint| Some(Command::ReindexMint(mint)))
+            .map_err(|_| format!("Invalid mint address: {}", mint)),
+        [command, ..] => Err(format!("Unknown command or arguments: {}", command)),
+    }
+}
+
+pub async fn run(command: Command, client: Arc<dyn ChainClient>) -> Result<(), String> {
+    let database_url = std::env::var("DATABASE_URL").ok()
+        .filter(|url| !url.is_empty())
+        .ok_or("DATABASE_URL must be set to index the marketplace")?;
+    let db = IndexDb::connect(&database_url).await?;
+
+    match command {
+        Command::BackfillFromSlot(slot) => {
+            let count = indexer::backfill(client.as_ref(), &db, slot).await?;
+            println!("Backfilled {} marketplace transactions", count);
+        }
+        Command::BackfillFromSignature(signature) => {
+            // Everything from the transaction's slot, the transaction included
+            let slot = indexer::transaction_slot(client.as_ref(), &signature).await?;
+            let count = indexer::backfill(client.as_ref(), &db, slot).await?;
+            println!("Backfilled {} marketplace transactions", count);
+        }
+        Command::ReindexMint(mint) => {
+            let count = indexer::reindex_mint(client.as_ref(), &db, &mint).await?;
+            println!("Re-indexed {} from {} transactions", mint, count);
+        }
+    }
+    Ok(())
+}
@@ -2,6 +2,10 @@ use serde::Deserialize;
 use utoipa::ToSchema;
 use std::sync::Arc;
 
+use crate::chain::ChainClient;
+
+// Accepted and documented, but unused until collection creation is implemented
+#[allow(dead_code)]
 #[derive(Deserialize, ToSchema)]
 pub struct CreateCollectionRequest {
     pub name: String,
@@ -11,7 +15,7 @@ pub struct CreateCollectionRequest {
 }
 
 pub async fn create_collection(
-    _client: Arc<solana_client::rpc_client::RpcClient>,
+    _client: Arc<dyn ChainClient>,
     _req: CreateCollectionRequest,
 ) -> Result<serde_json::Value, String> {
     // TODO: Implement collection creation
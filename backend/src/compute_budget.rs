@@ -0,0 +1,241 @@
+// Compute unit limits and priority fees for transactions built by the backend
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    transaction::Transaction,
+};
+
+use crate::chain::ChainClient;
+use crate::simulation::{self, SimulationError};
+
+// Highest compute unit limit a transaction may request
+const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+// getRecentPrioritizationFees accepts at most this many accounts
+const MAX_FEE_ACCOUNTS: usize = 128;
+
+const MICRO_LAMPORTS_PER_LAMPORT: u64 = 1_000_000;
+
+// Priority fee policy for one kind of transaction. Every field can be overridden
+// from the environment, e.g. PRIORITY_FEE_PERCENTILE_BUY_NFT=90.
+#[derive(Clone, Copy)]
+pub struct ComputeBudgetConfig {
+    pub percentile: u8, // Percentile of recent prioritization fees to pay
+    pub max_unit_price: u64, // Cap in micro-lamports per compute unit
+    pub unit_limit_margin_percent: u32, // Headroom over simulated compute units
+    pub default_unit_limit: u32, // Used for estimates before a transaction exists
+}
+
+impl ComputeBudgetConfig {
+    pub fn for_action(action: &str) -> Self {
+        let defaults = match action {
+            "mint_nft" => ComputeBudgetConfig {
+                percentile: 50,
+                max_unit_price: 1_000_000,
+                unit_limit_margin_percent: 20,
+                default_unit_limit: 250_000,
+            },
+            // Purchases race other buyers for the same listing
+            "buy_nft" => ComputeBudgetConfig {
+                percentile: 75,
+                max_unit_price: 2_000_000,
+                unit_limit_margin_percent: 20,
+                default_unit_limit: 80_000,
+            },
+            _ => ComputeBudgetConfig {
+                percentile: 50,
+                max_unit_price: 1_000_000,
+                unit_limit_margin_percent: 20,
+                default_unit_limit: 80_000,
+            },
+        };
+
+        ComputeBudgetConfig {
+            percentile: env_override("PRIORITY_FEE_PERCENTILE", action)
+                .unwrap_or(defaults.percentile)
+                .min(100),
+            max_unit_price: env_override("MAX_PRIORITY_FEE_MICRO_LAMPORTS", action)
+                .unwrap_or(defaults.max_unit_price),
+            unit_limit_margin_percent: env_override("COMPUTE_UNIT_MARGIN_PERCENT", action)
+                .unwrap_or(defaults.unit_limit_margin_percent),
+            default_unit_limit: env_override("COMPUTE_UNIT_LIMIT", action)
+                .unwrap_or(defaults.default_unit_limit),
+        }
+    }
+}
+
+// `{NAME}_{ACTION}` takes precedence over `{NAME}`
+fn env_override<T: std::str::FromStr>(name: &str, action: &str) -> Option<T> {
+    std::env::var(format!("{}_{}", name, action.to_uppercase()))
+        .or_else(|_| std::env::var(name))
+        .ok()
+        .and_then(|value| value.parse().ok())
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ComputeBudget {
+    pub unit_limit: u32,
+    pub unit_price: u64, // Micro-lamports per compute unit
+    pub units_consumed: Option<u64>, // From simulation, when the budget was sized from one
+}
+
+impl ComputeBudget {
+    // Lamports paid on top of the signature fee
+    pub fn priority_fee(&self) -> u64 {
+        (self.unit_limit as u64 * self.unit_price).div_ceil(MICRO_LAMPORTS_PER_LAMPORT)
+    }
+
+    // Compute budget instructions go first, followed by `instructions`
+    pub fn apply(&self, instructions: &[Instruction]) -> Vec<Instruction> {
+        let mut with_budget = vec![
+            ComputeBudgetInstruction::set_compute_unit_limit(self.unit_limit),
+            ComputeBudgetInstruction::set_compute_unit_price(self.unit_price),
+        ];
+        with_budget.extend_from_slice(instructions);
+        with_budget
+    }
+}
+
+/// Priority fee in micro-lamports per compute unit: the configured percentile of
+/// recent fees paid by transactions writing to the same accounts
+pub async fn unit_price(
+    client: &dyn ChainClient,
+    config: &ComputeBudgetConfig,
+    writable_accounts: &[Pubkey],
+) -> Result<u64, String> {
+    let accounts = &writable_accounts[..writable_accounts.len().min(MAX_FEE_ACCOUNTS)];
+    let fees = client.get_recent_prioritization_fees(accounts).await
+        .map_err(|e| format!("Failed to get recent prioritization fees: {}", e))?
+        .into_iter()
+        .map(|fee| fee.prioritization_fee)
+        .collect();
+    Ok(percentile_fee(fees, config))
+}
+
+// The configured percentile of `fees`, capped; nothing without recent fees
+fn percentile_fee(mut fees: Vec<u64>, config: &ComputeBudgetConfig) -> u64 {
+    if fees.is_empty() {
+        return 0;
+    }
+    fees.sort_unstable();
+    let index = (fees.len() - 1) * config.percentile as usize / 100;
+    fees[index].min(config.max_unit_price)
+}
+
+// Simulated units plus the configured margin, within what a transaction may request
+fn unit_limit(units_consumed: u64, config: &ComputeBudgetConfig) -> u32 {
+    let with_margin = units_consumed * (100 + config.unit_limit_margin_percent as u64) / 100;
+    with_margin.clamp(1, MAX_COMPUTE_UNIT_LIMIT as u64) as u32
+}
+
+/// Budget for an action before its transaction is built, from the configured
+/// default compute unit limit
+pub async fn estimate(client: &dyn ChainClient, action: &str) -> Result<ComputeBudget, String> {
+    let config = ComputeBudgetConfig::for_action(action);
+    Ok(ComputeBudget {
+        unit_limit: config.default_unit_limit,
+        unit_price: unit_price(client, &config, &[]).await?,
+        units_consumed: None,
+    })
+}
+
+/// Size the compute unit limit of `instructions` from a simulation and price it
+/// from recent fees on the accounts they write to
+pub async fn plan(
+    client: &dyn ChainClient,
+    action: &str,
+    instructions: &[Instruction],
+    payer: &Pubkey,
+) -> Result<ComputeBudget, SimulationError> {
+    let config = ComputeBudgetConfig::for_action(action);
+
+    let mut writable_accounts: Vec<Pubkey> = Vec::new();
+    for meta in instructions.iter().flat_map(|ix| &ix.accounts) {
+        if meta.is_writable && !writable_accounts.contains(&meta.pubkey) {
+            writable_accounts.push(meta.pubkey);
+        }
+    }
+    let unit_price = unit_price(client, &config, &writable_accounts).await
+        .map_err(SimulationError::Rpc)?;
+
+    // Simulate at the maximum limit so the measurement isn't capped by the budget itself
+    let probe = ComputeBudget {
+        unit_limit: MAX_COMPUTE_UNIT_LIMIT,
+        unit_price,
+        units_consumed: None,
+    };
+    let transaction = Transaction::new_with_payer(&probe.apply(instructions), Some(payer));
+    let units_consumed = simulation::simulate(client, &transaction, false).await?;
+
+    let unit_limit = match units_consumed {
+        Some(units) => unit_limit(units, &config),
+        None => config.default_unit_limit,
+    };
+
+    Ok(ComputeBudget {
+        unit_limit,
+        unit_price,
+        units_consumed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_chain::MemoryChain;
+    use solana_sdk::{native_token::LAMPORTS_PER_SOL, signature::{Keypair, Signer}, system_instruction};
+
+    const CONFIG: ComputeBudgetConfig = ComputeBudgetConfig {
+        percentile: 75,
+        max_unit_price: 500,
+        unit_limit_margin_percent: 20,
+        default_unit_limit: 80_000,
+    };
+
+    #[test]
+    fn unit_prices_are_the_configured_percentile_of_recent_fees() {
+        assert_eq!(percentile_fee(vec![], &CONFIG), 0);
+        assert_eq!(percentile_fee(vec![40], &CONFIG), 40);
+        // Index (5 - 1) * 75 / 100 = 3 of the sorted fees
+        assert_eq!(percentile_fee(vec![50, 10, 40, 30, 20], &CONFIG), 40);
+        assert_eq!(percentile_fee(vec![50, 10, 40, 30, 20], &ComputeBudgetConfig { percentile: 100, ..CONFIG }), 50);
+        assert_eq!(percentile_fee(vec![50, 10, 40, 30, 20], &ComputeBudgetConfig { percentile: 0, ..CONFIG }), 10);
+        assert_eq!(percentile_fee(vec![1000, 2000], &CONFIG), CONFIG.max_unit_price);
+    }
+
+    #[test]
+    fn unit_limits_add_the_margin_within_bounds() {
+        assert_eq!(unit_limit(10_000, &CONFIG), 12_000);
+        assert_eq!(unit_limit(0, &CONFIG), 1);
+        assert_eq!(unit_limit(MAX_COMPUTE_UNIT_LIMIT as u64, &CONFIG), MAX_COMPUTE_UNIT_LIMIT);
+        assert_eq!(unit_limit(u32::MAX as u64 * 2, &CONFIG), MAX_COMPUTE_UNIT_LIMIT);
+    }
+
+    #[test]
+    fn priority_fees_are_rounded_up_to_whole_lamports() {
+        let budget = |unit_limit, unit_price| ComputeBudget { unit_limit, unit_price, units_consumed: None };
+        assert_eq!(budget(200_000, 0).priority_fee(), 0);
+        assert_eq!(budget(200_000, 5).priority_fee(), 1);
+        assert_eq!(budget(200_000, 10).priority_fee(), 2);
+        assert_eq!(budget(1_400_000, 1_000_000).priority_fee(), 1_400_000);
+    }
+
+    #[tokio::test]
+    async fn plans_are_sized_from_a_simulation() {
+        let chain = MemoryChain::new();
+        let payer = Keypair::new();
+        chain.airdrop(&payer.pubkey(), LAMPORTS_PER_SOL);
+        let transfer = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1000);
+
+        let budget = plan(&chain, "transfer", std::slice::from_ref(&transfer), &payer.pubkey()).await.unwrap();
+        let units = budget.units_consumed.unwrap();
+        let margin = ComputeBudgetConfig::for_action("transfer").unit_limit_margin_percent as u64;
+        assert_eq!(budget.unit_limit as u64, (units * (100 + margin) / 100).max(1));
+        assert_eq!(budget.unit_price, 0);
+
+        let instructions = budget.apply(&[transfer]);
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(instructions[0], ComputeBudgetInstruction::set_compute_unit_limit(budget.unit_limit));
+    }
+}
@@ -0,0 +1,195 @@
+// Fee payments that already funded a mint. With an index database (DATABASE_URL)
+// a claim is a unique insert into `consumed_fee_payments`, so a payment funds one
+// mint across restarts and instances. The claim records the mint it funded, so a
+// restarted server still releases it if that mint never lands. Without a
+// database, claims live in memory and are lost on restart, so payments made
+// before the server started are refused.
+use std::{collections::HashSet, sync::Arc};
+
+use crate::index_db::IndexDb;
+use crate::transactions::{TransactionStatus, TransactionTracker};
+
+// A claim whose mint was never sent, because the server stopped in between, is
+// released once it is this old. Sending takes seconds after the claim.
+const UNSENT_CLAIM_TIMEOUT_SECS: i64 = 600;
+
+#[derive(Clone)]
+pub struct FeePaymentLedger {
+    index: Option<IndexDb>,
+    claimed: Arc<tokio::sync::RwLock<HashSet<String>>>, // Used without an index database
+    started_at: i64,
+}
+
+impl FeePaymentLedger {
+    pub fn new(index: Option<IndexDb>) -> Self {
+        FeePaymentLedger {
+            index,
+            claimed: Arc::default(),
+            started_at: chrono::Utc::now().timestamp(),
+        }
+    }
+
+    /// Reserve `signature` for one mint. Claim before verifying the payment so
+    /// concurrent requests can't both use it.
+    pub async fn claim(&self, signature: &str) -> Result<(), String> {
+        let claimed = match &self.index {
+            Some(index) => index.claim_fee_payment(signature, chrono::Utc::now().timestamp()).await?,
+            None => self.claimed.write().await.insert(signature.to_string()),
+        };
+        if !claimed {
+            return Err("Fee payment has already been used for another mint".to_string());
+        }
+        Ok(())
+    }
+
+    /// Give a claimed payment back, when the mint it was claimed for failed or
+    /// never landed, so the creator can retry with it
+    pub async fn release(&self, signature: &str) {
+        match &self.index {
+            Some(index) => {
+                if let Err(e) = index.release_fee_payment(signature).await {
+                    println!("{}", e);
+                }
+            }
+            None => {
+                self.claimed.write().await.remove(signature);
+            }
+        }
+    }
+
+    /// Tie a claimed payment to the mint it funded, then release it if that mint
+    /// doesn't finalize
+    pub async fn track_mint(&self, tracker: &TransactionTracker, signature: &str, mint_signature: &str) {
+        if let Some(index) = &self.index {
+            if let Err(e) = index.set_fee_payment_mint(signature, mint_signature).await {
+                println!("{}", e);
+            }
+        }
+        self.settle_when_final(tracker, signature, mint_signature);
+    }
+
+    /// Resolve the claims left by a previous run: those whose mint was sent are
+    /// settled or released once it is final, those never sent are released
+    pub async fn resume(&self, tracker: &TransactionTracker) -> Result<(), String> {
+        let Some(index) = &self.index else {
+            return Ok(());
+        };
+        let now = chrono::Utc::now().timestamp();
+        for (signature, mint_signature, claimed_at) in index.unsettled_fee_payments().await? {
+            match mint_signature {
+                Some(mint_signature) => self.settle_when_final(tracker, &signature, &mint_signature),
+                None if claimed_at < now - UNSENT_CLAIM_TIMEOUT_SECS => self.release(&signature).await,
+                // Possibly still being sent by another instance
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn settle_when_final(&self, tracker: &TransactionTracker, signature: &str, mint_signature: &str) {
+        let (ledger, tracker) = (self.clone(), tracker.clone());
+        let (signature, mint_signature) = (signature.to_string(), mint_signature.to_string());
+        tokio::spawn(async move {
+            if tracker.final_status(&mint_signature).await == Some(TransactionStatus::Finalized) {
+                if let Some(index) = &ledger.index {
+                    if let Err(e) = index.settle_fee_payment(&signature).await {
+                        println!("{}", e);
+                    }
+                }
+            } else {
+                ledger.release(&signature).await;
+            }
+        });
+    }
+
+    /// Whether a payment with this block time can be checked for reuse: claims
+    /// kept in memory only cover payments made since the server started
+    pub fn check_block_time(&self, block_time: i64) -> Result<(), String> {
+        if self.index.is_none() && block_time < self.started_at {
+            return Err(
+                "Fee payment was made before the server restarted and can't be checked for reuse; make a new payment"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn payments_are_claimed_once_until_released() {
+        let index = IndexDb::connect("sqlite::memory:").await.unwrap();
+        for ledger in [FeePaymentLedger::new(None), FeePaymentLedger::new(Some(index))] {
+            ledger.claim("payment").await.unwrap();
+            assert!(ledger.claim("payment").await.is_err());
+            ledger.claim("other").await.unwrap();
+
+            ledger.release("payment").await;
+            ledger.claim("payment").await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn claims_in_the_database_outlive_the_ledger() {
+        let index = IndexDb::connect("sqlite::memory:").await.unwrap();
+        FeePaymentLedger::new(Some(index.clone())).claim("payment").await.unwrap();
+
+        // A restarted server sees the claim, and accepts payments from before it started
+        let restarted = FeePaymentLedger::new(Some(index));
+        assert!(restarted.claim("payment").await.is_err());
+        assert!(restarted.check_block_time(restarted.started_at - 60).is_ok());
+    }
+
+    #[tokio::test]
+    async fn claims_left_by_a_previous_run_are_resolved() {
+        let index = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let chain: Arc<dyn crate::chain::ChainClient> = Arc::new(crate::memory_chain::MemoryChain::new());
+        let cache = crate::cache::ChainCache::new(chain, None, std::time::Duration::ZERO);
+        let tracker = TransactionTracker::new(cache, Some(index.clone()));
+        let now = chrono::Utc::now().timestamp();
+
+        // Mints that finalized and expired while the server was down
+        for (mint, status) in [("landed", TransactionStatus::Finalized), ("dropped", TransactionStatus::Expired)] {
+            let tracked = crate::transactions::TrackedTransaction {
+                action: "mint_nft".to_string(),
+                status,
+                error: None,
+                submitted_at: String::new(),
+                updated_at: String::new(),
+                last_valid_block_height: 0,
+                rebroadcasts: 0,
+                replaced_by: None,
+            };
+            index.save_submitted(mint, &tracked, None).await.unwrap();
+        }
+        let ledger = FeePaymentLedger::new(Some(index.clone()));
+        for (payment, mint) in [("paid-landed", "landed"), ("paid-dropped", "dropped")] {
+            ledger.claim(payment).await.unwrap();
+            index.set_fee_payment_mint(payment, mint).await.unwrap();
+        }
+        // Claims whose mint was never sent
+        index.claim_fee_payment("unsent-old", now - UNSENT_CLAIM_TIMEOUT_SECS - 1).await.unwrap();
+        index.claim_fee_payment("unsent-recent", now).await.unwrap();
+
+        let restarted = FeePaymentLedger::new(Some(index.clone()));
+        restarted.resume(&tracker).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let unsettled: Vec<String> = index.unsettled_fee_payments().await.unwrap().into_iter().map(|(payment, ..)| payment).collect();
+        assert_eq!(unsettled, ["unsent-recent"]);
+        assert!(restarted.claim("paid-landed").await.is_err());
+        assert!(restarted.claim("unsent-recent").await.is_err());
+        restarted.claim("paid-dropped").await.unwrap();
+        restarted.claim("unsent-old").await.unwrap();
+    }
+
+    #[test]
+    fn memory_claims_only_cover_payments_since_startup() {
+        let ledger = FeePaymentLedger::new(None);
+        assert!(ledger.check_block_time(ledger.started_at).is_ok());
+        assert!(ledger.check_block_time(ledger.started_at - 1).is_err());
+    }
+}
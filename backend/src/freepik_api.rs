@@ -92,7 +92,7 @@ impl FreepikApiClient {
             tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
 
             let poll_response = self.client
-                .get(&format!("https://api.freepik.com/v1/ai/mystic/{}", task_id))
+                .get(format!("https://api.freepik.com/v1/ai/mystic/{}", task_id))
                 .header("x-freepik-api-key", &self.api_key)
                 .send()
                 .await?;
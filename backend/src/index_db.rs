@@ -0,0 +1,1340 @@
+// Marketplace index: listings, sales and NFTs decoded from program transactions,
+// stored in SQLite or Postgres (DATABASE_URL), plus the indexer's checkpoints and
+// the backend's own state that must survive a restart (used fee payments and the
+// transactions it prepared and sent).
+// Listing and sale events carry the commitment they were indexed at. Finalized
+// rows are never overwritten by confirmed ones; confirmed rows are promoted when
+// their transaction finalizes or rolled back when it drops with its fork.
+// Every write is idempotent and independent of the order transactions are
+// applied in, so the live indexer and a backfill can run side by side, and
+// re-applying a transaction replaces what an older decoder recorded for it.
+use std::collections::HashSet;
+
+use sqlx::{
+    any::{Any, AnyArguments, AnyPool, AnyPoolOptions},
+    query::Query,
+    Row,
+};
+
+use crate::activity::{ActivityKind, ActivityRecord, WalletActivityEvent, WalletActivityKind};
+use crate::cache::FirstTransaction;
+use crate::chain::rfc3339;
+use crate::indexer::{Commitment, IndexedTransaction, MarketplaceEvent};
+use crate::transactions::{TrackedTransaction, TransactionStatus};
+
+const MAX_CONNECTIONS: u32 = 5;
+
+// Portable between SQLite and Postgres: no serial columns or dialect-only functions
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS indexer_checkpoints (
+        name TEXT PRIMARY KEY,
+        signature TEXT NOT NULL,
+        slot BIGINT NOT NULL,
+        updated_at BIGINT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS nfts (
+        mint TEXT PRIMARY KEY,
+        name TEXT,
+        symbol TEXT,
+        uri TEXT,
+        collection TEXT,
+        first_seen_slot BIGINT NOT NULL,
+        first_seen_at BIGINT
+    )",
+    "CREATE INDEX IF NOT EXISTS nfts_collection ON nfts (collection)",
+    "CREATE TABLE IF NOT EXISTS listings (
+        listing_address TEXT PRIMARY KEY,
+        mint TEXT NOT NULL,
+        seller TEXT NOT NULL,
+        price BIGINT NOT NULL,
+        status TEXT NOT NULL,
+        listed_signature TEXT,
+        listed_slot BIGINT,
+        listed_at BIGINT,
+        closed_signature TEXT,
+        closed_slot BIGINT,
+        closed_at BIGINT,
+        listed_commitment TEXT,
+        closed_commitment TEXT
+    )",
+    "CREATE INDEX IF NOT EXISTS listings_mint ON listings (mint)",
+    "CREATE INDEX IF NOT EXISTS listings_seller ON listings (seller)",
+    "CREATE TABLE IF NOT EXISTS sales (
+        signature TEXT NOT NULL,
+        instruction_index BIGINT NOT NULL,
+        listing_address TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        seller TEXT NOT NULL,
+        buyer TEXT NOT NULL,
+        price BIGINT NOT NULL,
+        slot BIGINT NOT NULL,
+        block_time BIGINT,
+        commitment TEXT NOT NULL,
+        PRIMARY KEY (signature, instruction_index)
+    )",
+    "CREATE INDEX IF NOT EXISTS sales_mint ON sales (mint)",
+    "CREATE INDEX IF NOT EXISTS sales_block_time ON sales (block_time)",
+    // Fee and fee payer of each transaction with marketplace events
+    "CREATE TABLE IF NOT EXISTS indexed_transactions (
+        signature TEXT PRIMARY KEY,
+        fee BIGINT,
+        fee_payer TEXT
+    )",
+    // Transactions indexed at `confirmed`, awaiting finalization or rollback, with
+    // how many polls in a row found no status for them
+    "CREATE TABLE IF NOT EXISTS unfinalized_transactions (
+        signature TEXT PRIMARY KEY,
+        slot BIGINT NOT NULL,
+        missed_polls BIGINT NOT NULL DEFAULT 0
+    )",
+    // How each NFT in the index was minted, from its mint account's first transaction
+    "CREATE TABLE IF NOT EXISTS mints (
+        mint TEXT PRIMARY KEY,
+        signature TEXT NOT NULL,
+        slot BIGINT NOT NULL,
+        block_time BIGINT,
+        minter TEXT,
+        amount BIGINT,
+        fee BIGINT,
+        fee_payer TEXT
+    )",
+    "CREATE INDEX IF NOT EXISTS mints_minter ON mints (minter)",
+    // Fee payments that funded a mint, see fee_payments.rs
+    "CREATE TABLE IF NOT EXISTS consumed_fee_payments (
+        signature TEXT PRIMARY KEY,
+        claimed_at BIGINT NOT NULL,
+        mint_signature TEXT,
+        settled BOOLEAN NOT NULL DEFAULT TRUE
+    )",
+    // Transactions returned for wallets to sign, keyed by message hash (transactions.rs)
+    "CREATE TABLE IF NOT EXISTS prepared_transactions (
+        message_hash TEXT PRIMARY KEY,
+        action TEXT NOT NULL,
+        last_valid_block_height BIGINT NOT NULL
+    )",
+    // Sent transactions by signature. Until their status is final they keep the
+    // transaction and, when the backend signed it alone, what it takes to re-sign it.
+    "CREATE TABLE IF NOT EXISTS submitted_transactions (
+        signature TEXT PRIMARY KEY,
+        action TEXT NOT NULL,
+        status TEXT NOT NULL,
+        error TEXT,
+        submitted_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        last_valid_block_height BIGINT NOT NULL,
+        rebroadcasts BIGINT NOT NULL,
+        replaced_by TEXT,
+        transaction_data TEXT,
+        resigner TEXT
+    )",
+];
+
+// Columns added after their table was first released: (table, column, definition)
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("unfinalized_transactions", "missed_polls", "BIGINT NOT NULL DEFAULT 0"),
+    ("consumed_fee_payments", "mint_signature", "TEXT"),
+    ("consumed_fee_payments", "settled", "BOOLEAN NOT NULL DEFAULT TRUE"),
+];
+
+// Metadata recorded for an NFT the first time it is seen in marketplace activity
+pub struct NftRecord {
+    pub mint: String,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub uri: Option<String>,
+    // Verified collection mint
+    pub collection: Option<String>,
+    // Set the first time the NFT is seen, or when it is re-indexed
+    pub minted: Option<MintRecord>,
+}
+
+// The first transaction of an NFT's mint account
+pub struct MintRecord {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub minter: Option<String>, // Wallet the transaction left the token with
+    pub amount: Option<u64>, // Lamports the minter spent besides the network fee
+    pub fee: Option<u64>,
+    pub fee_payer: Option<String>,
+}
+
+// Marketplace totals over finalized data; prices and volumes in lamports
+pub struct MarketStats {
+    pub total_nfts: u64,
+    pub total_listed: u64,
+    pub total_sold: u64,
+    pub floor_price: Option<u64>,
+    pub total_volume: u64,
+    pub volume_24h: u64,
+    pub volume_7d: u64,
+    pub volume_30d: u64,
+    pub unique_traders: u64,
+}
+
+// A finalized sale's block time and price in lamports
+pub struct SalePrice {
+    pub time: i64,
+    pub price: u64,
+}
+
+// When a finalized listing was open, `closed_at` None while it still is
+pub struct ListingSpan {
+    pub price: u64,
+    pub listed_at: i64,
+    pub closed_at: Option<i64>,
+}
+
+// A sent transaction whose status is not final yet, to be tracked again after a restart
+pub struct PendingTransaction {
+    pub signature: String,
+    pub tracked: TrackedTransaction,
+    pub transaction: String, // Base64 bincode
+    pub resigner: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct IndexDb {
+    pool: AnyPool,
+}
+
+impl IndexDb {
+    /// Connect to DATABASE_URL (`sqlite://...` or `postgres://...`) and create
+    /// the schema if it does not exist yet
+    pub async fn connect(url: &str) -> Result<Self, String> {
+        let pool = AnyPoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect(url)
+            .await
+            .map_err(|e| format!("Failed to connect to index database: {}", e))?;
+        for statement in SCHEMA {
+            sqlx::query(statement)
+                .execute(&pool)
+                .await
+                .map_err(|e| format!("Failed to create index schema: {}", e))?;
+        }
+        // Neither database has a portable ADD COLUMN IF NOT EXISTS
+        for (table, column, definition) in ADDED_COLUMNS {
+            let exists = sqlx::query(&format!("SELECT {} FROM {} LIMIT 1", column, table))
+                .fetch_optional(&pool)
+                .await
+                .is_ok();
+            if !exists {
+                sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+                    .execute(&pool)
+                    .await
+                    .map_err(|e| format!("Failed to add {}.{}: {}", table, column, e))?;
+            }
+        }
+        Ok(IndexDb { pool })
+    }
+
+    /// Signature the checkpoint `name` was last moved to
+    pub async fn checkpoint(&self, name: &str) -> Result<Option<String>, String> {
+        let row = sqlx::query("SELECT signature FROM indexer_checkpoints WHERE name = $1")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to read checkpoint {}: {}", name, e))?;
+        Ok(row.map(|row| row.get("signature")))
+    }
+
+    /// Transactions indexed at `confirmed` that have not finalized yet, with their slots
+    pub async fn unfinalized(&self) -> Result<Vec<(String, u64)>, String> {
+        let rows = sqlx::query("SELECT signature, slot FROM unfinalized_transactions ORDER BY slot")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to read unfinalized transactions: {}", e))?;
+        Ok(rows.iter()
+            .map(|row| (row.get("signature"), row.get::<i64, _>("slot") as u64))
+            .collect())
+    }
+
+    /// Count a poll that found no status for an unfinalized transaction; returns
+    /// how many polls in a row have missed it
+    pub async fn record_missed_poll(&self, signature: &str) -> Result<u32, String> {
+        let db_error = |e: sqlx::Error| format!("Failed to update {}: {}", signature, e);
+        sqlx::query("UPDATE unfinalized_transactions SET missed_polls = missed_polls + 1 WHERE signature = $1")
+            .bind(signature)
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)?;
+        let row = sqlx::query("SELECT missed_polls FROM unfinalized_transactions WHERE signature = $1")
+            .bind(signature)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_error)?;
+        Ok(row.map_or(0, |row| row.get::<i64, _>("missed_polls") as u32))
+    }
+
+    /// An unfinalized transaction has a status again
+    pub async fn clear_missed_polls(&self, signature: &str) -> Result<(), String> {
+        sqlx::query("UPDATE unfinalized_transactions SET missed_polls = 0 WHERE signature = $1 AND missed_polls > 0")
+            .bind(signature)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to update {}: {}", signature, e))?;
+        Ok(())
+    }
+
+    /// Undo what an unfinalized transaction recorded: its sales are deleted, the
+    /// listings it sold are active again and the listings it created are gone
+    pub async fn roll_back(&self, signature: &str) -> Result<(), String> {
+        let db_error = |e: sqlx::Error| format!("Failed to roll back {}: {}", signature, e);
+        let mut db = self.pool.begin().await.map_err(db_error)?;
+        for statement in [
+            "DELETE FROM sales WHERE signature = $1 AND commitment = 'confirmed'",
+            "UPDATE listings SET status = 'active', closed_signature = NULL, closed_slot = NULL,
+                 closed_at = NULL, closed_commitment = NULL
+             WHERE closed_signature = $1 AND closed_commitment = 'confirmed'",
+            "DELETE FROM listings WHERE listed_signature = $1 AND listed_commitment = 'confirmed'",
+            "DELETE FROM indexed_transactions WHERE signature = $1",
+            "DELETE FROM unfinalized_transactions WHERE signature = $1",
+        ] {
+            sqlx::query(statement).bind(signature).execute(&mut db).await.map_err(db_error)?;
+        }
+        // Sale-only rows whose sale was undone, and NFTs no event refers to any more
+        for statement in [
+            "DELETE FROM listings WHERE listed_signature IS NULL AND closed_signature IS NULL",
+            "DELETE FROM nfts WHERE NOT EXISTS (SELECT 1 FROM listings WHERE listings.mint = nfts.mint)
+                 AND NOT EXISTS (SELECT 1 FROM sales WHERE sales.mint = nfts.mint)",
+        ] {
+            sqlx::query(statement).execute(&mut db).await.map_err(db_error)?;
+        }
+        db.commit().await.map_err(db_error)
+    }
+
+    /// Replace a mint's listings, sales and NFT row with freshly decoded
+    /// transactions, in one database transaction so readers never see the mint
+    /// half rebuilt. Unfinalized transactions of the mint that no longer have
+    /// rows are forgotten, since only finalized transactions are re-indexed.
+    pub async fn replace_mint(&self, mint: &str, transactions: &[(IndexedTransaction, Vec<NftRecord>)]) -> Result<(), String> {
+        let db_error = |e: sqlx::Error| format!("Failed to re-index {}: {}", mint, e);
+        let mut db = self.pool.begin().await.map_err(db_error)?;
+
+        let previous = sqlx::query(
+            "SELECT signature FROM sales WHERE mint = $1
+             UNION SELECT listed_signature FROM listings WHERE mint = $1 AND listed_signature IS NOT NULL
+             UNION SELECT closed_signature FROM listings WHERE mint = $1 AND closed_signature IS NOT NULL",
+        )
+        .bind(mint)
+        .fetch_all(&mut db)
+        .await
+        .map_err(db_error)?;
+        for statement in [
+            "DELETE FROM sales WHERE mint = $1",
+            "DELETE FROM listings WHERE mint = $1",
+            "DELETE FROM nfts WHERE mint = $1",
+        ] {
+            sqlx::query(statement).bind(mint).execute(&mut db).await.map_err(db_error)?;
+        }
+
+        for (transaction, nfts) in transactions {
+            apply_events(&mut db, transaction, nfts, None).await?;
+        }
+
+        for row in &previous {
+            let signature: String = row.get(0);
+            sqlx::query(
+                "DELETE FROM unfinalized_transactions WHERE signature = $1
+                     AND NOT EXISTS (SELECT 1 FROM sales WHERE signature = $1)
+                     AND NOT EXISTS (SELECT 1 FROM listings WHERE listed_signature = $1 OR closed_signature = $1)",
+            )
+            .bind(signature)
+            .execute(&mut db)
+            .await
+            .map_err(db_error)?;
+        }
+        db.commit().await.map_err(db_error)
+    }
+
+    /// Record a transaction's events, its NFTs and, when `checkpoint` is given,
+    /// move that checkpoint to it, all in one database transaction
+    pub async fn apply(
+        &self,
+        transaction: &IndexedTransaction,
+        nfts: &[NftRecord],
+        checkpoint: Option<&str>,
+    ) -> Result<(), String> {
+        let db_error = |e: sqlx::Error| format!("Failed to index {}: {}", transaction.signature, e);
+        let mut db = self.pool.begin().await.map_err(db_error)?;
+        apply_events(&mut db, transaction, nfts, checkpoint).await?;
+        db.commit().await.map_err(db_error)
+    }
+
+    /// Record a fee payment as used; false when it already was
+    pub async fn claim_fee_payment(&self, signature: &str, now: i64) -> Result<bool, String> {
+        let result = sqlx::query(
+            "INSERT INTO consumed_fee_payments (signature, claimed_at, settled) VALUES ($1, $2, $3)
+             ON CONFLICT (signature) DO NOTHING",
+        )
+        .bind(signature)
+        .bind(now)
+        .bind(false)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| format!("Failed to claim fee payment {}: {}", signature, e))?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn release_fee_payment(&self, signature: &str) -> Result<(), String> {
+        sqlx::query("DELETE FROM consumed_fee_payments WHERE signature = $1")
+            .bind(signature)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to release fee payment {}: {}", signature, e))?;
+        Ok(())
+    }
+
+    /// Record the mint a claimed payment funded, sent but not yet final
+    pub async fn set_fee_payment_mint(&self, signature: &str, mint_signature: &str) -> Result<(), String> {
+        sqlx::query("UPDATE consumed_fee_payments SET mint_signature = $2 WHERE signature = $1")
+            .bind(signature)
+            .bind(mint_signature)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to record the mint of fee payment {}: {}", signature, e))?;
+        Ok(())
+    }
+
+    /// Keep a claimed payment for good, once the mint it funded finalized
+    pub async fn settle_fee_payment(&self, signature: &str) -> Result<(), String> {
+        sqlx::query("UPDATE consumed_fee_payments SET settled = $2 WHERE signature = $1")
+            .bind(signature)
+            .bind(true)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to settle fee payment {}: {}", signature, e))?;
+        Ok(())
+    }
+
+    /// Claimed payments whose mint hasn't finalized: signature, mint signature
+    /// once it was sent, and claim time
+    pub async fn unsettled_fee_payments(&self) -> Result<Vec<(String, Option<String>, i64)>, String> {
+        let rows = sqlx::query(
+            "SELECT signature, mint_signature, claimed_at FROM consumed_fee_payments WHERE settled = $1",
+        )
+        .bind(false)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| format!("Failed to read fee payments: {}", e))?;
+        Ok(rows.iter()
+            .map(|row| (row.get("signature"), row.get("mint_signature"), row.get("claimed_at")))
+            .collect())
+    }
+
+    /// Remember a prepared transaction, dropping the ones that can no longer land
+    pub async fn save_prepared(&self, message_hash: &str, action: &str, last_valid_block_height: u64, expired_before: u64) -> Result<(), String> {
+        let db_error = |e: sqlx::Error| format!("Failed to save prepared transaction: {}", e);
+        let mut db = self.pool.begin().await.map_err(db_error)?;
+        sqlx::query("DELETE FROM prepared_transactions WHERE last_valid_block_height < $1")
+            .bind(expired_before as i64)
+            .execute(&mut db)
+            .await
+            .map_err(db_error)?;
+        sqlx::query(
+            "INSERT INTO prepared_transactions (message_hash, action, last_valid_block_height) VALUES ($1, $2, $3)
+             ON CONFLICT (message_hash) DO UPDATE SET
+                 action = excluded.action, last_valid_block_height = excluded.last_valid_block_height",
+        )
+        .bind(message_hash)
+        .bind(action)
+        .bind(last_valid_block_height as i64)
+        .execute(&mut db)
+        .await
+        .map_err(db_error)?;
+        db.commit().await.map_err(db_error)
+    }
+
+    pub async fn delete_prepared(&self, message_hash: &str) -> Result<(), String> {
+        sqlx::query("DELETE FROM prepared_transactions WHERE message_hash = $1")
+            .bind(message_hash)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to delete prepared transaction: {}", e))?;
+        Ok(())
+    }
+
+    /// Prepared transactions as (message hash, action, last valid block height)
+    pub async fn prepared_transactions(&self) -> Result<Vec<(String, String, u64)>, String> {
+        let rows = sqlx::query("SELECT message_hash, action, last_valid_block_height FROM prepared_transactions")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to read prepared transactions: {}", e))?;
+        Ok(rows.iter()
+            .map(|row| (
+                row.get("message_hash"),
+                row.get("action"),
+                row.get::<i64, _>("last_valid_block_height") as u64,
+            ))
+            .collect())
+    }
+
+    /// Record a sent transaction's status. `sent` carries the transaction and its
+    /// re-signing data when it is first recorded; both are dropped once the status
+    /// is final, since nothing will send it again.
+    pub async fn save_submitted(
+        &self,
+        signature: &str,
+        tracked: &TrackedTransaction,
+        sent: Option<(&str, Option<&str>)>,
+    ) -> Result<(), String> {
+        let (transaction, resigner) = sent.unzip();
+        sqlx::query(
+            "INSERT INTO submitted_transactions (signature, action, status, error, submitted_at, updated_at,
+                 last_valid_block_height, rebroadcasts, replaced_by, transaction_data, resigner)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+             ON CONFLICT (signature) DO UPDATE SET
+                 status = excluded.status, error = excluded.error, updated_at = excluded.updated_at,
+                 last_valid_block_height = excluded.last_valid_block_height,
+                 rebroadcasts = excluded.rebroadcasts, replaced_by = excluded.replaced_by,
+                 transaction_data = CASE WHEN $12 THEN NULL
+                     ELSE COALESCE(excluded.transaction_data, submitted_transactions.transaction_data) END,
+                 resigner = CASE WHEN $12 THEN NULL
+                     ELSE COALESCE(excluded.resigner, submitted_transactions.resigner) END",
+        )
+        .bind(signature)
+        .bind(&tracked.action)
+        .bind(tracked.status.as_str())
+        .bind(&tracked.error)
+        .bind(&tracked.submitted_at)
+        .bind(&tracked.updated_at)
+        .bind(tracked.last_valid_block_height as i64)
+        .bind(tracked.rebroadcasts as i64)
+        .bind(&tracked.replaced_by)
+        .bind(transaction)
+        .bind(resigner.flatten())
+        .bind(tracked.status.is_final())
+        .execute(&self.pool)
+        .await
+        .map_err(|e| format!("Failed to save transaction {}: {}", signature, e))?;
+        Ok(())
+    }
+
+    pub async fn submitted_transaction(&self, signature: &str) -> Result<Option<TrackedTransaction>, String> {
+        let row = sqlx::query("SELECT * FROM submitted_transactions WHERE signature = $1")
+            .bind(signature)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to read transaction {}: {}", signature, e))?;
+        Ok(row.as_ref().map(tracked_transaction))
+    }
+
+    /// Sent transactions whose status is not final yet, oldest first
+    pub async fn pending_transactions(&self) -> Result<Vec<PendingTransaction>, String> {
+        let rows = sqlx::query(
+            "SELECT * FROM submitted_transactions
+             WHERE status IN ('submitted', 'confirmed') AND transaction_data IS NOT NULL
+             ORDER BY submitted_at",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| format!("Failed to read pending transactions: {}", e))?;
+        Ok(rows.iter()
+            .map(|row| PendingTransaction {
+                signature: row.get("signature"),
+                tracked: tracked_transaction(row),
+                transaction: row.get("transaction_data"),
+                resigner: row.get("resigner"),
+            })
+            .collect())
+    }
+
+    /// Listing and sale events of one NFT, in no particular order
+    pub async fn mint_activity(&self, mint: &str) -> Result<Vec<ActivityRecord>, String> {
+        self.activity("mint = $1", "mint = $1", mint).await
+    }
+
+    /// A wallet's mints, listings and the sales it bought or sold in, of `kinds`
+    /// (empty for all) with a block time in `[from, to)` (no block time counts as
+    /// 0), newest first: by slot, then signature. A transaction's fee is charged
+    /// once, on its first event, and only when the wallet paid it.
+    pub async fn wallet_activity(
+        &self,
+        wallet: &str,
+        kinds: &[WalletActivityKind],
+        from: Option<i64>,
+        to: Option<i64>,
+        limit: Option<usize>,
+        offset: usize,
+    ) -> Result<Vec<WalletActivityEvent>, String> {
+        let rows = sqlx::query(&format!(
+            "{},
+             charged AS (
+                 SELECT events.*, ROW_NUMBER() OVER (PARTITION BY signature ORDER BY kind_rank, mint) AS position
+                 FROM events
+             )
+             SELECT event_type, mint, signature, slot, block_time, amount, counterparty, commitment,
+                 CASE WHEN position = 1 AND fee_payer = $1 THEN COALESCE(fee, 0) ELSE 0 END AS fee
+             FROM charged
+             ORDER BY slot DESC, signature, kind_rank, mint
+             LIMIT $4 OFFSET $5",
+            wallet_events(kinds),
+        ))
+        .bind(wallet)
+        .bind(from.unwrap_or(i64::MIN))
+        .bind(to.unwrap_or(i64::MAX))
+        .bind(limit.map_or(i64::MAX, |limit| limit as i64))
+        .bind(offset as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| format!("Failed to read wallet activity: {}", e))?;
+        rows.iter()
+            .map(|row| Ok(WalletActivityEvent {
+                event_type: row.get::<String, _>("event_type").parse()?,
+                mint: row.get("mint"),
+                signature: row.get("signature"),
+                slot: row.get::<i64, _>("slot") as u64,
+                timestamp: rfc3339(row.get("block_time")),
+                amount: row.get::<Option<i64>, _>("amount").map(|amount| amount as u64),
+                fee: row.get::<i64, _>("fee") as u64,
+                royalties: 0,
+                counterparty: row.get("counterparty"),
+                commitment: row.get("commitment"),
+            }))
+            .collect()
+    }
+
+    /// How many events `wallet_activity` has over `[from, to)`, before paging
+    pub async fn wallet_activity_count(
+        &self,
+        wallet: &str,
+        kinds: &[WalletActivityKind],
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<u64, String> {
+        let row = sqlx::query(&format!("{} SELECT COUNT(*) AS count FROM events", wallet_events(kinds)))
+            .bind(wallet)
+            .bind(from.unwrap_or(i64::MIN))
+            .bind(to.unwrap_or(i64::MAX))
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to count activity: {}", e))?;
+        Ok(row.get::<i64, _>("count") as u64)
+    }
+
+    /// Price of the latest list_nft indexed for a listing account
+    pub async fn listing_price(&self, listing_address: &str) -> Result<Option<u64>, String> {
+        let row = sqlx::query("SELECT price FROM listings WHERE listing_address = $1")
+            .bind(listing_address)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to read listing {}: {}", listing_address, e))?;
+        Ok(row.map(|row| row.get::<i64, _>("price") as u64))
+    }
+
+    /// The first transaction of `mint` as recorded when the indexer first saw it
+    pub async fn mint_transaction(&self, mint: &str) -> Result<Option<FirstTransaction>, String> {
+        let row = sqlx::query("SELECT signature, slot, block_time FROM mints WHERE mint = $1")
+            .bind(mint)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to read mint {}: {}", mint, e))?;
+        Ok(row.map(|row| FirstTransaction {
+            signature: row.get("signature"),
+            slot: row.get::<i64, _>("slot") as u64,
+            block_time: row.get("block_time"),
+        }))
+    }
+
+    /// Which of `mints` have a mint record already
+    pub async fn recorded_mints(&self, mints: &[String]) -> Result<HashSet<String>, String> {
+        let mut recorded = HashSet::new();
+        for mint in mints {
+            let row = sqlx::query("SELECT mint FROM mints WHERE mint = $1")
+                .bind(mint)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| format!("Failed to read mint {}: {}", mint, e))?;
+            if row.is_some() {
+                recorded.insert(mint.clone());
+            }
+        }
+        Ok(recorded)
+    }
+
+    async fn activity(&self, listings_filter: &str, sales_filter: &str, value: &str) -> Result<Vec<ActivityRecord>, String> {
+        let db_error = |e: sqlx::Error| format!("Failed to read activity: {}", e);
+        let listings = sqlx::query(&format!(
+            "SELECT listing_address, mint, seller, price, listed_signature, listed_slot, listed_at, listed_commitment
+             FROM listings WHERE listed_signature IS NOT NULL AND {}",
+            listings_filter,
+        ))
+        .bind(value)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_error)?;
+        let sales = sqlx::query(&format!(
+            "SELECT signature, listing_address, mint, seller, buyer, price, slot, block_time, commitment
+             FROM sales WHERE {}",
+            sales_filter,
+        ))
+        .bind(value)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        let listed = listings.iter().map(|row| ActivityRecord {
+            kind: ActivityKind::List,
+            signature: row.get("listed_signature"),
+            slot: row.get::<Option<i64>, _>("listed_slot").unwrap_or_default() as u64,
+            block_time: row.get("listed_at"),
+            mint: row.get("mint"),
+            price: Some(row.get::<i64, _>("price") as u64),
+            seller: Some(row.get("seller")),
+            buyer: None,
+            listing_address: Some(row.get("listing_address")),
+            commitment: commitment(row.get("listed_commitment")),
+        });
+        let sold = sales.iter().map(|row| ActivityRecord {
+            kind: ActivityKind::Sale,
+            signature: row.get("signature"),
+            slot: row.get::<i64, _>("slot") as u64,
+            block_time: row.get("block_time"),
+            mint: row.get("mint"),
+            price: Some(row.get::<i64, _>("price") as u64),
+            seller: Some(row.get("seller")),
+            buyer: Some(row.get("buyer")),
+            listing_address: Some(row.get("listing_address")),
+            commitment: commitment(row.get("commitment")),
+        });
+        Ok(listed.chain(sold).collect())
+    }
+
+    /// Totals over finalized events only, so nothing a dropped fork recorded is
+    /// counted; `collection` narrows them to NFTs of that verified collection
+    pub async fn stats(&self, collection: Option<&str>, now: i64) -> Result<MarketStats, String> {
+        let db_error = |e: sqlx::Error| format!("Failed to compute marketplace stats: {}", e);
+        // Always the last placeholder of the query it is appended to
+        let in_collection = |placeholder: &str| match collection {
+            Some(_) => format!(" AND mint IN (SELECT mint FROM nfts WHERE collection = {})", placeholder),
+            None => String::new(),
+        };
+
+        let nfts_query = format!(
+            "SELECT COUNT(*) AS count FROM nfts
+             WHERE (EXISTS (SELECT 1 FROM listings WHERE listings.mint = nfts.mint AND listings.listed_commitment = 'finalized')
+                 OR EXISTS (SELECT 1 FROM sales WHERE sales.mint = nfts.mint AND sales.commitment = 'finalized')){}",
+            if collection.is_some() { " AND collection = $1" } else { "" },
+        );
+        let nfts = bind_collection(sqlx::query(&nfts_query), collection)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        // Active as far as finalized data goes: a sale that is only confirmed does not end it
+        let listed_query = format!(
+            "SELECT COUNT(*) AS count, MIN(price) AS floor FROM listings
+             WHERE listed_commitment = 'finalized'
+                 AND (status = 'active' OR closed_commitment IS NULL OR closed_commitment <> 'finalized'){}",
+            in_collection("$1"),
+        );
+        let listed = bind_collection(sqlx::query(&listed_query), collection)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        let sales_query = format!(
+            "SELECT COUNT(*) AS count,
+                 CAST(COALESCE(SUM(price), 0) AS BIGINT) AS volume,
+                 CAST(COALESCE(SUM(CASE WHEN block_time >= $1 THEN price ELSE 0 END), 0) AS BIGINT) AS volume_24h,
+                 CAST(COALESCE(SUM(CASE WHEN block_time >= $2 THEN price ELSE 0 END), 0) AS BIGINT) AS volume_7d,
+                 CAST(COALESCE(SUM(CASE WHEN block_time >= $3 THEN price ELSE 0 END), 0) AS BIGINT) AS volume_30d
+             FROM sales WHERE commitment = 'finalized'{}",
+            in_collection("$4"),
+        );
+        const DAY: i64 = 24 * 60 * 60;
+        let sales = sqlx::query(&sales_query)
+            .bind(now - DAY)
+            .bind(now - 7 * DAY)
+            .bind(now - 30 * DAY);
+        let sales = bind_collection(sales, collection)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        let traders_query = format!(
+            "SELECT COUNT(*) AS count FROM (
+                 SELECT seller AS trader FROM sales WHERE commitment = 'finalized'{0}
+                 UNION SELECT buyer AS trader FROM sales WHERE commitment = 'finalized'{0}
+             ) traders",
+            in_collection("$1"),
+        );
+        let traders = bind_collection(sqlx::query(&traders_query), collection)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        let count = |row: &sqlx::any::AnyRow, column: &str| row.get::<i64, _>(column) as u64;
+        Ok(MarketStats {
+            total_nfts: count(&nfts, "count"),
+            total_listed: count(&listed, "count"),
+            total_sold: count(&sales, "count"),
+            floor_price: listed.get::<Option<i64>, _>("floor").map(|floor| floor as u64),
+            total_volume: count(&sales, "volume"),
+            volume_24h: count(&sales, "volume_24h"),
+            volume_7d: count(&sales, "volume_7d"),
+            volume_30d: count(&sales, "volume_30d"),
+            unique_traders: count(&traders, "count"),
+        })
+    }
+
+    /// Finalized sales of a collection's NFTs with a block time in `[from, to)`,
+    /// oldest first
+    pub async fn collection_sales(&self, collection: &str, from: i64, to: i64) -> Result<Vec<SalePrice>, String> {
+        let rows = sqlx::query(
+            "SELECT block_time, price FROM sales
+             WHERE commitment = 'finalized' AND block_time >= $1 AND block_time < $2
+                 AND mint IN (SELECT mint FROM nfts WHERE collection = $3)
+             ORDER BY block_time, slot, signature, instruction_index",
+        )
+        .bind(from)
+        .bind(to)
+        .bind(collection)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| format!("Failed to read sales: {}", e))?;
+        Ok(rows.iter()
+            .map(|row| SalePrice {
+                time: row.get("block_time"),
+                price: row.get::<i64, _>("price") as u64,
+            })
+            .collect())
+    }
+
+    /// Finalized listings of a collection's NFTs that were open at some point in
+    /// `[from, to)`. A sale that is only confirmed does not close a listing.
+    pub async fn collection_listings(&self, collection: &str, from: i64, to: i64) -> Result<Vec<ListingSpan>, String> {
+        let rows = sqlx::query(
+            "SELECT price, listed_at, CASE WHEN closed_commitment = 'finalized' THEN closed_at END AS closed_at
+             FROM listings
+             WHERE listed_commitment = 'finalized' AND listed_at IS NOT NULL AND listed_at < $2
+                 AND (closed_commitment IS NULL OR closed_commitment <> 'finalized' OR closed_at >= $1)
+                 AND mint IN (SELECT mint FROM nfts WHERE collection = $3)",
+        )
+        .bind(from)
+        .bind(to)
+        .bind(collection)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| format!("Failed to read listings: {}", e))?;
+        Ok(rows.iter()
+            .map(|row| ListingSpan {
+                price: row.get::<i64, _>("price") as u64,
+                listed_at: row.get("listed_at"),
+                closed_at: row.get("closed_at"),
+            })
+            .collect())
+    }
+}
+
+// The writes of `IndexDb::apply`, inside the caller's database transaction
+async fn apply_events(
+    db: &mut sqlx::Transaction<'_, Any>,
+    transaction: &IndexedTransaction,
+    nfts: &[NftRecord],
+    checkpoint: Option<&str>,
+) -> Result<(), String> {
+    let db_error = |e: sqlx::Error| format!("Failed to index {}: {}", transaction.signature, e);
+    let slot = transaction.slot as i64;
+    let commitment = transaction.commitment.as_str();
+
+    for (instruction_index, event) in &transaction.events {
+        match event {
+            MarketplaceEvent::Listed { listing, nft_mint, seller, price } => {
+                // A sale indexed first leaves the listing sold
+                sqlx::query(
+                    "INSERT INTO listings (listing_address, mint, seller, price, status, listed_signature, listed_slot, listed_at, listed_commitment)
+                     VALUES ($1, $2, $3, $4, 'active', $5, $6, $7, $8)
+                     ON CONFLICT (listing_address) DO UPDATE SET
+                         mint = excluded.mint, seller = excluded.seller, price = excluded.price,
+                         listed_signature = excluded.listed_signature, listed_slot = excluded.listed_slot,
+                         listed_at = excluded.listed_at, listed_commitment = excluded.listed_commitment
+                     WHERE excluded.listed_commitment = 'finalized' OR listings.listed_commitment IS NULL
+                         OR listings.listed_commitment = 'confirmed'",
+                )
+                .bind(listing.to_string())
+                .bind(nft_mint.to_string())
+                .bind(seller.to_string())
+                .bind(*price as i64)
+                .bind(&transaction.signature)
+                .bind(slot)
+                .bind(transaction.block_time)
+                .bind(commitment)
+                .execute(&mut *db)
+                .await
+                .map_err(db_error)?;
+            }
+            MarketplaceEvent::Sold { listing, nft_mint, seller, buyer, price } => {
+                sqlx::query(
+                    "INSERT INTO sales (signature, instruction_index, listing_address, mint, seller, buyer, price, slot, block_time, commitment)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                     ON CONFLICT (signature, instruction_index) DO UPDATE SET
+                         listing_address = excluded.listing_address, mint = excluded.mint,
+                         seller = excluded.seller, buyer = excluded.buyer, price = excluded.price,
+                         slot = excluded.slot, block_time = excluded.block_time, commitment = excluded.commitment
+                     WHERE excluded.commitment = 'finalized' OR sales.commitment = 'confirmed'",
+                )
+                .bind(&transaction.signature)
+                .bind(*instruction_index as i64)
+                .bind(listing.to_string())
+                .bind(nft_mint.to_string())
+                .bind(seller.to_string())
+                .bind(buyer.to_string())
+                .bind(*price as i64)
+                .bind(slot)
+                .bind(transaction.block_time)
+                .bind(commitment)
+                .execute(&mut *db)
+                .await
+                .map_err(db_error)?;
+
+                sqlx::query(
+                    "INSERT INTO listings (listing_address, mint, seller, price, status, closed_signature, closed_slot, closed_at, closed_commitment)
+                     VALUES ($1, $2, $3, $4, 'sold', $5, $6, $7, $8)
+                     ON CONFLICT (listing_address) DO UPDATE SET
+                         status = 'sold', closed_signature = excluded.closed_signature,
+                         closed_slot = excluded.closed_slot, closed_at = excluded.closed_at,
+                         closed_commitment = excluded.closed_commitment
+                     WHERE excluded.closed_commitment = 'finalized' OR listings.closed_commitment IS NULL
+                         OR listings.closed_commitment = 'confirmed'",
+                )
+                .bind(listing.to_string())
+                .bind(nft_mint.to_string())
+                .bind(seller.to_string())
+                .bind(*price as i64)
+                .bind(&transaction.signature)
+                .bind(slot)
+                .bind(transaction.block_time)
+                .bind(commitment)
+                .execute(&mut *db)
+                .await
+                .map_err(db_error)?;
+            }
+        }
+    }
+
+    for nft in nfts {
+        sqlx::query(
+            "INSERT INTO nfts (mint, name, symbol, uri, collection, first_seen_slot, first_seen_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (mint) DO UPDATE SET
+                 name = COALESCE(excluded.name, nfts.name),
+                 symbol = COALESCE(excluded.symbol, nfts.symbol),
+                 uri = COALESCE(excluded.uri, nfts.uri),
+                 collection = COALESCE(excluded.collection, nfts.collection),
+                 first_seen_at = CASE WHEN excluded.first_seen_slot < nfts.first_seen_slot
+                     THEN excluded.first_seen_at ELSE nfts.first_seen_at END,
+                 first_seen_slot = CASE WHEN excluded.first_seen_slot < nfts.first_seen_slot
+                     THEN excluded.first_seen_slot ELSE nfts.first_seen_slot END",
+        )
+        .bind(&nft.mint)
+        .bind(&nft.name)
+        .bind(&nft.symbol)
+        .bind(&nft.uri)
+        .bind(&nft.collection)
+        .bind(slot)
+        .bind(transaction.block_time)
+        .execute(&mut *db)
+        .await
+        .map_err(db_error)?;
+
+        if let Some(minted) = &nft.minted {
+            sqlx::query(
+                "INSERT INTO mints (mint, signature, slot, block_time, minter, amount, fee, fee_payer)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                 ON CONFLICT (mint) DO UPDATE SET
+                     signature = excluded.signature, slot = excluded.slot, block_time = excluded.block_time,
+                     minter = excluded.minter, amount = excluded.amount, fee = excluded.fee,
+                     fee_payer = excluded.fee_payer",
+            )
+            .bind(&nft.mint)
+            .bind(&minted.signature)
+            .bind(minted.slot as i64)
+            .bind(minted.block_time)
+            .bind(&minted.minter)
+            .bind(minted.amount.map(|amount| amount as i64))
+            .bind(minted.fee.map(|fee| fee as i64))
+            .bind(&minted.fee_payer)
+            .execute(&mut *db)
+            .await
+            .map_err(db_error)?;
+        }
+    }
+
+    if !transaction.events.is_empty() {
+        sqlx::query(
+            "INSERT INTO indexed_transactions (signature, fee, fee_payer) VALUES ($1, $2, $3)
+             ON CONFLICT (signature) DO UPDATE SET fee = excluded.fee, fee_payer = excluded.fee_payer",
+        )
+        .bind(&transaction.signature)
+        .bind(transaction.fee.map(|fee| fee as i64))
+        .bind(transaction.fee_payer.map(|payer| payer.to_string()))
+        .execute(&mut *db)
+        .await
+        .map_err(db_error)?;
+    }
+
+    // Finalizing promotes every row above, so nothing is left to roll back
+    let pending = match transaction.commitment {
+        Commitment::Confirmed => sqlx::query(
+            "INSERT INTO unfinalized_transactions (signature, slot, missed_polls) VALUES ($1, $2, 0)
+             ON CONFLICT (signature) DO UPDATE SET slot = excluded.slot, missed_polls = 0",
+        )
+        .bind(&transaction.signature)
+        .bind(slot),
+        Commitment::Finalized => sqlx::query("DELETE FROM unfinalized_transactions WHERE signature = $1")
+            .bind(&transaction.signature),
+    };
+    pending.execute(&mut *db).await.map_err(db_error)?;
+
+    if let Some(name) = checkpoint {
+        sqlx::query(
+            "INSERT INTO indexer_checkpoints (name, signature, slot, updated_at) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (name) DO UPDATE SET
+                 signature = excluded.signature, slot = excluded.slot, updated_at = excluded.updated_at",
+        )
+        .bind(name)
+        .bind(&transaction.signature)
+        .bind(slot)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(&mut *db)
+        .await
+        .map_err(db_error)?;
+    }
+    Ok(())
+}
+
+// A WITH clause defining `events`: a wallet's events of `kinds` (empty for all)
+// in a block time range, with the wallet bound to $1 and the range to $2 and $3.
+// kind_rank orders the events of one transaction as they happened; integer
+// literals are cast so Postgres reads them as BIGINT.
+fn wallet_events(kinds: &[WalletActivityKind]) -> String {
+    let fee = "LEFT JOIN indexed_transactions ON indexed_transactions.signature";
+    let branches: Vec<String> = [
+        WalletActivityKind::Mint,
+        WalletActivityKind::List,
+        WalletActivityKind::Buy,
+        WalletActivityKind::Sell,
+    ]
+    .into_iter()
+    .filter(|kind| kinds.is_empty() || kinds.contains(kind))
+    .map(|kind| match kind {
+        WalletActivityKind::Mint => "SELECT 'mint' AS event_type, CAST(0 AS BIGINT) AS kind_rank, mint, signature, slot,
+                block_time, amount, CAST(NULL AS TEXT) AS counterparty, 'finalized' AS commitment, fee, fee_payer
+             FROM mints WHERE minter = $1 AND COALESCE(block_time, 0) >= $2 AND COALESCE(block_time, 0) < $3"
+            .to_string(),
+        WalletActivityKind::List => format!(
+            "SELECT 'list' AS event_type, CAST(1 AS BIGINT) AS kind_rank, mint, listed_signature AS signature,
+                COALESCE(listed_slot, 0) AS slot, listed_at AS block_time, price AS amount,
+                CAST(NULL AS TEXT) AS counterparty, listed_commitment AS commitment, fee, fee_payer
+             FROM listings {} = listings.listed_signature
+             WHERE listed_signature IS NOT NULL AND seller = $1
+                 AND COALESCE(listed_at, 0) >= $2 AND COALESCE(listed_at, 0) < $3",
+            fee,
+        ),
+        // A wallet buying its own listing both buys and sells
+        WalletActivityKind::Buy | WalletActivityKind::Sell => {
+            let (rank, wallet, counterparty) = match kind {
+                WalletActivityKind::Buy => (2, "buyer", "seller"),
+                _ => (3, "seller", "buyer"),
+            };
+            format!(
+                "SELECT '{}' AS event_type, CAST({} AS BIGINT) AS kind_rank, mint, sales.signature AS signature, slot,
+                    block_time, price AS amount, {} AS counterparty, commitment, fee, fee_payer
+                 FROM sales {} = sales.signature
+                 WHERE {} = $1 AND COALESCE(block_time, 0) >= $2 AND COALESCE(block_time, 0) < $3",
+                kind.as_str(), rank, counterparty, fee, wallet,
+            )
+        }
+    })
+    .collect();
+    format!("WITH events AS ({})", branches.join(" UNION ALL "))
+}
+
+// Binds the collection of a query that filters on one
+fn bind_collection<'q>(
+    query: Query<'q, Any, AnyArguments<'q>>,
+    collection: Option<&'q str>,
+) -> Query<'q, Any, AnyArguments<'q>> {
+    match collection {
+        Some(collection) => query.bind(collection),
+        None => query,
+    }
+}
+
+fn tracked_transaction(row: &sqlx::any::AnyRow) -> TrackedTransaction {
+    TrackedTransaction {
+        action: row.get("action"),
+        status: TransactionStatus::parse(row.get("status")),
+        error: row.get("error"),
+        submitted_at: row.get("submitted_at"),
+        updated_at: row.get("updated_at"),
+        last_valid_block_height: row.get::<i64, _>("last_valid_block_height") as u64,
+        rebroadcasts: row.get::<i64, _>("rebroadcasts") as u32,
+        replaced_by: row.get("replaced_by"),
+    }
+}
+
+fn commitment(value: Option<String>) -> Commitment {
+    match value.as_deref() {
+        Some("finalized") => Commitment::Finalized,
+        _ => Commitment::Confirmed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::pubkey::Pubkey;
+
+    const COLLECTION: &str = "collection";
+
+    // One NFT of COLLECTION with its listing, seller and buyer
+    struct Nft {
+        mint: Pubkey,
+        listing: Pubkey,
+        seller: Pubkey,
+        buyer: Pubkey,
+    }
+
+    impl Nft {
+        fn new() -> Self {
+            Nft {
+                mint: Pubkey::new_unique(),
+                listing: Pubkey::new_unique(),
+                seller: Pubkey::new_unique(),
+                buyer: Pubkey::new_unique(),
+            }
+        }
+
+        fn listed(&self, price: u64) -> MarketplaceEvent {
+            MarketplaceEvent::Listed { listing: self.listing, nft_mint: self.mint, seller: self.seller, price }
+        }
+
+        fn sold(&self, price: u64) -> MarketplaceEvent {
+            MarketplaceEvent::Sold { listing: self.listing, nft_mint: self.mint, seller: self.seller, buyer: self.buyer, price }
+        }
+
+        fn record(&self, collection: &str) -> NftRecord {
+            NftRecord {
+                mint: self.mint.to_string(),
+                name: Some("NFT".to_string()),
+                symbol: None,
+                uri: None,
+                collection: Some(collection.to_string()),
+                minted: None,
+            }
+        }
+    }
+
+    // A transaction with one event, at slot and block time `time`
+    fn transaction(signature: &str, time: i64, commitment: Commitment, event: MarketplaceEvent) -> IndexedTransaction {
+        IndexedTransaction {
+            signature: signature.to_string(),
+            slot: time as u64,
+            block_time: Some(time),
+            commitment,
+            fee: Some(5000),
+            fee_payer: None,
+            events: vec![(0, event)],
+        }
+    }
+
+    async fn index(db: &IndexDb, nft: &Nft, signature: &str, time: i64, commitment: Commitment, event: MarketplaceEvent) {
+        db.apply(&transaction(signature, time, commitment, event), &[nft.record(COLLECTION)], Some("live")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn applying_a_transaction_twice_changes_nothing() {
+        let db = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let nft = Nft::new();
+        for _ in 0..2 {
+            index(&db, &nft, "list", 100, Commitment::Finalized, nft.listed(1000)).await;
+            index(&db, &nft, "buy", 200, Commitment::Finalized, nft.sold(1000)).await;
+        }
+
+        assert_eq!(db.mint_activity(&nft.mint.to_string()).await.unwrap().len(), 2);
+        let stats = db.stats(None, 300).await.unwrap();
+        assert_eq!((stats.total_nfts, stats.total_listed, stats.total_sold, stats.total_volume), (1, 0, 1, 1000));
+        assert_eq!(db.checkpoint("live").await.unwrap().as_deref(), Some("buy"));
+    }
+
+    #[tokio::test]
+    async fn a_sale_indexed_before_its_listing_leaves_the_listing_sold() {
+        let db = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let nft = Nft::new();
+        index(&db, &nft, "buy", 200, Commitment::Finalized, nft.sold(1000)).await;
+        index(&db, &nft, "list", 100, Commitment::Finalized, nft.listed(1000)).await;
+
+        let stats = db.stats(None, 300).await.unwrap();
+        assert_eq!((stats.total_listed, stats.total_sold), (0, 1));
+        let listings = db.collection_listings(COLLECTION, 0, 300).await.unwrap();
+        assert_eq!(listings.len(), 1);
+        assert_eq!((listings[0].listed_at, listings[0].closed_at), (100, Some(200)));
+    }
+
+    #[tokio::test]
+    async fn confirmed_events_are_promoted_when_they_finalize() {
+        let db = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let nft = Nft::new();
+        index(&db, &nft, "list", 100, Commitment::Confirmed, nft.listed(1000)).await;
+        assert_eq!(db.unfinalized().await.unwrap(), vec![("list".to_string(), 100)]);
+        assert_eq!(db.stats(None, 300).await.unwrap().total_listed, 0);
+
+        index(&db, &nft, "list", 100, Commitment::Finalized, nft.listed(1000)).await;
+        assert!(db.unfinalized().await.unwrap().is_empty());
+        assert_eq!(db.stats(None, 300).await.unwrap().total_listed, 1);
+
+        // A late confirmed copy doesn't demote it
+        index(&db, &nft, "list", 100, Commitment::Confirmed, nft.listed(1000)).await;
+        let activity = db.mint_activity(&nft.mint.to_string()).await.unwrap();
+        assert!(activity[0].commitment == Commitment::Finalized);
+    }
+
+    #[tokio::test]
+    async fn rolling_back_undoes_only_the_dropped_transaction() {
+        let db = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let nft = Nft::new();
+        let dropped = Nft::new();
+        index(&db, &nft, "list", 100, Commitment::Finalized, nft.listed(1000)).await;
+        index(&db, &nft, "buy", 200, Commitment::Confirmed, nft.sold(1000)).await;
+        index(&db, &dropped, "dropped-list", 150, Commitment::Confirmed, dropped.listed(500)).await;
+
+        db.roll_back("buy").await.unwrap();
+        db.roll_back("dropped-list").await.unwrap();
+
+        let activity = db.mint_activity(&nft.mint.to_string()).await.unwrap();
+        assert_eq!(activity.len(), 1);
+        assert!(activity[0].kind == ActivityKind::List);
+        assert!(db.mint_activity(&dropped.mint.to_string()).await.unwrap().is_empty());
+        assert!(db.unfinalized().await.unwrap().is_empty());
+        let listings = db.collection_listings(COLLECTION, 0, 300).await.unwrap();
+        assert_eq!(listings.len(), 1);
+        assert_eq!(listings[0].closed_at, None);
+    }
+
+    #[tokio::test]
+    async fn replacing_a_mint_rebuilds_only_its_rows() {
+        let db = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let (nft, other) = (Nft::new(), Nft::new());
+        index(&db, &nft, "list", 100, Commitment::Finalized, nft.listed(1000)).await;
+        index(&db, &nft, "buy", 200, Commitment::Confirmed, nft.sold(1000)).await;
+        index(&db, &other, "other-buy", 150, Commitment::Confirmed, other.sold(500)).await;
+
+        // The decoder now prices the listing differently, and the sale hasn't finalized
+        let relisted = transaction("list", 100, Commitment::Finalized, nft.listed(1200));
+        db.replace_mint(&nft.mint.to_string(), &[(relisted, vec![nft.record(COLLECTION)])]).await.unwrap();
+
+        let activity = db.mint_activity(&nft.mint.to_string()).await.unwrap();
+        assert_eq!(activity.len(), 1);
+        assert_eq!(activity[0].price, Some(1200));
+        assert_eq!(db.mint_activity(&other.mint.to_string()).await.unwrap().len(), 1);
+        assert_eq!(db.unfinalized().await.unwrap(), vec![("other-buy".to_string(), 150)]);
+    }
+
+    #[tokio::test]
+    async fn wallet_activity_is_paged_newest_first() {
+        let db = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let nft = Nft::new();
+        let mut record = nft.record(COLLECTION);
+        record.minted = Some(MintRecord {
+            signature: "mint".to_string(),
+            slot: 50,
+            block_time: Some(50),
+            minter: Some(nft.seller.to_string()),
+            amount: Some(20_000),
+            fee: Some(5000),
+            fee_payer: Some(nft.seller.to_string()),
+        });
+        db.apply(&transaction("list", 100, Commitment::Finalized, nft.listed(1000)), &[record], None).await.unwrap();
+        index(&db, &nft, "buy", 200, Commitment::Finalized, nft.sold(1000)).await;
+        // The seller buys back a listing of its own, paying the fee once
+        let own = Nft { seller: nft.seller, buyer: nft.seller, ..Nft::new() };
+        let mut own_buy = transaction("own-buy", 300, Commitment::Confirmed, own.sold(2000));
+        own_buy.fee_payer = Some(nft.seller);
+        db.apply(&own_buy, &[own.record(COLLECTION)], None).await.unwrap();
+
+        let seller = nft.seller.to_string();
+        let events = |events: Vec<WalletActivityEvent>| -> Vec<(String, String, u64)> {
+            events.into_iter().map(|event| (event.event_type.as_str().to_string(), event.signature, event.fee)).collect()
+        };
+        let event = |kind: &str, signature: &str, fee: u64| (kind.to_string(), signature.to_string(), fee);
+        let everything = db.wallet_activity(&seller, &[], None, None, None, 0).await.unwrap();
+        assert_eq!(everything[0].counterparty, Some(seller.clone()));
+        assert_eq!(everything[0].commitment, "confirmed");
+        assert_eq!(everything[4].amount, Some(20_000));
+        assert_eq!(events(everything), vec![
+            event("buy", "own-buy", 5000),
+            event("sell", "own-buy", 0),
+            event("sell", "buy", 0),
+            event("list", "list", 0),
+            event("mint", "mint", 5000),
+        ]);
+        let page = db.wallet_activity(&seller, &[], None, None, Some(2), 1).await.unwrap();
+        assert_eq!(events(page), vec![event("sell", "own-buy", 0), event("sell", "buy", 0)]);
+        let range = db.wallet_activity(&seller, &[], Some(60), Some(200), None, 0).await.unwrap();
+        assert_eq!(events(range), vec![event("list", "list", 0)]);
+        let buys = db.wallet_activity(&seller, &[WalletActivityKind::Buy], None, None, None, 0).await.unwrap();
+        assert_eq!(events(buys), vec![event("buy", "own-buy", 5000)]);
+        assert_eq!(db.wallet_activity(&seller, &[], None, None, Some(0), 0).await.unwrap().len(), 0);
+
+        assert_eq!(db.mint_transaction(&nft.mint.to_string()).await.unwrap().unwrap().signature, "mint");
+        assert_eq!(db.wallet_activity_count(&seller, &[], None, None).await.unwrap(), 5);
+        assert_eq!(db.wallet_activity_count(&seller, &[], Some(100), None).await.unwrap(), 4);
+        assert_eq!(db.wallet_activity_count(&seller, &[WalletActivityKind::Sell], None, None).await.unwrap(), 2);
+        assert_eq!(db.wallet_activity_count(&nft.buyer.to_string(), &[], None, None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn stats_cover_finalized_activity_of_the_collection() {
+        let db = IndexDb::connect("sqlite::memory:").await.unwrap();
+        const DAY: i64 = 24 * 60 * 60;
+        let now = 40 * DAY;
+
+        // In the collection: one sold 2 days ago, one sold 10 days ago, one listed
+        let (sold, older, listed) = (Nft::new(), Nft::new(), Nft::new());
+        index(&db, &sold, "list-1", now - 3 * DAY, Commitment::Finalized, sold.listed(1000)).await;
+        index(&db, &sold, "buy-1", now - 2 * DAY, Commitment::Finalized, sold.sold(1000)).await;
+        index(&db, &older, "list-2", now - 11 * DAY, Commitment::Finalized, older.listed(3000)).await;
+        index(&db, &older, "buy-2", now - 10 * DAY, Commitment::Finalized, older.sold(3000)).await;
+        index(&db, &listed, "list-3", now - DAY, Commitment::Finalized, listed.listed(700)).await;
+        // A confirmed sale counts neither as a sale nor as closing the listing
+        index(&db, &listed, "buy-3", now - 60, Commitment::Confirmed, listed.sold(700)).await;
+
+        // Outside it: a recent sale
+        let other = Nft::new();
+        let transaction = transaction("buy-4", now - 60, Commitment::Finalized, other.sold(9000));
+        db.apply(&transaction, &[other.record("other")], None).await.unwrap();
+
+        let stats = db.stats(Some(COLLECTION), now).await.unwrap();
+        assert_eq!((stats.total_nfts, stats.total_listed, stats.total_sold), (3, 1, 2));
+        assert_eq!(stats.floor_price, Some(700));
+        assert_eq!((stats.total_volume, stats.volume_24h, stats.volume_7d, stats.volume_30d), (4000, 0, 1000, 4000));
+        assert_eq!(stats.unique_traders, 4);
+
+        let stats = db.stats(None, now).await.unwrap();
+        assert_eq!((stats.total_nfts, stats.total_sold, stats.volume_24h, stats.unique_traders), (4, 3, 9000, 6));
+    }
+
+    #[tokio::test]
+    async fn collection_listings_are_the_ones_open_in_the_range() {
+        let db = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let (closed, open, pending) = (Nft::new(), Nft::new(), Nft::new());
+        index(&db, &closed, "list-1", 100, Commitment::Finalized, closed.listed(1000)).await;
+        index(&db, &closed, "buy-1", 200, Commitment::Finalized, closed.sold(1000)).await;
+        index(&db, &open, "list-2", 300, Commitment::Finalized, open.listed(2000)).await;
+        index(&db, &pending, "list-3", 150, Commitment::Finalized, pending.listed(3000)).await;
+        index(&db, &pending, "buy-3", 250, Commitment::Confirmed, pending.sold(3000)).await;
+
+        let prices = |listings: Vec<ListingSpan>| {
+            let mut prices: Vec<u64> = listings.iter().map(|listing| listing.price).collect();
+            prices.sort();
+            prices
+        };
+        assert_eq!(prices(db.collection_listings(COLLECTION, 0, 150).await.unwrap()), vec![1000]);
+        assert_eq!(prices(db.collection_listings(COLLECTION, 200, 400).await.unwrap()), vec![1000, 2000, 3000]);
+        assert_eq!(prices(db.collection_listings(COLLECTION, 201, 400).await.unwrap()), vec![2000, 3000]);
+        assert!(db.collection_listings("other", 0, 400).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,810 @@
+// Marketplace indexer: follows the nft_marketplace program by polling
+// getSignaturesForAddress, decodes list and buy instructions (top-level and CPI)
+// from each finalized transaction and writes them to the index database. The
+// last indexed signature is checkpointed with every transaction, so a restart
+// resumes where the previous run stopped.
+//
+// At `confirmed` commitment (INDEXER_COMMITMENT, the default) transactions that
+// have not finalized yet are indexed too, marked confirmed. Each poll re-indexes
+// the ones that have since finalized and rolls back the ones that were on a
+// dropped fork: the cluster has finalized past their slot and consecutive polls
+// found no status for them.
+//
+// The first time an NFT is seen, the first transaction of its mint account is
+// recorded too, as its mint event (who received the token and what they paid).
+//
+// The program has no cancel instruction; listings only end by being bought.
+use anchor_client::anchor_lang::AccountDeserialize;
+use serde::{Deserialize, Serialize};
+use solana_client::{
+    rpc_client::GetConfirmedSignaturesForAddress2Config, rpc_config::RpcTransactionConfig,
+    rpc_response::RpcConfirmedTransactionStatusWithSignature,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, EncodedConfirmedTransactionWithStatusMeta,
+    TransactionConfirmationStatus, UiInstruction, UiTransactionEncoding,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::cache;
+use crate::chain::ChainClient;
+use crate::index_db::{IndexDb, MintRecord, NftRecord};
+use crate::marketplace_program::{self, Listing, MarketplaceInstruction};
+use crate::token_metadata::{self, TokenMetadata};
+
+// Checkpoint of the live indexer
+pub const LIVE_CHECKPOINT: &str = "live";
+
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 5;
+
+// getSignaturesForAddress returns at most this many signatures per call
+const SIGNATURES_PAGE_LIMIT: usize = 1000;
+
+// getSignatureStatuses accepts at most this many signatures per call
+const MAX_SIGNATURE_STATUSES: usize = 256;
+
+// Polls in a row that must find no status before a transaction is rolled back,
+// so one lagging or failed-over node can't undo it
+const MISSED_POLLS_BEFORE_ROLLBACK: u32 = 2;
+
+// Commitment an event was indexed at
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Commitment {
+    Confirmed,
+    Finalized,
+}
+
+impl Commitment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Commitment::Confirmed => "confirmed",
+            Commitment::Finalized => "finalized",
+        }
+    }
+
+    fn config(&self) -> CommitmentConfig {
+        match self {
+            Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+
+    fn of(status: &RpcConfirmedTransactionStatusWithSignature) -> Self {
+        match status.confirmation_status {
+            Some(TransactionConfirmationStatus::Finalized) => Commitment::Finalized,
+            _ => Commitment::Confirmed,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum MarketplaceEvent {
+    Listed { listing: Pubkey, nft_mint: Pubkey, seller: Pubkey, price: u64 },
+    Sold { listing: Pubkey, nft_mint: Pubkey, seller: Pubkey, buyer: Pubkey, price: u64 },
+}
+
+impl MarketplaceEvent {
+    pub fn nft_mint(&self) -> Pubkey {
+        match self {
+            MarketplaceEvent::Listed { nft_mint, .. } | MarketplaceEvent::Sold { nft_mint, .. } => *nft_mint,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IndexedTransaction {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub commitment: Commitment,
+    pub fee: Option<u64>,
+    pub fee_payer: Option<Pubkey>,
+    // Keyed by the instruction's position among all instructions, CPIs included
+    pub events: Vec<(u32, MarketplaceEvent)>,
+}
+
+pub struct Indexer {
+    client: Arc<dyn ChainClient>,
+    db: IndexDb,
+    program_id: Pubkey,
+    poll_interval: Duration,
+    commitment: Commitment,
+}
+
+impl Indexer {
+    /// Indexer for the configured marketplace program, polling every
+    /// INDEXER_POLL_INTERVAL_SECS seconds (default 5) at INDEXER_COMMITMENT
+    /// (`confirmed`, the default, or `finalized`)
+    pub fn new(client: Arc<dyn ChainClient>, db: IndexDb) -> Self {
+        let poll_interval = std::env::var("INDEXER_POLL_INTERVAL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(DEFAULT_POLL_INTERVAL_SECS);
+        let commitment = match std::env::var("INDEXER_COMMITMENT").as_deref() {
+            Ok("finalized") => Commitment::Finalized,
+            _ => Commitment::Confirmed,
+        };
+        Indexer {
+            client,
+            db,
+            program_id: marketplace_program::program_id(),
+            poll_interval: Duration::from_secs(poll_interval.max(1)),
+            commitment,
+        }
+    }
+
+    pub async fn run(self) {
+        println!("Indexing marketplace program {}", self.program_id);
+        loop {
+            match self.sync().await {
+                Ok(0) => {}
+                Ok(count) => println!("Indexed {} marketplace transactions", count),
+                Err(e) => println!("Indexer error: {}", e),
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Index every finalized program transaction after the live checkpoint,
+    /// oldest first, then at `confirmed` settle earlier unfinalized transactions
+    /// and index the newer confirmed ones. Returns how many were indexed.
+    pub async fn sync(&self) -> Result<usize, String> {
+        let client = self.client.as_ref();
+        let until = self.live_checkpoint().await?;
+        let finalized = signatures_between(client, &self.program_id, None, until).await?;
+        for status in &finalized {
+            index_signature(client, &self.db, &self.program_id, status, Commitment::Finalized, Some(LIVE_CHECKPOINT)).await?;
+        }
+        if self.commitment == Commitment::Finalized {
+            return Ok(finalized.len());
+        }
+
+        self.settle_unfinalized().await?;
+
+        // Newer than everything finalized; already-indexed ones are skipped unless they moved slot
+        let until = self.live_checkpoint().await?;
+        let indexed: HashSet<(String, u64)> = self.db.unfinalized().await?.into_iter().collect();
+        let confirmed = collect_signatures(client, &self.program_id, None, until, None, Commitment::Confirmed).await?;
+        let mut count = finalized.len();
+        for status in confirmed.iter().filter(|status| !indexed.contains(&(status.signature.clone(), status.slot))) {
+            index_signature(client, &self.db, &self.program_id, status, Commitment::of(status), None).await?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    async fn live_checkpoint(&self) -> Result<Option<Signature>, String> {
+        self.db.checkpoint(LIVE_CHECKPOINT).await?
+            .map(|signature| Signature::from_str(&signature)
+                .map_err(|e| format!("Invalid checkpoint signature: {}", e)))
+            .transpose()
+    }
+
+    // Promote unfinalized transactions that have finalized by re-indexing them, and
+    // roll back the ones no node knows any more. A transaction that reappears on
+    // another fork is picked up again by the confirmed pass.
+    async fn settle_unfinalized(&self) -> Result<(), String> {
+        let unfinalized = self.db.unfinalized().await?;
+        if unfinalized.is_empty() {
+            return Ok(());
+        }
+        // Until the cluster finalizes past its slot, a missing transaction may still land
+        let finalized_slot = self.client.get_slot_with_commitment(CommitmentConfig::finalized()).await
+            .map_err(|e| format!("Failed to fetch finalized slot: {}", e))?;
+        for chunk in unfinalized.chunks(MAX_SIGNATURE_STATUSES) {
+            let signatures = chunk.iter()
+                .map(|(signature, _)| Signature::from_str(signature)
+                    .map_err(|e| format!("Invalid signature {}: {}", signature, e)))
+                .collect::<Result<Vec<_>, _>>()?;
+            // With history, so transactions older than the status cache are still found
+            let statuses = self.client.get_signature_statuses_with_history(&signatures).await
+                .map_err(|e| format!("Failed to fetch signature statuses: {}", e))?
+                .value;
+            for ((signature, slot), status) in chunk.iter().zip(statuses) {
+                match status {
+                    None => {
+                        let missed_polls = self.db.record_missed_poll(signature).await?;
+                        if missed_polls >= MISSED_POLLS_BEFORE_ROLLBACK && *slot <= finalized_slot {
+                            self.db.roll_back(signature).await?;
+                            println!("Rolled back {} from dropped slot {}", signature, slot);
+                        }
+                    }
+                    Some(status) if status.satisfies_commitment(CommitmentConfig::finalized()) => {
+                        let status = RpcConfirmedTransactionStatusWithSignature {
+                            signature: signature.clone(),
+                            slot: status.slot,
+                            err: status.err,
+                            memo: None,
+                            block_time: None,
+                            confirmation_status: Some(TransactionConfirmationStatus::Finalized),
+                        };
+                        index_signature(self.client.as_ref(), &self.db, &self.program_id, &status, Commitment::Finalized, None).await?;
+                    }
+                    Some(_) => self.db.clear_missed_polls(signature).await?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Signatures of finalized transactions mentioning `address`, newer than `until`
+/// and older than `before` (both exclusive), oldest first
+pub async fn signatures_between(
+    client: &dyn ChainClient,
+    address: &Pubkey,
+    before: Option<Signature>,
+    until: Option<Signature>,
+) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, String> {
+    collect_signatures(client, address, before, until, None, Commitment::Finalized).await
+}
+
+/// Signatures of finalized transactions mentioning `address` at or after
+/// `from_slot`, oldest first
+pub async fn signatures_since_slot(
+    client: &dyn ChainClient,
+    address: &Pubkey,
+    from_slot: u64,
+) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, String> {
+    collect_signatures(client, address, None, None, Some(from_slot), Commitment::Finalized).await
+}
+
+// Pages back from `before` until `until`, the oldest signature or `min_slot`
+async fn collect_signatures(
+    client: &dyn ChainClient,
+    address: &Pubkey,
+    mut before: Option<Signature>,
+    until: Option<Signature>,
+    min_slot: Option<u64>,
+    commitment: Commitment,
+) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, String> {
+    let min_slot = min_slot.unwrap_or(0);
+    let mut signatures = Vec::new();
+    loop {
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before,
+            until,
+            limit: Some(SIGNATURES_PAGE_LIMIT),
+            commitment: Some(commitment.config()),
+        };
+        let page = client.get_signatures_for_address_with_config(address, config).await
+            .map_err(|e| format!("Failed to fetch signatures for {}: {}", address, e))?;
+        let full_page = page.len() == SIGNATURES_PAGE_LIMIT;
+        before = page.last().and_then(|status| Signature::from_str(&status.signature).ok());
+        let reached_min_slot = page.last().is_some_and(|status| status.slot < min_slot);
+        signatures.extend(page.into_iter().filter(|status| status.slot >= min_slot));
+        if !full_page || before.is_none() || reached_min_slot {
+            break;
+        }
+    }
+    signatures.reverse();
+    Ok(signatures)
+}
+
+/// Slot a finalized transaction landed in
+pub async fn transaction_slot(client: &dyn ChainClient, signature: &Signature) -> Result<u64, String> {
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(CommitmentConfig::finalized()),
+        max_supported_transaction_version: Some(0),
+    };
+    let confirmed = client.get_transaction_with_config(signature, config).await
+        .map_err(|e| format!("Failed to fetch transaction {}: {}", signature, e))?;
+    Ok(confirmed.slot)
+}
+
+/// Re-index every program transaction from `from_slot` on, oldest first. No
+/// checkpoint moves and every write is idempotent, so this can run while the
+/// live indexer is running.
+pub async fn backfill(client: &dyn ChainClient, db: &IndexDb, from_slot: u64) -> Result<usize, String> {
+    let program_id = marketplace_program::program_id();
+    let signatures = signatures_since_slot(client, &program_id, from_slot).await?;
+    println!("Backfilling {} marketplace transactions from slot {}", signatures.len(), from_slot);
+    for (count, status) in signatures.iter().enumerate() {
+        index_signature(client, db, &program_id, status, Commitment::Finalized, None).await?;
+        if (count + 1) % SIGNATURES_PAGE_LIMIT == 0 {
+            println!("Backfilled {} of {} (slot {})", count + 1, signatures.len(), status.slot);
+        }
+    }
+    Ok(signatures.len())
+}
+
+/// Rebuild a mint's listings, sales and NFT row from the transactions of its
+/// listing account. Everything is fetched and decoded before the index changes,
+/// and the old rows are swapped for the new ones in one database transaction.
+pub async fn reindex_mint(client: &dyn ChainClient, db: &IndexDb, mint: &Pubkey) -> Result<usize, String> {
+    let program_id = marketplace_program::program_id();
+    let (listing, _) = marketplace_program::find_listing_address(mint);
+    let signatures = signatures_between(client, &listing, None, None).await?;
+    let mut decoded = Vec::with_capacity(signatures.len());
+    // Mint records are decoded again too, once each
+    let mut recorded = HashSet::new();
+    for status in &signatures {
+        let transaction = decode_transaction(client, Some(db), &program_id, status, Commitment::Finalized).await?;
+        let nfts = nft_records(client, &transaction.events, &mut recorded).await?;
+        decoded.push((transaction, nfts));
+    }
+    db.replace_mint(&mint.to_string(), &decoded).await?;
+    Ok(signatures.len())
+}
+
+/// Fetch, decode and store one transaction at `commitment`. Failed transactions
+/// store nothing but still move `checkpoint` past them.
+pub async fn index_signature(
+    client: &dyn ChainClient,
+    db: &IndexDb,
+    program_id: &Pubkey,
+    status: &RpcConfirmedTransactionStatusWithSignature,
+    commitment: Commitment,
+    checkpoint: Option<&str>,
+) -> Result<(), String> {
+    let transaction = decode_transaction(client, Some(db), program_id, status, commitment).await?;
+    let mints: Vec<String> = transaction.events.iter().map(|(_, event)| event.nft_mint().to_string()).collect();
+    let mut recorded = db.recorded_mints(&mints).await?;
+    let nfts = nft_records(client, &transaction.events, &mut recorded).await?;
+    db.apply(&transaction, &nfts, checkpoint).await
+}
+
+/// Fetch one transaction at `commitment` and decode its marketplace events;
+/// failed transactions have none. `index` prices buys of listings whose account
+/// is gone; a buy with no known price is left out.
+pub async fn decode_transaction(
+    client: &dyn ChainClient,
+    index: Option<&IndexDb>,
+    program_id: &Pubkey,
+    status: &RpcConfirmedTransactionStatusWithSignature,
+    commitment: Commitment,
+) -> Result<IndexedTransaction, String> {
+    let mut transaction = IndexedTransaction {
+        signature: status.signature.clone(),
+        slot: status.slot,
+        block_time: status.block_time,
+        commitment,
+        fee: None,
+        fee_payer: None,
+        events: Vec::new(),
+    };
+    if status.err.is_some() {
+        return Ok(transaction);
+    }
+
+    let signature = Signature::from_str(&status.signature)
+        .map_err(|e| format!("Invalid signature {}: {}", status.signature, e))?;
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(commitment.config()),
+        max_supported_transaction_version: Some(0),
+    };
+    let confirmed = client.get_transaction_with_config(&signature, config).await
+        .map_err(|e| format!("Failed to fetch transaction {}: {}", signature, e))?;
+    transaction.slot = confirmed.slot;
+    transaction.block_time = confirmed.block_time.or(status.block_time);
+    transaction.fee = confirmed.transaction.meta.as_ref().map(|meta| meta.fee);
+    transaction.fee_payer = confirmed.transaction.transaction.decode()
+        .and_then(|decoded| decoded.message.static_account_keys().first().copied());
+    transaction.events = decode_events(client, index, &confirmed, program_id).await?;
+    Ok(transaction)
+}
+
+// Marketplace events of a transaction, from its top-level and inner instructions
+async fn decode_events(
+    client: &dyn ChainClient,
+    index: Option<&IndexDb>,
+    confirmed: &EncodedConfirmedTransactionWithStatusMeta,
+    program_id: &Pubkey,
+) -> Result<Vec<(u32, MarketplaceEvent)>, String> {
+    let transaction = confirmed.transaction.transaction.decode()
+        .ok_or("Failed to decode transaction")?;
+    let meta = confirmed.transaction.meta.as_ref().ok_or("Transaction has no status meta")?;
+
+    // Static keys, then addresses loaded from lookup tables (writable, then readonly)
+    let mut account_keys = transaction.message.static_account_keys().to_vec();
+    if let OptionSerializer::Some(loaded) = &meta.loaded_addresses {
+        for address in loaded.writable.iter().chain(&loaded.readonly) {
+            account_keys.push(Pubkey::from_str(address).map_err(|e| format!("Invalid loaded address: {}", e))?);
+        }
+    }
+    let inner: HashMap<u8, &Vec<UiInstruction>> = match &meta.inner_instructions {
+        OptionSerializer::Some(inner) => inner.iter().map(|ix| (ix.index, &ix.instructions)).collect(),
+        _ => HashMap::new(),
+    };
+
+    // (program id index, account indexes, data) in execution order
+    let mut instructions: Vec<(u8, Vec<u8>, Vec<u8>)> = Vec::new();
+    for (index, ix) in transaction.message.instructions().iter().enumerate() {
+        instructions.push((ix.program_id_index, ix.accounts.clone(), ix.data.clone()));
+        for inner_ix in inner.get(&(index as u8)).into_iter().flat_map(|ixs| ixs.iter()) {
+            if let UiInstruction::Compiled(compiled) = inner_ix {
+                let data = bs58::decode(&compiled.data).into_vec()
+                    .map_err(|e| format!("Invalid inner instruction data: {}", e))?;
+                instructions.push((compiled.program_id_index, compiled.accounts.clone(), data));
+            }
+        }
+    }
+
+    let mut decoded = Vec::new();
+    for (position, (program_index, accounts, data)) in instructions.into_iter().enumerate() {
+        if account_keys.get(program_index as usize) != Some(program_id) {
+            continue;
+        }
+        let accounts: Vec<Pubkey> = accounts.iter()
+            .filter_map(|index| account_keys.get(*index as usize).copied())
+            .collect();
+        if let Some(instruction) = marketplace_program::decode_instruction(&data, &accounts) {
+            decoded.push((position as u32, instruction));
+        }
+    }
+
+    // buy_nft carries no price; it is the listing's, which a listing can never change.
+    // Listing accounts that no longer exist are priced from their list_nft in the
+    // index, or else from the seller's balance change.
+    let listing_addresses: Vec<Pubkey> = decoded.iter()
+        .filter_map(|(_, instruction)| match instruction {
+            MarketplaceInstruction::BuyNft { listing, .. } => Some(*listing),
+            MarketplaceInstruction::ListNft { .. } => None,
+        })
+        .collect();
+    let mut listing_prices = HashMap::new();
+    if !listing_addresses.is_empty() {
+        let accounts = client.get_multiple_accounts(&listing_addresses).await
+            .map_err(|e| format!("Failed to fetch listings: {}", e))?;
+        for (address, account) in listing_addresses.iter().zip(accounts) {
+            if let Some(listing) = account.and_then(|account| Listing::try_deserialize(&mut account.data.as_slice()).ok()) {
+                listing_prices.insert(*address, listing.price);
+            }
+        }
+        if let Some(index) = index {
+            for address in &listing_addresses {
+                if listing_prices.contains_key(address) {
+                    continue;
+                }
+                if let Some(price) = index.listing_price(&address.to_string()).await? {
+                    listing_prices.insert(*address, price);
+                }
+            }
+        }
+    }
+    let balance_change = |key: &Pubkey| {
+        let index = account_keys.iter().position(|k| k == key)?;
+        let pre = *meta.pre_balances.get(index)?;
+        let post = *meta.post_balances.get(index)?;
+        post.checked_sub(pre)
+    };
+
+    let mut events = Vec::with_capacity(decoded.len());
+    for (position, instruction) in decoded {
+        let event = match instruction {
+            MarketplaceInstruction::ListNft { listing, nft_mint, seller, price } => {
+                // A buy later in the same transaction pays this price
+                listing_prices.insert(listing, price);
+                MarketplaceEvent::Listed { listing, nft_mint, seller, price }
+            }
+            MarketplaceInstruction::BuyNft { listing, nft_mint, seller, buyer } => {
+                let price = listing_prices.get(&listing).copied().or_else(|| balance_change(&seller));
+                let Some(price) = price.filter(|price| *price > 0) else {
+                    // Recording it at 0 would skew floor prices and volumes
+                    println!("Skipping sale of {} with an unknown price", nft_mint);
+                    continue;
+                };
+                MarketplaceEvent::Sold { listing, nft_mint, seller, buyer, price }
+            }
+        };
+        events.push((position, event));
+    }
+    Ok(events)
+}
+
+// Metaplex name, symbol, URI and verified collection of the NFTs the events touch,
+// and how each was minted unless it is in `recorded`, which it is added to
+async fn nft_records(
+    client: &dyn ChainClient,
+    events: &[(u32, MarketplaceEvent)],
+    recorded: &mut HashSet<String>,
+) -> Result<Vec<NftRecord>, String> {
+    let mut mints: Vec<Pubkey> = events.iter().map(|(_, event)| event.nft_mint()).collect();
+    mints.sort();
+    mints.dedup();
+    if mints.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let addresses: Vec<Pubkey> = mints.iter().map(token_metadata::metadata_address).collect();
+    let accounts = client.get_multiple_accounts(&addresses).await
+        .map_err(|e| format!("Failed to fetch token metadata: {}", e))?;
+    let mut records = Vec::with_capacity(mints.len());
+    for (mint, account) in mints.iter().zip(accounts) {
+        let minted = match recorded.insert(mint.to_string()) {
+            true => mint_record(client, mint).await?,
+            false => None,
+        };
+        let metadata = account.as_ref().and_then(TokenMetadata::from_account);
+        records.push(NftRecord {
+            mint: mint.to_string(),
+            name: metadata.as_ref().map(|m| m.name.clone()),
+            symbol: metadata.as_ref().map(|m| m.symbol.clone()),
+            uri: metadata.as_ref().map(|m| m.uri.clone()),
+            collection: metadata.filter(|m| m.collection_verified)
+                .and_then(|m| m.collection)
+                .map(|collection| collection.to_string()),
+            minted,
+        });
+    }
+    Ok(records)
+}
+
+// The mint account's first successful transaction, with the wallet it left the
+// token with and what that wallet paid; None when its history is too long to
+// search (see cache::first_signature)
+async fn mint_record(client: &dyn ChainClient, mint: &Pubkey) -> Result<Option<MintRecord>, String> {
+    let first = cache::first_signature(client, mint, Some(CommitmentConfig::finalized())).await
+        .map_err(|e| format!("Failed to fetch signatures for {}: {}", mint, e))?;
+    let Some(first) = first else {
+        return Ok(None);
+    };
+    let signature = Signature::from_str(&first.signature)
+        .map_err(|e| format!("Invalid signature {}: {}", first.signature, e))?;
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(CommitmentConfig::finalized()),
+        max_supported_transaction_version: Some(0),
+    };
+    let confirmed = client.get_transaction_with_config(&signature, config).await
+        .map_err(|e| format!("Failed to fetch transaction {}: {}", signature, e))?;
+    let meta = confirmed.transaction.meta.as_ref();
+    let account_keys = confirmed.transaction.transaction.decode()
+        .map(|decoded| decoded.message.static_account_keys().to_vec())
+        .unwrap_or_default();
+
+    let mint_address = mint.to_string();
+    let minter = meta
+        .and_then(|meta| match &meta.post_token_balances {
+            OptionSerializer::Some(balances) => balances.iter()
+                .find(|balance| balance.mint == mint_address && balance.ui_token_amount.amount == "1"),
+            _ => None,
+        })
+        .and_then(|balance| match &balance.owner {
+            OptionSerializer::Some(owner) => Pubkey::from_str(owner).ok(),
+            _ => None,
+        });
+    let fee_payer = account_keys.first().copied();
+    let fee = meta.map(|meta| meta.fee);
+    // Rent and platform fees the minter paid, besides the network fee
+    let amount = minter.zip(meta).and_then(|(minter, meta)| {
+        let index = account_keys.iter().position(|key| *key == minter)?;
+        let spent = meta.pre_balances.get(index)?.saturating_sub(*meta.post_balances.get(index)?);
+        let fee = if fee_payer == Some(minter) { meta.fee } else { 0 };
+        Some(spent.saturating_sub(fee))
+    });
+
+    Ok(Some(MintRecord {
+        signature: first.signature,
+        slot: confirmed.slot,
+        block_time: confirmed.block_time.or(first.block_time),
+        minter: minter.map(|minter| minter.to_string()),
+        amount,
+        fee,
+        fee_payer: fee_payer.map(|payer| payer.to_string()),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_chain::MemoryChain;
+    use anchor_client::anchor_lang::{AnchorSerialize, Discriminator};
+    use solana_client::rpc_config::RpcSendTransactionConfig;
+    use solana_sdk::{
+        account::Account,
+        instruction::{AccountMeta, CompiledInstruction, Instruction},
+        message::Message,
+        native_token::LAMPORTS_PER_SOL,
+        signature::{Keypair, Signer},
+        system_instruction,
+        transaction::Transaction,
+    };
+    use solana_transaction_status::{
+        EncodedTransaction, EncodedTransactionWithStatusMeta, InnerInstruction, InnerInstructions,
+        TransactionBinaryEncoding, TransactionStatusMeta, UiTransactionStatusMeta,
+    };
+
+    // A landed transaction as getTransaction returns it
+    fn confirmed(transaction: &Transaction, meta: TransactionStatusMeta) -> EncodedConfirmedTransactionWithStatusMeta {
+        use base64::Engine;
+        let serialized = bincode::serialize(transaction).unwrap();
+        EncodedConfirmedTransactionWithStatusMeta {
+            slot: 1,
+            transaction: EncodedTransactionWithStatusMeta {
+                transaction: EncodedTransaction::Binary(
+                    base64::engine::general_purpose::STANDARD.encode(serialized),
+                    TransactionBinaryEncoding::Base64,
+                ),
+                meta: Some(UiTransactionStatusMeta::from(meta)),
+                version: None,
+            },
+            block_time: Some(1),
+        }
+    }
+
+    fn meta(keys: usize) -> TransactionStatusMeta {
+        TransactionStatusMeta {
+            pre_balances: vec![0; keys],
+            post_balances: vec![0; keys],
+            ..TransactionStatusMeta::default()
+        }
+    }
+
+    // Land a transfer, which moves the in-memory chain on by one slot; returns its signature
+    async fn land_transfer(chain: &MemoryChain, payer: &Keypair) -> Signature {
+        let blockhash = chain.get_latest_blockhash().await.unwrap();
+        let transfer = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1_000_000);
+        let transaction = Transaction::new_signed_with_payer(&[transfer], Some(&payer.pubkey()), &[payer], blockhash);
+        chain.send_transaction_with_config(&transaction, RpcSendTransactionConfig::default()).await.unwrap()
+    }
+
+    fn confirmed_listing(signature: &Signature, slot: u64, nft_mint: Pubkey) -> IndexedTransaction {
+        IndexedTransaction {
+            signature: signature.to_string(),
+            slot,
+            block_time: Some(slot as i64),
+            commitment: Commitment::Confirmed,
+            fee: None,
+            fee_payer: None,
+            events: vec![(0, MarketplaceEvent::Listed {
+                listing: marketplace_program::find_listing_address(&nft_mint).0,
+                nft_mint,
+                seller: Pubkey::new_unique(),
+                price: 1000,
+            })],
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_transactions_are_rolled_back_once_their_slot_finalizes() {
+        let chain = Arc::new(MemoryChain::new());
+        let db = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let indexer = Indexer {
+            client: chain.clone(),
+            db: db.clone(),
+            program_id: marketplace_program::program_id(),
+            poll_interval: Duration::from_secs(1),
+            commitment: Commitment::Confirmed,
+        };
+        let payer = Keypair::new();
+        chain.airdrop(&payer.pubkey(), LAMPORTS_PER_SOL);
+
+        // One that lands at slot 2, and one at slot 3 that no node knows
+        let landed = land_transfer(&chain, &payer).await;
+        db.apply(&confirmed_listing(&landed, 2, Pubkey::new_unique()), &[], None).await.unwrap();
+        let (dropped, dropped_mint) = (Signature::new_unique(), Pubkey::new_unique());
+        db.apply(&confirmed_listing(&dropped, 3, dropped_mint), &[], None).await.unwrap();
+        let unfinalized = || async { db.unfinalized().await.unwrap() };
+
+        // The landed one finalizes; the missing one survives the first miss...
+        indexer.settle_unfinalized().await.unwrap();
+        assert_eq!(unfinalized().await, vec![(dropped.to_string(), 3)]);
+        // ...and the second, since slot 3 isn't finalized yet
+        indexer.settle_unfinalized().await.unwrap();
+        assert_eq!(unfinalized().await.len(), 1);
+        assert_eq!(db.mint_activity(&dropped_mint.to_string()).await.unwrap().len(), 1);
+
+        land_transfer(&chain, &payer).await;
+        indexer.settle_unfinalized().await.unwrap();
+        assert!(unfinalized().await.is_empty());
+        assert!(db.mint_activity(&dropped_mint.to_string()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn buys_are_priced_from_the_listing_or_the_seller_balance() {
+        let chain = MemoryChain::new();
+        let program_id = marketplace_program::program_id();
+        let buyer = Pubkey::new_unique();
+        let listing = Listing { nft_mint: Pubkey::new_unique(), seller: Pubkey::new_unique(), price: 2_000_000, is_active: false };
+        let (listing_address, _) = marketplace_program::find_listing_address(&listing.nft_mint);
+        let message = Message::new(&[marketplace_program::buy_nft(&buyer, &listing_address, &listing)], Some(&buyer));
+        let transaction = Transaction::new_unsigned(message);
+        let keys = &transaction.message.account_keys;
+
+        // The listing account is gone, so the seller's balance change is the price
+        let mut balances = meta(keys.len());
+        let seller_index = keys.iter().position(|key| *key == listing.seller).unwrap();
+        balances.post_balances[seller_index] = 1_500_000;
+        let events = decode_events(&chain, None, &confirmed(&transaction, balances.clone()), &program_id).await.unwrap();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            (0, MarketplaceEvent::Sold { listing: address, nft_mint, seller, buyer: sold_to, price }) => {
+                assert_eq!((*address, *nft_mint, *seller, *sold_to), (listing_address, listing.nft_mint, listing.seller, buyer));
+                assert_eq!(*price, 1_500_000);
+            }
+            _ => panic!("expected a sale"),
+        }
+
+        let mut data = Listing::DISCRIMINATOR.to_vec();
+        listing.serialize(&mut data).unwrap();
+        chain.set_account(&listing_address, Account { lamports: 1, data, owner: program_id, executable: false, rent_epoch: 0 });
+        let events = decode_events(&chain, None, &confirmed(&transaction, balances), &program_id).await.unwrap();
+        assert!(matches!(events[0].1, MarketplaceEvent::Sold { price: 2_000_000, .. }));
+    }
+
+    #[tokio::test]
+    async fn buys_of_closed_listings_are_priced_from_the_index_or_left_out() {
+        let chain = MemoryChain::new();
+        let db = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let program_id = marketplace_program::program_id();
+        let buyer = Pubkey::new_unique();
+        let listing = Listing { nft_mint: Pubkey::new_unique(), seller: Pubkey::new_unique(), price: 2_000_000, is_active: false };
+        let (listing_address, _) = marketplace_program::find_listing_address(&listing.nft_mint);
+        let message = Message::new(&[marketplace_program::buy_nft(&buyer, &listing_address, &listing)], Some(&buyer));
+        let transaction = Transaction::new_unsigned(message);
+        // No listing account and no gain for the seller, who paid the fee in the same transaction
+        let balances = meta(transaction.message.account_keys.len());
+
+        let events = decode_events(&chain, Some(&db), &confirmed(&transaction, balances.clone()), &program_id).await.unwrap();
+        assert!(events.is_empty());
+
+        let listed = MarketplaceEvent::Listed {
+            listing: listing_address,
+            nft_mint: listing.nft_mint,
+            seller: listing.seller,
+            price: listing.price,
+        };
+        let indexed = IndexedTransaction {
+            signature: "list".to_string(),
+            slot: 1,
+            block_time: Some(1),
+            commitment: Commitment::Finalized,
+            fee: None,
+            fee_payer: None,
+            events: vec![(0, listed)],
+        };
+        db.apply(&indexed, &[], None).await.unwrap();
+        let events = decode_events(&chain, Some(&db), &confirmed(&transaction, balances), &program_id).await.unwrap();
+        assert!(matches!(events[..], [(0, MarketplaceEvent::Sold { price: 2_000_000, .. })]));
+    }
+
+    #[tokio::test]
+    async fn listings_made_through_another_program_are_decoded() {
+        let chain = MemoryChain::new();
+        let program_id = marketplace_program::program_id();
+        let seller = Pubkey::new_unique();
+        let nft_mint = Pubkey::new_unique();
+        let list = marketplace_program::list_nft(&seller, &nft_mint, 750);
+
+        // A program that calls list_nft, passing on its accounts
+        let mut accounts = list.accounts.clone();
+        accounts.push(AccountMeta::new_readonly(program_id, false));
+        let outer = Instruction { program_id: Pubkey::new_unique(), accounts, data: vec![] };
+        let transaction = Transaction::new_unsigned(Message::new(&[outer], Some(&seller)));
+        let keys = &transaction.message.account_keys;
+        let index_of = |key: &Pubkey| keys.iter().position(|k| k == key).unwrap() as u8;
+
+        let mut meta = meta(keys.len());
+        meta.inner_instructions = Some(vec![InnerInstructions {
+            index: 0,
+            instructions: vec![InnerInstruction {
+                instruction: CompiledInstruction {
+                    program_id_index: index_of(&program_id),
+                    accounts: list.accounts.iter().map(|account| index_of(&account.pubkey)).collect(),
+                    data: list.data.clone(),
+                },
+                stack_height: Some(2),
+            }],
+        }]);
+
+        let events = decode_events(&chain, None, &confirmed(&transaction, meta), &program_id).await.unwrap();
+        assert_eq!(events.len(), 1);
+        let (listing, _) = marketplace_program::find_listing_address(&nft_mint);
+        match &events[0] {
+            // The outer instruction is position 0
+            (1, MarketplaceEvent::Listed { listing: address, nft_mint: mint, seller: listed_by, price }) => {
+                assert_eq!((*address, *mint, *listed_by, *price), (listing, nft_mint, seller, 750));
+            }
+            _ => panic!("expected a listing"),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use axum::{
+    middleware,
     routing::{get, post},
     Router,
     extract::{State, Path, Query},
@@ -8,7 +9,10 @@ use axum::{
 };
 use std::{net::SocketAddr, sync::Arc, collections::HashMap};
 use tower_http::cors::CorsLayer;
-use utoipa::{OpenApi, ToSchema};
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
 use utoipa_swagger_ui::SwaggerUi;
 
 mod nft;
@@ -17,7 +21,23 @@ mod wallet;
 mod freepik_api;
 mod marketplace;
 mod api;
+mod auth;
+mod transactions;
+mod marketplace_program;
+mod simulation;
+mod compute_budget;
+mod chain;
+mod memory_chain;
+mod rpc_pool;
+mod cache;
+mod token_metadata;
+mod index_db;
+mod indexer;
+mod cli;
+mod activity;
+mod fee_payments;
 
+use chain::ChainClient;
 use freepik_api::{FreepikApiClient, GenerateImageRequest, GenerateImageResponse};
 
 #[derive(OpenApi)]
@@ -41,11 +61,23 @@ use freepik_api::{FreepikApiClient, GenerateImageRequest, GenerateImageResponse}
         search_nfts_handler,
         get_nft_details_handler,
         // New v1 API endpoints
+        api::issue_nonce,
+        api::login,
+        api::logout,
         api::generate_images,
         api::mint_nft,
         api::get_wallet_nfts,
         api::list_nft,
+        api::buy_nft,
+        api::submit_transaction,
+        api::get_transaction_status,
         api::get_listings,
+        api::get_marketplace_stats,
+        api::get_collection_stats,
+        api::get_collection_history,
+        api::get_nft_activity,
+        api::get_wallet_activity,
+        api::export_wallet_activity,
         api::get_fee_estimates,
         api::health_check,
     ),
@@ -57,28 +89,50 @@ use freepik_api::{FreepikApiClient, GenerateImageRequest, GenerateImageResponse}
             nft::MintNftResponse,
             nft::FeeBreakdown,
             nft::FeeEstimateResponse,
+            nft::FeeQuote,
+            nft::ActionFeeEstimate,
             nft::ListNftRequest,
+            nft::ListNftResponse,
             nft::BuyNftRequest,
+            nft::BuyNftResponse,
             collection::CreateCollectionRequest,
             GenerateImageRequest,
             GenerateImageResponse,
             wallet::WalletBalanceRequest,
             wallet::WalletBalanceResponse,
             wallet::WalletNftsRequest,
+            wallet::WalletNft,
             wallet::WalletNftsResponse,
             marketplace::MarketplaceStatsResponse,
+            marketplace::PriceCandle,
+            marketplace::CollectionHistoryResponse,
             marketplace::GetListedNftsResponse,
             marketplace::SearchNftsRequest,
             marketplace::NftDetailsRequest,
+            marketplace::MasterEditionInfo,
             marketplace::NftDetailsResponse,
             // New v1 API schemas
             api::ApiResponse<api::GenerateImageResponse>,
             api::ApiResponse<api::MintNftResponse>,
             api::ApiResponse<api::GetWalletNftsResponse>,
             api::ApiResponse<api::ListNftResponse>,
+            api::ApiResponse<api::BuyNftResponse>,
+            api::ApiResponse<transactions::SubmitTransactionResponse>,
+            api::ApiResponse<transactions::TransactionStatusResponse>,
             api::ApiResponse<api::GetListingsResponse>,
+            api::ApiResponse<marketplace::MarketplaceStatsResponse>,
+            api::ApiResponse<marketplace::CollectionHistoryResponse>,
+            api::CollectionHistoryRequest,
+            api::ApiResponse<api::NftActivityResponse>,
+            api::ApiResponse<api::WalletActivityResponse>,
             api::ApiResponse<api::FeeEstimateResponse>,
             api::ApiResponse<api::HealthResponse>,
+            api::ApiResponse<auth::NonceResponse>,
+            api::ApiResponse<auth::SessionResponse>,
+            api::NonceRequest,
+            auth::NonceResponse,
+            api::LoginRequest,
+            auth::SessionResponse,
             api::GenerateImageRequest,
             api::GenerateImageResponse,
             api::GeneratedImage,
@@ -90,11 +144,29 @@ use freepik_api::{FreepikApiClient, GenerateImageRequest, GenerateImageResponse}
             api::NftInfo,
             api::ListNftRequest,
             api::ListNftResponse,
+            api::BuyNftRequest,
+            api::BuyNftResponse,
+            transactions::PreparedTransaction,
+            transactions::SubmitTransactionRequest,
+            transactions::SubmitTransactionResponse,
+            transactions::TransactionStatus,
+            transactions::TransactionStatusResponse,
+            simulation::SimulationDetails,
             api::GetListingsRequest,
             api::GetListingsResponse,
             api::NftListing,
+            api::NftActivityRequest,
+            api::NftActivityResponse,
+            activity::ActivityEvent,
+            activity::ActivityKind,
+            api::WalletActivityRequest,
+            api::WalletActivityResponse,
+            api::ExportWalletActivityRequest,
+            activity::WalletActivityEvent,
+            activity::WalletActivityKind,
             api::FeeEstimateResponse,
             api::HealthResponse,
+            rpc_pool::RpcEndpointHealth,
             api::ApiError,
         )
     ),
@@ -105,28 +177,114 @@ use freepik_api::{FreepikApiClient, GenerateImageRequest, GenerateImageResponse}
         (name = "image", description = "Image generation operations"),
         (name = "images", description = "AI image generation operations"),
         (name = "utilities", description = "Utility endpoints"),
-    )
+        (name = "auth", description = "Wallet authentication"),
+        (name = "transactions", description = "Submitting wallet-signed transactions"),
+    ),
+    modifiers(&SecurityAddon)
 )]
 struct ApiDoc;
 
+// Registers the bearer token scheme issued by /api/v1/auth/login
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer_auth",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
+
 #[derive(Clone)]
 struct AppState {
-    solana_client: Arc<solana_client::rpc_client::RpcClient>,
+    solana_client: Arc<dyn ChainClient>,
     freepik_client: Option<FreepikApiClient>,
     keypair: Arc<solana_sdk::signature::Keypair>,
     url_mappings: Arc<tokio::sync::RwLock<HashMap<String, String>>>,
+    fee_payments: fee_payments::FeePaymentLedger,
+    auth: auth::AuthState,
+    transactions: transactions::TransactionTracker,
+    cache: cache::ChainCache,
+    index: Option<index_db::IndexDb>,
     api_state: api::ApiState,
 }
 
+impl AppState {
+    fn new(
+        solana_client: Arc<dyn ChainClient>,
+        freepik_client: Option<FreepikApiClient>,
+        keypair: solana_sdk::signature::Keypair,
+        auth: auth::AuthState,
+        cache: cache::ChainCache,
+        index: Option<index_db::IndexDb>,
+    ) -> Self {
+        let keypair = Arc::new(keypair);
+
+        // Image short links, written by mints and read by the image proxy
+        let url_mappings = Arc::new(tokio::sync::RwLock::new(HashMap::new()));
+
+        // Fee payments already used to fund a mint, shared by legacy and v1 endpoints
+        let fee_payments = fee_payments::FeePaymentLedger::new(index.clone());
+
+        // Transactions prepared for wallets to sign and the ones they submitted
+        let transactions = transactions::TransactionTracker::new(cache.clone(), index.clone());
+
+        let api_state = api::ApiState {
+            solana_client: solana_client.clone(),
+            freepik_client: freepik_client.clone(),
+            keypair: keypair.clone(),
+            url_mappings: url_mappings.clone(),
+            fee_payments: fee_payments.clone(),
+            auth: auth.clone(),
+            transactions: transactions.clone(),
+            cache: cache.clone(),
+            index: index.clone(),
+        };
+
+        AppState {
+            solana_client,
+            freepik_client,
+            keypair,
+            url_mappings,
+            fee_payments,
+            auth,
+            transactions,
+            cache,
+            index,
+            api_state,
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     // Load environment variables from .env file
     let dotenv_path = dotenv::dotenv().expect("Failed to load .env");
     println!("Loaded .env from {}", dotenv_path.display());
 
+    // Index maintenance commands (see cli.rs) run instead of the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
     // Validate required environment variables
-    let rpc_url = std::env::var("SOLANA_RPC_URL")
-        .unwrap_or_else(|_| "https://api.devnet.solana.com".to_string());
+    // (SOLANA_RPC_URLS: comma-separated endpoints to fail over between)
+    let rpc_urls: Vec<String> = std::env::var("SOLANA_RPC_URLS").ok()
+        .filter(|urls| !urls.trim().is_empty())
+        .or_else(|| std::env::var("SOLANA_RPC_URL").ok())
+        .unwrap_or_else(|| "https://api.devnet.solana.com".to_string())
+        .split(',')
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .collect();
 
     let private_key_str = std::env::var("SOLANA_PRIVATE_KEY")
         .expect("SOLANA_PRIVATE_KEY environment variable must be set");
@@ -139,32 +297,77 @@ async fn main() {
     let keypair = solana_sdk::signature::Keypair::from_bytes(&bytes)
         .expect("Invalid keypair bytes");
 
-    // Initialize Solana client
-    let solana_client = Arc::new(solana_client::rpc_client::RpcClient::new(rpc_url));
+    // Initialize Solana client (or the in-memory chain, see CHAIN_BACKEND)
+    let solana_client = chain::connect(rpc_urls, &solana_sdk::signer::Signer::pubkey(&keypair));
+
+    if let Some(command) = command {
+        if let Err(e) = cli::run(command, solana_client).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
     // Initialize Freepik client
     let freepik_client = std::env::var("FREEPIK_API_KEY")
         .ok()
         .map(FreepikApiClient::new);
 
-    let keypair_arc = Arc::new(keypair);
+    // Domain that signed messages must name, guarding against phishing sites
+    let auth_domain = std::env::var("AUTH_DOMAIN")
+        .unwrap_or_else(|_| "mintmint.fun".to_string());
+    let auth = auth::AuthState::new(auth_domain);
 
-    let api_state = api::ApiState {
-        solana_client: solana_client.clone(),
-        freepik_client: freepik_client.clone(),
-        keypair: keypair_arc.clone(),
-        url_mappings: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
-    };
+    // Hot account reads, evicted when our transactions write the accounts
+    let cache = cache::ChainCache::from_env(solana_client.clone()).await;
 
-    let state = AppState {
-        solana_client,
-        freepik_client,
-        keypair: keypair_arc,
-        url_mappings: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
-        api_state,
-    };
+    // Marketplace index in SQLite or Postgres, kept up to date in the background
+    let mut index = None;
+    if let Some(database_url) = std::env::var("DATABASE_URL").ok().filter(|url| !url.is_empty()) {
+        match index_db::IndexDb::connect(&database_url).await {
+            Ok(db) => {
+                tokio::spawn(indexer::Indexer::new(solana_client.clone(), db.clone()).run());
+                index = Some(db);
+            }
+            Err(e) => println!("{}; marketplace indexer disabled", e),
+        }
+    }
+
+    let state = AppState::new(solana_client, freepik_client, keypair, auth, cache, index);
 
-    let app = Router::new()
+    // Transactions that were still pending when the server last stopped
+    if let Err(e) = transactions::resume(state.solana_client.clone(), &state.transactions, &state.keypair).await {
+        println!("Failed to resume transaction tracking: {}", e);
+    }
+    if let Err(e) = state.fee_payments.resume(&state.transactions).await {
+        println!("Failed to resume fee payment claims: {}", e);
+    }
+
+    let app = router(state);
+
+    // Get port from environment variable (Render provides PORT)
+    let port = std::env::var("PORT")
+        .unwrap_or_else(|_| "3001".to_string())
+        .parse::<u16>()
+        .unwrap_or(3001);
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    println!("mintmint.fun API server starting on {}", addr);
+
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
+
+fn router(state: AppState) -> Router {
+    // v1 routes that act on behalf of a wallet require a session token
+    let authenticated = Router::new()
+        .route("/api/v1/nfts/mint", post(api::mint_nft))
+        .route("/api/v1/marketplace/list", post(api::list_nft))
+        .route("/api/v1/marketplace/buy", post(api::buy_nft))
+        .route("/api/v1/auth/logout", post(api::logout))
+        .route_layer(middleware::from_fn_with_state(state.clone(), api::require_session));
+
+    Router::new()
         // Legacy endpoints (keeping for backward compatibility)
         .route("/", get(health_check))
         .route("/mint-nft", post(mint_nft_handler))
@@ -186,29 +389,26 @@ async fn main() {
         .route("/marketplace/search", post(search_nfts_handler))
         .route("/marketplace/nft/:address", get(get_nft_details_handler))
         // New v1 API endpoints
+        .route("/api/v1/auth/nonce", get(api::issue_nonce))
+        .route("/api/v1/auth/login", post(api::login))
         .route("/api/v1/images/generate", post(api::generate_images))
-        .route("/api/v1/nfts/mint", post(api::mint_nft))
         .route("/api/v1/wallet/:address/nfts", get(api::get_wallet_nfts))
-        .route("/api/v1/marketplace/list", post(api::list_nft))
         .route("/api/v1/marketplace/listings", get(api::get_listings))
+        .route("/api/v1/marketplace/stats", get(api::get_marketplace_stats))
+        .route("/api/v1/collections/:address/stats", get(api::get_collection_stats))
+        .route("/api/v1/collections/:address/history", get(api::get_collection_history))
+        .route("/api/v1/nfts/:address/activity", get(api::get_nft_activity))
+        .route("/api/v1/wallet/:address/activity", get(api::get_wallet_activity))
+        .route("/api/v1/wallet/:address/activity/export", get(api::export_wallet_activity))
+        .route("/api/v1/transactions/submit", post(api::submit_transaction))
+        .route("/api/v1/transactions/:signature", get(api::get_transaction_status))
         .route("/api/v1/fees/estimate", get(api::get_fee_estimates))
         .route("/api/v1/health", get(api::health_check))
+        .merge(authenticated)
         // Swagger UI
         .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
         .layer(CorsLayer::permissive())
-        .with_state(state);
-
-    // Get port from environment variable (Render provides PORT)
-    let port = std::env::var("PORT")
-        .unwrap_or_else(|_| "3001".to_string())
-        .parse::<u16>()
-        .unwrap_or(3001);
-
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    println!("mintmint.fun API server starting on {}", addr);
-
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+        .with_state(state)
 }
 
 #[utoipa::path(
@@ -236,7 +436,29 @@ async fn mint_nft_handler(
     State(state): State<AppState>,
     Json(req): Json<nft::MintNftRequest>,
 ) -> Result<Json<nft::MintNftResponse>, String> {
-    let result = nft::mint_nft(state.solana_client, &*state.keypair, req, state.url_mappings.clone()).await?;
+    // Every field the mint acts on, absent ones as empty strings
+    let request_hash = auth::request_hash(&[
+        &req.name,
+        &req.symbol,
+        &req.uri,
+        &req.creator_pubkey,
+        req.fee_payment_signature.as_deref().unwrap_or_default(),
+        req.fee_quote.as_deref().unwrap_or_default(),
+    ]);
+    state.auth
+        .verify(&req.message, &req.signature, &req.creator_pubkey, "mint_nft", &request_hash)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let result = nft::mint_nft(
+        state.solana_client,
+        &state.keypair,
+        req,
+        state.url_mappings.clone(),
+        &state.fee_payments,
+        &state.transactions,
+    ).await
+    .map_err(|e| e.to_string())?;
     Ok(Json(result))
 }
 
@@ -262,15 +484,16 @@ async fn create_collection_handler(
     path = "/list-nft",
     request_body = nft::ListNftRequest,
     responses(
-        (status = 200, description = "NFT listed successfully", body = serde_json::Value)
+        (status = 200, description = "Listing transaction prepared", body = nft::ListNftResponse)
     ),
     tag = "nft"
 )]
 async fn list_nft_handler(
     State(state): State<AppState>,
     Json(req): Json<nft::ListNftRequest>,
-) -> Result<Json<serde_json::Value>, String> {
-    let result = nft::list_nft(state.solana_client, &*state.keypair, req).await?;
+) -> Result<Json<nft::ListNftResponse>, String> {
+    let result = nft::list_nft(state.solana_client, &state.transactions, req).await
+        .map_err(|e| e.to_string())?;
     Ok(Json(result))
 }
 
@@ -279,15 +502,16 @@ async fn list_nft_handler(
     path = "/buy-nft",
     request_body = nft::BuyNftRequest,
     responses(
-        (status = 200, description = "NFT purchased successfully", body = serde_json::Value)
+        (status = 200, description = "Purchase transaction prepared", body = nft::BuyNftResponse)
     ),
     tag = "nft"
 )]
 async fn buy_nft_handler(
     State(state): State<AppState>,
     Json(req): Json<nft::BuyNftRequest>,
-) -> Result<Json<serde_json::Value>, String> {
-    let result = nft::buy_nft(state.solana_client, &*state.keypair, req).await?;
+) -> Result<Json<nft::BuyNftResponse>, String> {
+    let result = nft::buy_nft(state.solana_client, &state.transactions, req).await
+        .map_err(|e| e.to_string())?;
     Ok(Json(result))
 }
 
@@ -319,13 +543,30 @@ async fn generate_and_mint_nft_handler(
     State(state): State<AppState>,
     Json(req): Json<nft::GenerateAndMintNftRequest>,
 ) -> Result<Json<nft::MintNftResponse>, String> {
+    let request_hash = auth::request_hash(&[
+        &req.name,
+        &req.symbol,
+        &req.prompt,
+        req.style.as_deref().unwrap_or_default(),
+        &req.creator_pubkey,
+        req.fee_payment_signature.as_deref().unwrap_or_default(),
+        req.fee_quote.as_deref().unwrap_or_default(),
+    ]);
+    state.auth
+        .verify(&req.message, &req.signature, &req.creator_pubkey, "generate_and_mint_nft", &request_hash)
+        .await
+        .map_err(|e| e.to_string())?;
+
     let result = nft::generate_and_mint_nft(
         state.solana_client, 
-        &*state.keypair, 
+        &state.keypair, 
         state.freepik_client.as_ref(), 
         state.url_mappings.clone(),
+        &state.fee_payments,
+        &state.transactions,
         req
-    ).await?;
+    ).await
+    .map_err(|e| e.to_string())?;
     Ok(Json(result))
 }
 
@@ -340,7 +581,7 @@ async fn generate_and_mint_nft_handler(
 async fn get_fee_estimate_handler(
     State(state): State<AppState>,
 ) -> Result<Json<nft::FeeEstimateResponse>, String> {
-    let result = nft::get_fee_estimate(state.solana_client, &*state.keypair).await?;
+    let result = nft::get_fee_estimate(state.solana_client, &state.keypair).await?;
     Ok(Json(result))
 }
 
@@ -444,7 +685,7 @@ async fn get_wallet_balance_handler(
     State(state): State<AppState>,
     Json(req): Json<wallet::WalletBalanceRequest>,
 ) -> Result<Json<wallet::WalletBalanceResponse>, StatusCode> {
-    match wallet::get_wallet_balance(state.solana_client.clone(), &req.wallet_address).await {
+    match wallet::get_wallet_balance(&state.cache, &req.wallet_address).await {
         Ok(response) => Ok(Json(response)),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
@@ -463,7 +704,7 @@ async fn get_wallet_nfts_handler(
     State(state): State<AppState>,
     Json(req): Json<wallet::WalletNftsRequest>,
 ) -> Result<Json<wallet::WalletNftsResponse>, StatusCode> {
-    match wallet::get_wallet_nfts(state.solana_client.clone(), &req.wallet_address).await {
+    match wallet::get_wallet_nfts(state.solana_client.clone(), &state.cache, &req.wallet_address).await {
         Ok(response) => Ok(Json(response)),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
@@ -473,6 +714,9 @@ async fn get_wallet_nfts_handler(
 #[utoipa::path(
     get,
     path = "/marketplace/stats",
+    params(
+        ("collection" = Option<String>, Query, description = "Verified collection mint to limit the stats to")
+    ),
     responses(
         (status = 200, description = "Marketplace stats retrieved successfully", body = marketplace::MarketplaceStatsResponse)
     ),
@@ -480,8 +724,13 @@ async fn get_wallet_nfts_handler(
 )]
 async fn get_marketplace_stats_handler(
     State(state): State<AppState>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
 ) -> Result<Json<marketplace::MarketplaceStatsResponse>, StatusCode> {
-    match marketplace::get_marketplace_stats(state.solana_client.clone()).await {
+    let collection = match params.get("collection").map(|c| c.parse::<solana_sdk::pubkey::Pubkey>()).transpose() {
+        Ok(collection) => collection,
+        Err(_) => return Err(StatusCode::BAD_REQUEST),
+    };
+    match marketplace::get_marketplace_stats(state.index.as_ref(), collection.as_ref()).await {
         Ok(response) => Ok(Json(response)),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
@@ -502,7 +751,7 @@ async fn get_listed_nfts_handler(
     let page = params.get("page").and_then(|p| p.parse().ok()).unwrap_or(1);
     let per_page = params.get("per_page").and_then(|p| p.parse().ok()).unwrap_or(20);
     
-    match marketplace::get_listed_nfts(state.solana_client.clone(), page, per_page).await {
+    match marketplace::get_listed_nfts(&state.cache, page, per_page).await {
         Ok(response) => Ok(Json(response)),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
@@ -521,7 +770,7 @@ async fn search_nfts_handler(
     State(state): State<AppState>,
     Json(req): Json<marketplace::SearchNftsRequest>,
 ) -> Result<Json<marketplace::GetListedNftsResponse>, StatusCode> {
-    match marketplace::search_nfts(state.solana_client.clone(), req).await {
+    match marketplace::search_nfts(&state.cache, req).await {
         Ok(response) => Ok(Json(response)),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
@@ -542,8 +791,296 @@ async fn get_nft_details_handler(
     State(state): State<AppState>,
     Path(address): Path<String>,
 ) -> Result<Json<marketplace::NftDetailsResponse>, StatusCode> {
-    match marketplace::get_nft_details(state.solana_client.clone(), &address).await {
+    match marketplace::get_nft_details(state.solana_client.clone(), &state.cache, state.index.as_ref(), &address).await {
         Ok(response) => Ok(Json(response)),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_client::anchor_lang::{AnchorSerialize, Discriminator};
+    use axum::{body::{to_bytes, Body}, http::Request};
+    use base64::Engine;
+    use memory_chain::MemoryChain;
+    use serde_json::{json, Value};
+    use solana_sdk::{
+        account::Account, instruction::InstructionError, native_token::LAMPORTS_PER_SOL, program_pack::Pack,
+        pubkey::Pubkey, signature::{Keypair, Signer}, transaction::Transaction,
+    };
+    use tower::ServiceExt;
+
+    // The API on an in-memory chain, with a funded platform wallet
+    fn test_app() -> (Arc<MemoryChain>, Router) {
+        test_app_with_index(None)
+    }
+
+    fn test_app_with_index(index: Option<index_db::IndexDb>) -> (Arc<MemoryChain>, Router) {
+        let chain = Arc::new(MemoryChain::new());
+        let platform = Keypair::new();
+        chain.airdrop(&platform.pubkey(), 10 * LAMPORTS_PER_SOL);
+
+        let cache = cache::ChainCache::new(chain.clone(), None, std::time::Duration::ZERO);
+        let auth = auth::AuthState::new("mintmint.fun".to_string());
+        let state = AppState::new(chain.clone(), None, platform, auth, cache, index);
+        (chain, router(state))
+    }
+
+    async fn call(app: &Router, method: &str, uri: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = match body {
+            Some(body) => request.header(header::CONTENT_TYPE, "application/json").body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }.unwrap();
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    // Signs a login message for a fresh nonce and returns the session token
+    async fn login(app: &Router, wallet: &Keypair) -> String {
+        let uri = format!("/api/v1/auth/nonce?wallet_address={}", wallet.pubkey());
+        let (_, nonce) = call(app, "GET", &uri, None, None).await;
+        let nonce = &nonce["data"];
+        let message = format!(
+            "{} wants you to sign in with your Solana account:\n{}\n\nAction: login\nRequest Hash: {}\nNonce: {}\nIssued At: {}\nExpiration Time: {}",
+            nonce["domain"].as_str().unwrap(),
+            wallet.pubkey(),
+            auth::request_hash(&[&wallet.pubkey().to_string()]),
+            nonce["nonce"].as_str().unwrap(),
+            nonce["issued_at"].as_str().unwrap(),
+            nonce["expiration_time"].as_str().unwrap(),
+        );
+        let body = json!({
+            "wallet_address": wallet.pubkey().to_string(),
+            "signature": wallet.sign_message(message.as_bytes()).to_string(),
+            "message": message,
+        });
+        let (_, session) = call(app, "POST", "/api/v1/auth/login", None, Some(body)).await;
+        session["data"]["token"].as_str().expect("login succeeds").to_string()
+    }
+
+    // Adds the wallet's signature to a prepared transaction and submits it
+    async fn sign_and_submit(app: &Router, prepared: &Value, wallet: &Keypair) -> Value {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let bytes = engine.decode(prepared["transaction"].as_str().unwrap()).unwrap();
+        let mut transaction: Transaction = bincode::deserialize(&bytes).unwrap();
+        let blockhash = transaction.message.recent_blockhash;
+        transaction.partial_sign(&[wallet], blockhash);
+
+        let body = json!({ "transaction": engine.encode(bincode::serialize(&transaction).unwrap()) });
+        call(app, "POST", "/api/v1/transactions/submit", None, Some(body)).await.1
+    }
+
+    async fn token_amount(chain: &MemoryChain, owner: &Pubkey, mint: &Pubkey) -> u64 {
+        let address = spl_associated_token_account::get_associated_token_address(owner, mint);
+        chain.get_account(&address).await
+            .map_or(0, |account| spl_token::state::Account::unpack(&account.data).unwrap().amount)
+    }
+
+    // Mints an NFT to `creator` through the co-signed flow
+    async fn mint(app: &Router, creator: &Keypair, token: &str) -> Pubkey {
+        let body = json!({ "name": "Test", "symbol": "TST", "image_url": "https://example.com/image.png" });
+        let (_, minted) = call(app, "POST", "/api/v1/nfts/mint", Some(token), Some(body)).await;
+        assert_eq!(minted["success"], true, "{}", minted);
+        let submitted = sign_and_submit(app, &minted["data"]["transaction"], creator).await;
+        assert_eq!(submitted["success"], true, "{}", submitted);
+        assert_eq!(submitted["data"]["transaction_signature"], minted["data"]["transaction_signature"]);
+        minted["data"]["nft_address"].as_str().unwrap().parse().unwrap()
+    }
+
+    fn error_code(response: &Value) -> &str {
+        response["error"]["code"].as_str().unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn mint_list_and_buy_over_http() {
+        let (chain, app) = test_app();
+        let (creator, buyer, broke_buyer) = (Keypair::new(), Keypair::new(), Keypair::new());
+        chain.airdrop(&creator.pubkey(), LAMPORTS_PER_SOL);
+        chain.airdrop(&buyer.pubkey(), 10 * LAMPORTS_PER_SOL);
+        chain.airdrop(&broke_buyer.pubkey(), LAMPORTS_PER_SOL / 100);
+        let creator_token = login(&app, &creator).await;
+        let buyer_token = login(&app, &buyer).await;
+
+        let nft = mint(&app, &creator, &creator_token).await;
+        assert_eq!(token_amount(&chain, &creator.pubkey(), &nft).await, 1);
+
+        // Only the holder can list it
+        let body = json!({ "nft_address": nft.to_string(), "price": LAMPORTS_PER_SOL });
+        let (_, listed) = call(&app, "POST", "/api/v1/marketplace/list", Some(&buyer_token), Some(body.clone())).await;
+        assert_eq!(listed["success"], false);
+        assert_eq!(error_code(&listed), "NOT_OWNER");
+
+        let (_, listed) = call(&app, "POST", "/api/v1/marketplace/list", Some(&creator_token), Some(body)).await;
+        assert_eq!(listed["success"], true, "{}", listed);
+        let submitted = sign_and_submit(&app, &listed["data"]["transaction"], &creator).await;
+        assert_eq!(submitted["success"], true, "{}", submitted);
+        let listing_address: Pubkey = listed["data"]["listing_address"].as_str().unwrap().parse().unwrap();
+        assert_eq!(token_amount(&chain, &listing_address, &nft).await, 1);
+
+        // The program debits the buyer's wallet directly, so simulation rejects every purchase
+        let body = json!({ "listing_address": listing_address.to_string(), "nft_address": nft.to_string() });
+        let (_, bought) = call(&app, "POST", "/api/v1/marketplace/buy", Some(&buyer_token), Some(body.clone())).await;
+        assert_eq!(bought["success"], false);
+        assert_eq!(error_code(&bought), "BUY_FAILED");
+        assert_eq!(bought["error"]["details"]["program"], "marketplace");
+        assert_eq!(
+            bought["error"]["details"]["error_message"],
+            InstructionError::ExternalAccountLamportSpend.to_string()
+        );
+
+        // A buyer who can't cover the price fails before that
+        let broke_token = login(&app, &broke_buyer).await;
+        let (_, bought) = call(&app, "POST", "/api/v1/marketplace/buy", Some(&broke_token), Some(body)).await;
+        assert_eq!(error_code(&bought), "BUY_FAILED");
+        assert_eq!(
+            bought["error"]["details"]["error_message"],
+            InstructionError::ProgramFailedToComplete.to_string()
+        );
+        assert!(bought["error"]["details"]["logs"].as_array().unwrap().iter()
+            .any(|line| line.as_str().unwrap().contains("attempt to subtract with overflow")));
+
+        assert_eq!(token_amount(&chain, &listing_address, &nft).await, 1);
+        assert_eq!(token_amount(&chain, &buyer.pubkey(), &nft).await, 0);
+    }
+
+    #[tokio::test]
+    async fn listing_is_rejected_while_a_listing_account_exists() {
+        let (chain, app) = test_app();
+        let creator = Keypair::new();
+        chain.airdrop(&creator.pubkey(), LAMPORTS_PER_SOL);
+        let token = login(&app, &creator).await;
+        let nft = mint(&app, &creator, &token).await;
+
+        // The listing a completed sale leaves behind; the program never closes it
+        let listing = marketplace_program::Listing {
+            nft_mint: nft,
+            seller: Pubkey::new_unique(),
+            price: LAMPORTS_PER_SOL,
+            is_active: false,
+        };
+        let mut data = marketplace_program::Listing::DISCRIMINATOR.to_vec();
+        listing.serialize(&mut data).unwrap();
+        let (listing_address, _) = marketplace_program::find_listing_address(&nft);
+        chain.set_account(&listing_address, Account {
+            lamports: LAMPORTS_PER_SOL / 100,
+            data,
+            owner: marketplace_program::program_id(),
+            executable: false,
+            rent_epoch: 0,
+        });
+
+        let body = json!({ "nft_address": nft.to_string(), "price": LAMPORTS_PER_SOL });
+        let (_, listed) = call(&app, "POST", "/api/v1/marketplace/list", Some(&token), Some(body)).await;
+        assert_eq!(listed["success"], false);
+        assert_eq!(error_code(&listed), "ALREADY_LISTED");
+    }
+
+    #[tokio::test]
+    async fn wallet_activity_is_read_from_the_index() {
+        let index = index_db::IndexDb::connect("sqlite::memory:").await.unwrap();
+        let (chain, app) = test_app_with_index(Some(index.clone()));
+        let creator = Keypair::new();
+        chain.airdrop(&creator.pubkey(), LAMPORTS_PER_SOL);
+        let token = login(&app, &creator).await;
+        let nft = mint(&app, &creator, &token).await;
+        let body = json!({ "nft_address": nft.to_string(), "price": LAMPORTS_PER_SOL });
+        let (_, listed) = call(&app, "POST", "/api/v1/marketplace/list", Some(&token), Some(body)).await;
+        sign_and_submit(&app, &listed["data"]["transaction"], &creator).await;
+
+        // Nothing until the indexer has seen the NFT
+        let uri = format!("/api/v1/wallet/{}/activity", creator.pubkey());
+        let (_, activity) = call(&app, "GET", &uri, None, None).await;
+        assert_eq!(activity["data"]["total_count"], 0);
+
+        indexer::backfill(chain.as_ref(), &index, 0).await.unwrap();
+        let (_, activity) = call(&app, "GET", &uri, None, None).await;
+        let events = activity["data"]["events"].as_array().unwrap();
+        let kinds: Vec<&str> = events.iter().map(|event| event["event_type"].as_str().unwrap()).collect();
+        assert_eq!(kinds, vec!["list", "mint"]);
+        assert_eq!(events[1]["mint"], nft.to_string());
+        assert!(events[1]["amount"].as_u64().unwrap() > 0, "{}", events[1]);
+
+        let export = |query: String| {
+            let app = app.clone();
+            let uri = format!("/api/v1/wallet/{}/activity/export?{}", creator.pubkey(), query);
+            async move {
+                let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                String::from_utf8(bytes.to_vec()).unwrap()
+            }
+        };
+        let csv = export("to=2100-01-01T00:00:00Z".to_string()).await;
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.lines().nth(1).unwrap().contains(",mint,"));
+        let csv = export("from=2100-01-01T00:00:00Z".to_string()).await;
+        assert_eq!(csv.lines().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn minting_requires_a_session_and_the_fee_balance() {
+        let (chain, app) = test_app();
+        let creator = Keypair::new();
+        chain.airdrop(&creator.pubkey(), LAMPORTS_PER_SOL / 1000);
+
+        let body = json!({ "name": "Test", "symbol": "TST", "image_url": "https://example.com/image.png" });
+        let (status, minted) = call(&app, "POST", "/api/v1/nfts/mint", None, Some(body.clone())).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error_code(&minted), "UNAUTHORIZED");
+
+        let token = login(&app, &creator).await;
+        let (_, minted) = call(&app, "POST", "/api/v1/nfts/mint", Some(&token), Some(body)).await;
+        assert_eq!(minted["success"], false);
+        assert_eq!(error_code(&minted), "MINT_FAILED");
+        assert!(minted["error"]["message"].as_str().unwrap().starts_with("Insufficient balance"), "{}", minted);
+    }
+
+    #[tokio::test]
+    async fn prepaid_mint_is_held_to_the_quote_and_funds_one_mint() {
+        let (chain, app) = test_app();
+        let creator = Keypair::new();
+        chain.airdrop(&creator.pubkey(), LAMPORTS_PER_SOL);
+        let token = login(&app, &creator).await;
+
+        let (_, estimate) = call(&app, "GET", "/api/v1/fees/estimate", None, None).await;
+        let quote = &estimate["data"]["prepaid_mint_fee"];
+        let total_fee = quote["fee_breakdown"]["total_fee"].as_u64().unwrap();
+        assert!(total_fee < estimate["data"]["mint_fee"]["total_fee"].as_u64().unwrap());
+
+        // Pay the quoted fee to the platform wallet, then mint with the payment
+        let (_, legacy) = call(&app, "GET", "/fee-estimate", None, None).await;
+        let platform: Pubkey = legacy["platform_wallet"].as_str().unwrap().parse().unwrap();
+        let blockhash = chain.get_latest_blockhash().await.unwrap();
+        let payment = Transaction::new_signed_with_payer(
+            &[solana_sdk::system_instruction::transfer(&creator.pubkey(), &platform, total_fee)],
+            Some(&creator.pubkey()),
+            &[&creator],
+            blockhash,
+        );
+        let payment = chain.send_transaction_with_config(&payment, Default::default()).await.unwrap();
+
+        let body = json!({
+            "name": "Test",
+            "symbol": "TST",
+            "image_url": "https://example.com/image.png",
+            "fee_payment_signature": payment.to_string(),
+            "fee_quote": quote["quote"],
+        });
+        let (_, minted) = call(&app, "POST", "/api/v1/nfts/mint", Some(&token), Some(body.clone())).await;
+        assert_eq!(minted["success"], true, "{}", minted);
+        assert_eq!(minted["data"]["transaction"], Value::Null);
+        let nft: Pubkey = minted["data"]["nft_address"].as_str().unwrap().parse().unwrap();
+        assert_eq!(token_amount(&chain, &creator.pubkey(), &nft).await, 1);
+
+        let (_, again) = call(&app, "POST", "/api/v1/nfts/mint", Some(&token), Some(body)).await;
+        assert_eq!(error_code(&again), "MINT_FAILED");
+        assert_eq!(again["error"]["message"], "Fee payment has already been used for another mint");
+    }
+}
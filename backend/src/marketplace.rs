@@ -1,16 +1,110 @@
 // Marketplace utilities and endpoints
+use anchor_client::anchor_lang::{AccountDeserialize, Discriminator};
+use futures::{stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use solana_client::rpc_client::RpcClient;
-use std::sync::Arc;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    native_token::{lamports_to_sol, LAMPORTS_PER_SOL},
+    program_pack::Pack,
+    pubkey::Pubkey,
+};
+use spl_token::state::{Account as TokenAccount, Mint};
+use std::{collections::BTreeMap, str::FromStr, sync::Arc};
 use utoipa::ToSchema;
 
+use crate::activity;
+use crate::cache::ChainCache;
+use crate::chain::{rfc3339, ChainClient};
+use crate::index_db::IndexDb;
+use crate::marketplace_program::{self, Listing};
+use crate::token_metadata::{self, TokenMetadata};
+use crate::wallet::TOKEN_2022_PROGRAM_ID;
+
+// Offset of `is_active` in a Listing account: discriminator, nft_mint, seller, price
+const LISTING_IS_ACTIVE_OFFSET: usize = 8 + 32 + 32 + 8;
+
+// Listings whose first transaction is looked up at once when sorting by recency
+const LISTED_AT_CONCURRENCY: usize = 8;
+
+pub const INDEX_UNAVAILABLE: &str = "Marketplace index is not configured (set DATABASE_URL)";
+
+// Candles returned when no range is given, and at most per request
+const DEFAULT_HOURLY_CANDLES: i64 = 24;
+const DEFAULT_DAILY_CANDLES: i64 = 30;
+const MAX_CANDLES: i64 = 1000;
+const MAX_PER_PAGE: u32 = 100;
+
+// Counted from finalized indexed activity; prices and volumes in SOL
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct MarketplaceStatsResponse {
+    pub collection: Option<String>, // Verified collection mint the stats are limited to
     pub total_nfts: u64,
     pub total_listed: u64,
     pub total_sold: u64,
     pub floor_price: Option<f64>,
     pub volume_24h: f64,
+    pub volume_7d: f64,
+    pub volume_30d: f64,
+    pub average_sale_price: Option<f64>,
+    pub unique_traders: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CandleInterval {
+    Hour,
+    Day,
+}
+
+impl CandleInterval {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandleInterval::Hour => "1h",
+            CandleInterval::Day => "1d",
+        }
+    }
+
+    pub fn seconds(&self) -> i64 {
+        match self {
+            CandleInterval::Hour => 60 * 60,
+            CandleInterval::Day => 24 * 60 * 60,
+        }
+    }
+}
+
+impl FromStr for CandleInterval {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "1h" => Ok(CandleInterval::Hour),
+            "1d" => Ok(CandleInterval::Day),
+            other => Err(format!("Unknown interval {} (expected 1h or 1d)", other)),
+        }
+    }
+}
+
+// One interval of finalized sales; prices and volume in SOL
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct PriceCandle {
+    pub timestamp: String, // Start of the interval, RFC 3339
+    pub open: Option<f64>, // First sale price, None without sales
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub close: Option<f64>, // Last sale price
+    pub volume: f64,
+    pub sales: u64,
+    pub floor_price: Option<f64>, // Lowest listing still open at the end of the interval
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CollectionHistoryResponse {
+    pub collection: String,
+    pub interval: String, // "1h" or "1d"
+    pub candles: Vec<PriceCandle>, // Oldest first, one per interval including empty ones
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -34,7 +128,8 @@ pub struct GetListedNftsResponse {
 
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct SearchNftsRequest {
-    pub query: Option<String>,
+    pub query: Option<String>, // Case-insensitive match on the NFT name or symbol
+    pub sort_by: Option<String>, // "price_asc", "price_desc", "recent"
     pub min_price: Option<f64>,
     pub max_price: Option<f64>,
     pub page: Option<u32>,
@@ -46,78 +141,619 @@ pub struct NftDetailsRequest {
     pub mint_address: String,
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MasterEditionInfo {
+    pub address: String,
+    pub supply: u64,
+    pub max_supply: Option<u64>, // None for unlimited prints
+}
+
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct NftDetailsResponse {
     pub mint_address: String,
     pub name: String,
+    pub symbol: String,
     pub description: Option<String>,
     pub image_url: String,
+    pub metadata_url: String,
     pub attributes: Vec<serde_json::Value>,
+    pub collection: Option<String>, // Verified collection mint
+    pub royalty_basis_points: u16,
+    pub master_edition: Option<MasterEditionInfo>,
     pub owner: String,
     pub is_listed: bool,
+    pub listing_address: Option<String>,
     pub price: Option<f64>,
     pub seller: Option<String>,
     pub created_at: String,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum ListingSort {
+    PriceAsc,
+    PriceDesc,
+    Recent,
+}
+
+impl FromStr for ListingSort {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "price_asc" => Ok(ListingSort::PriceAsc),
+            "price_desc" => Ok(ListingSort::PriceDesc),
+            "recent" => Ok(ListingSort::Recent),
+            other => Err(format!("Unknown sort_by '{}': expected price_asc, price_desc or recent", other)),
+        }
+    }
+}
+
+pub struct ListingQuery {
+    pub text: Option<String>,
+    pub min_price: Option<u64>, // Lamports, inclusive
+    pub max_price: Option<u64>,
+    pub sort: ListingSort,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+pub struct ActiveListing {
+    pub address: Pubkey,
+    pub listing: Listing,
+    pub metadata: Option<TokenMetadata>,
+    pub offchain: Option<serde_json::Value>,
+    pub listed_at: Option<i64>, // Block time of the listing transaction
+}
+
+impl ActiveListing {
+    pub fn name(&self) -> String {
+        self.metadata.as_ref().map(|m| m.name.clone()).unwrap_or_default()
+    }
+
+    pub fn image_url(&self) -> String {
+        self.offchain.as_ref().and_then(token_metadata::offchain_image).unwrap_or_default()
+    }
+
+    pub fn description(&self) -> Option<String> {
+        self.offchain.as_ref()
+            .and_then(|json| json.get("description"))
+            .and_then(|description| description.as_str())
+            .map(str::to_string)
+    }
+
+    pub fn listed_at_rfc3339(&self) -> String {
+        rfc3339(self.listed_at).unwrap_or_default()
+    }
+}
+
+pub struct ListingPage {
+    pub listings: Vec<ActiveListing>,
+    pub total_count: usize,
+}
+
+// Active Listing accounts of the marketplace program, matched on the account
+// discriminator and `is_active` so inactive listings never leave the node
+async fn active_listings(cache: &ChainCache) -> Result<Vec<(Pubkey, Listing)>, String> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![
+            RpcFilterType::DataSize(Listing::LEN as u64),
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(0, Listing::DISCRIMINATOR.to_vec())),
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(LISTING_IS_ACTIVE_OFFSET, vec![1])),
+        ]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let accounts = cache.program_accounts(&marketplace_program::program_id(), config).await
+        .map_err(|e| format!("Failed to fetch listings: {}", e))?;
+
+    Ok(accounts.into_iter()
+        .filter_map(|(address, account)| {
+            let listing = Listing::try_deserialize(&mut account.data.as_slice()).ok()?;
+            listing.is_active.then_some((address, listing))
+        })
+        .collect())
+}
+
+async fn fetch_metadata(cache: &ChainCache, listings: &mut [ActiveListing]) -> Result<(), String> {
+    let addresses: Vec<Pubkey> = listings.iter()
+        .map(|listing| token_metadata::metadata_address(&listing.listing.nft_mint))
+        .collect();
+    let accounts = cache.accounts(&addresses).await
+        .map_err(|e| format!("Failed to fetch token metadata: {}", e))?;
+    for (listing, account) in listings.iter_mut().zip(accounts) {
+        listing.metadata = account.as_ref().and_then(TokenMetadata::from_account);
+    }
+    Ok(())
+}
+
+async fn fetch_listed_at(cache: &ChainCache, listings: &mut [ActiveListing]) -> Result<(), String> {
+    let addresses: Vec<Pubkey> = listings.iter().map(|listing| listing.address).collect();
+    let times: Vec<_> = stream::iter(addresses)
+        .map(|address| async move { cache.first_block_time(&address).await })
+        .buffered(LISTED_AT_CONCURRENCY)
+        .collect()
+        .await;
+    for (listing, time) in listings.iter_mut().zip(times) {
+        listing.listed_at = time.map_err(|e| format!("Failed to fetch listing time: {}", e))?;
+    }
+    Ok(())
+}
+
+// Filters, sorts and pages the active listings. Metadata and listing times are
+// fetched for every listing only when the text filter or the sort needs them;
+// otherwise just for the returned page, along with the off-chain JSON.
+pub async fn query_listings(cache: &ChainCache, query: &ListingQuery) -> Result<ListingPage, String> {
+    let mut listings: Vec<ActiveListing> = active_listings(cache).await?
+        .into_iter()
+        .filter(|(_, listing)| query.min_price.is_none_or(|min| listing.price >= min))
+        .filter(|(_, listing)| query.max_price.is_none_or(|max| listing.price <= max))
+        .map(|(address, listing)| ActiveListing { address, listing, metadata: None, offchain: None, listed_at: None })
+        .collect();
+
+    let text = query.text.as_deref().map(str::trim).filter(|text| !text.is_empty()).map(str::to_lowercase);
+    if let Some(text) = &text {
+        fetch_metadata(cache, &mut listings).await?;
+        listings.retain(|listing| {
+            listing.metadata.as_ref().is_some_and(|m| {
+                m.name.to_lowercase().contains(text) || m.symbol.to_lowercase().contains(text)
+            })
+        });
+    }
+    if query.sort == ListingSort::Recent {
+        fetch_listed_at(cache, &mut listings).await?;
+    }
+
+    // Ties (and unknown listing times, which sort last) fall back to the listing address
+    match query.sort {
+        ListingSort::PriceAsc => listings.sort_by_key(|l| (l.listing.price, l.address)),
+        ListingSort::PriceDesc => listings.sort_by_key(|l| (std::cmp::Reverse(l.listing.price), l.address)),
+        ListingSort::Recent => listings.sort_by_key(|l| (std::cmp::Reverse(l.listed_at), l.address)),
+    }
+
+    let total_count = listings.len();
+    let mut page: Vec<ActiveListing> = listings.into_iter().skip(query.offset).take(query.limit).collect();
+
+    if text.is_none() {
+        fetch_metadata(cache, &mut page).await?;
+    }
+    if query.sort != ListingSort::Recent {
+        fetch_listed_at(cache, &mut page).await?;
+    }
+    let uris: Vec<String> = page.iter()
+        .map(|listing| listing.metadata.as_ref().map(|m| m.uri.clone()).unwrap_or_default())
+        .collect();
+    for (listing, offchain) in page.iter_mut().zip(token_metadata::fetch_offchain_batch(&uris).await) {
+        listing.offchain = offchain;
+    }
+
+    Ok(ListingPage { listings: page, total_count })
+}
+
+fn sol_to_lamports(sol: f64) -> u64 {
+    (sol.max(0.0) * LAMPORTS_PER_SOL as f64).round() as u64
+}
+
+fn to_listed_nft(listing: &ActiveListing) -> ListedNft {
+    ListedNft {
+        mint_address: listing.listing.nft_mint.to_string(),
+        name: listing.name(),
+        description: listing.description(),
+        image_url: listing.image_url(),
+        price: lamports_to_sol(listing.listing.price),
+        seller: listing.listing.seller.to_string(),
+        listed_at: listing.listed_at_rfc3339(),
+    }
+}
+
+/// Marketplace stats from the index, for one verified collection or overall
 pub async fn get_marketplace_stats(
-    _client: Arc<RpcClient>,
+    index: Option<&IndexDb>,
+    collection: Option<&Pubkey>,
 ) -> Result<MarketplaceStatsResponse, String> {
-    // TODO: Implement actual marketplace statistics
+    let index = index.ok_or(INDEX_UNAVAILABLE)?;
+    let collection = collection.map(|collection| collection.to_string());
+    let stats = index.stats(collection.as_deref(), chrono::Utc::now().timestamp()).await?;
     Ok(MarketplaceStatsResponse {
-        total_nfts: 0,
-        total_listed: 0,
-        total_sold: 0,
-        floor_price: None,
-        volume_24h: 0.0,
+        collection,
+        total_nfts: stats.total_nfts,
+        total_listed: stats.total_listed,
+        total_sold: stats.total_sold,
+        floor_price: stats.floor_price.map(lamports_to_sol),
+        volume_24h: lamports_to_sol(stats.volume_24h),
+        volume_7d: lamports_to_sol(stats.volume_7d),
+        volume_30d: lamports_to_sol(stats.volume_30d),
+        average_sale_price: (stats.total_sold > 0).then(|| lamports_to_sol(stats.total_volume) / stats.total_sold as f64),
+        unique_traders: stats.unique_traders,
+    })
+}
+
+/// Interval-aligned `[from, to)` covering the requested times: `to` defaults to
+/// now and is capped at the current interval, `from` defaults to the last 24
+/// hourly or 30 daily intervals
+pub fn history_range(
+    interval: CandleInterval,
+    from: Option<i64>,
+    to: Option<i64>,
+    now: i64,
+) -> Result<(i64, i64), String> {
+    let step = interval.seconds();
+    let to = to.unwrap_or(now).min(now);
+    // Rounded up, so the interval `to` falls in is included
+    let to = (to + step - 1).div_euclid(step) * step;
+    let from = match from {
+        Some(from) => from.div_euclid(step) * step,
+        None => to - step * match interval {
+            CandleInterval::Hour => DEFAULT_HOURLY_CANDLES,
+            CandleInterval::Day => DEFAULT_DAILY_CANDLES,
+        },
+    };
+    if from >= to {
+        return Err("from must be before to and not in the future".to_string());
+    }
+    if (to - from) / step > MAX_CANDLES {
+        return Err(format!("At most {} candles can be requested at once", MAX_CANDLES));
+    }
+    Ok((from, to))
+}
+
+/// Sale candles and floor price of a collection over a range from `history_range`
+pub async fn get_collection_history(
+    index: Option<&IndexDb>,
+    collection: &Pubkey,
+    interval: CandleInterval,
+    (from, to): (i64, i64),
+    now: i64,
+) -> Result<CollectionHistoryResponse, String> {
+    let index = index.ok_or(INDEX_UNAVAILABLE)?;
+    let collection = collection.to_string();
+    let sales = index.collection_sales(&collection, from, to).await?;
+    let listings = index.collection_listings(&collection, from, to).await?;
+
+    // Listings opening and closing, oldest first, swept alongside the candles.
+    // A listing closed before it was listed was never open.
+    let mut changes: Vec<(i64, bool, u64)> = listings.iter()
+        .filter(|listing| listing.closed_at.is_none_or(|closed_at| closed_at >= listing.listed_at))
+        .flat_map(|listing| {
+            let closed = listing.closed_at.map(|closed_at| (closed_at, false, listing.price));
+            std::iter::once((listing.listed_at, true, listing.price)).chain(closed)
+        })
+        .collect();
+    changes.sort_unstable_by_key(|&(time, opened, _)| (time, !opened));
+    let mut changes = changes.into_iter().peekable();
+    // Prices of the open listings, with how many are open at each
+    let mut open = BTreeMap::<u64, usize>::new();
+
+    let step = interval.seconds();
+    let mut sales = sales.iter().peekable();
+    let candles = (from..to).step_by(step as usize)
+        .map(|start| {
+            let end = start + step;
+            // Oldest first, so the first and last of the interval open and close it
+            let mut prices = Vec::new();
+            while let Some(sale) = sales.next_if(|sale| sale.time < end) {
+                prices.push(sale.price);
+            }
+            // The interval in progress shows the floor as of now, this second included
+            let snapshot = end.min(now + 1);
+            while let Some((_, opened, price)) = changes.next_if(|&(time, _, _)| time < snapshot) {
+                if opened {
+                    *open.entry(price).or_default() += 1;
+                } else if let Some(count) = open.get_mut(&price) {
+                    *count -= 1;
+                    if *count == 0 {
+                        open.remove(&price);
+                    }
+                }
+            }
+            let floor = open.keys().next().copied();
+            PriceCandle {
+                timestamp: rfc3339(Some(start)).unwrap_or_default(),
+                open: prices.first().copied().map(lamports_to_sol),
+                high: prices.iter().max().copied().map(lamports_to_sol),
+                low: prices.iter().min().copied().map(lamports_to_sol),
+                close: prices.last().copied().map(lamports_to_sol),
+                volume: lamports_to_sol(prices.iter().sum()),
+                sales: prices.len() as u64,
+                floor_price: floor.map(lamports_to_sol),
+            }
+        })
+        .collect();
+
+    Ok(CollectionHistoryResponse {
+        collection,
+        interval: interval.as_str().to_string(),
+        candles,
     })
 }
 
 pub async fn get_listed_nfts(
-    _client: Arc<RpcClient>,
+    cache: &ChainCache,
     page: u32,
     per_page: u32,
 ) -> Result<GetListedNftsResponse, String> {
-    // TODO: Implement actual listed NFTs fetching
-    Ok(GetListedNftsResponse {
-        nfts: Vec::new(),
-        total_count: 0,
-        page,
-        per_page,
-    })
+    search_nfts(cache, SearchNftsRequest {
+        query: None,
+        sort_by: None,
+        min_price: None,
+        max_price: None,
+        page: Some(page),
+        per_page: Some(per_page),
+    }).await
 }
 
 pub async fn search_nfts(
-    _client: Arc<RpcClient>,
+    cache: &ChainCache,
     request: SearchNftsRequest,
 ) -> Result<GetListedNftsResponse, String> {
-    let page = request.page.unwrap_or(1);
-    let per_page = request.per_page.unwrap_or(20);
-    
-    // TODO: Implement actual NFT search
+    let page = request.page.unwrap_or(1).max(1);
+    let per_page = request.per_page.unwrap_or(20).clamp(1, MAX_PER_PAGE);
+    let sort = request.sort_by.as_deref().map(ListingSort::from_str).transpose()?
+        .unwrap_or(ListingSort::Recent);
+
+    let result = query_listings(cache, &ListingQuery {
+        text: request.query,
+        min_price: request.min_price.map(sol_to_lamports),
+        max_price: request.max_price.map(sol_to_lamports),
+        sort,
+        offset: (page as usize - 1) * per_page as usize,
+        limit: per_page as usize,
+    }).await?;
+
     Ok(GetListedNftsResponse {
-        nfts: Vec::new(),
-        total_count: 0,
+        nfts: result.listings.iter().map(to_listed_nft).collect(),
+        total_count: result.total_count,
         page,
         per_page,
     })
 }
 
 pub async fn get_nft_details(
-    _client: Arc<RpcClient>,
+    client: Arc<dyn ChainClient>,
+    cache: &ChainCache,
+    index: Option<&IndexDb>,
     mint_address: &str,
 ) -> Result<NftDetailsResponse, String> {
-    // TODO: Implement actual NFT details fetching
+    let mint = Pubkey::from_str(mint_address)
+        .map_err(|e| format!("Invalid mint address: {}", e))?;
+    let metadata_address = token_metadata::metadata_address(&mint);
+    let edition_address = token_metadata::master_edition_address(&mint);
+    let (listing_address, _) = marketplace_program::find_listing_address(&mint);
+
+    let accounts = cache.accounts(&[mint, metadata_address, edition_address, listing_address]).await
+        .map_err(|e| format!("Failed to fetch accounts: {}", e))?;
+    let [mint_account, metadata_account, edition_account, listing_account]: [_; 4] = accounts
+        .try_into()
+        .map_err(|_| "Unexpected RPC response".to_string())?;
+
+    let is_nft_mint = mint_account
+        .filter(|account| account.owner == spl_token::id() || account.owner == TOKEN_2022_PROGRAM_ID)
+        .and_then(|account| Mint::unpack(account.data.get(..Mint::LEN)?).ok())
+        .is_some_and(|mint| mint.decimals == 0);
+    if !is_nft_mint {
+        return Err(format!("{} is not a 0-decimal token mint", mint));
+    }
+    let metadata = metadata_account.as_ref()
+        .and_then(TokenMetadata::from_account)
+        .ok_or_else(|| format!("{} has no token metadata", mint))?;
+    let master_edition = edition_account.as_ref()
+        .and_then(token_metadata::decode_master_edition)
+        .map(|(supply, max_supply)| MasterEditionInfo {
+            address: edition_address.to_string(),
+            supply,
+            max_supply,
+        });
+    let listing = listing_account
+        .filter(|account| account.owner == marketplace_program::program_id())
+        .and_then(|account| Listing::try_deserialize(&mut account.data.as_slice()).ok())
+        .filter(|listing| listing.is_active);
+
+    // The holder is the owner of the token account with the (single) token
+    let largest = client.get_token_largest_accounts(&mint).await
+        .map_err(|e| format!("Failed to fetch token holders: {}", e))?;
+    let holder_account = largest.iter()
+        .find(|balance| balance.amount.amount != "0")
+        .and_then(|balance| Pubkey::from_str(&balance.address).ok());
+    let holder = match holder_account {
+        Some(address) => cache.account(&address).await
+            .map_err(|e| format!("Failed to fetch token account: {}", e))?
+            .and_then(|account| TokenAccount::unpack(account.data.get(..TokenAccount::LEN)?).ok())
+            .map(|token| token.owner),
+        None => None,
+    };
+    // A listed NFT sits in escrow under the listing PDA; its owner is still the seller
+    let owner = match (&listing, holder) {
+        (Some(listing), Some(holder)) if holder == listing_address => Some(listing.seller),
+        (_, holder) => holder,
+    };
+
+    let (offchain, created_at) = tokio::join!(
+        token_metadata::fetch_offchain(&metadata.uri),
+        activity::first_transaction(cache, index, &mint),
+    );
+    let created_at = created_at?.and_then(|first| first.block_time);
+
     Ok(NftDetailsResponse {
-        mint_address: mint_address.to_string(),
-        name: "Sample NFT".to_string(),
-        description: Some("Sample description".to_string()),
-        image_url: "".to_string(),
-        attributes: Vec::new(),
-        owner: "".to_string(),
-        is_listed: false,
-        price: None,
-        seller: None,
-        created_at: "".to_string(),
+        mint_address: mint.to_string(),
+        name: metadata.name,
+        symbol: metadata.symbol,
+        description: offchain.as_ref()
+            .and_then(|json| json.get("description"))
+            .and_then(|description| description.as_str())
+            .map(str::to_string),
+        image_url: offchain.as_ref().and_then(token_metadata::offchain_image).unwrap_or_default(),
+        metadata_url: metadata.uri,
+        attributes: offchain.as_ref()
+            .and_then(|json| json.get("attributes"))
+            .and_then(|attributes| attributes.as_array())
+            .cloned()
+            .unwrap_or_default(),
+        collection: metadata.collection
+            .filter(|_| metadata.collection_verified)
+            .map(|collection| collection.to_string()),
+        royalty_basis_points: metadata.seller_fee_basis_points,
+        master_edition,
+        owner: owner.map(|owner| owner.to_string()).unwrap_or_default(),
+        is_listed: listing.is_some(),
+        listing_address: listing.as_ref().map(|_| listing_address.to_string()),
+        price: listing.as_ref().map(|listing| lamports_to_sol(listing.price)),
+        seller: listing.as_ref().map(|listing| listing.seller.to_string()),
+        created_at: rfc3339(created_at).unwrap_or_default(),
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_db::NftRecord;
+    use crate::indexer::{Commitment, IndexedTransaction, MarketplaceEvent};
+    use crate::memory_chain::MemoryChain;
+    use solana_client::rpc_config::RpcSendTransactionConfig;
+    use solana_sdk::{
+        instruction::Instruction,
+        rent::Rent,
+        signature::{Keypair, Signer},
+        transaction::Transaction,
+    };
+
+    const HOUR: i64 = 60 * 60;
+    const DAY: i64 = 24 * HOUR;
+
+    async fn send(chain: &MemoryChain, instructions: &[Instruction], signers: &[&Keypair]) {
+        let blockhash = chain.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(instructions, Some(&signers[0].pubkey()), signers, blockhash);
+        chain.send_transaction_with_config(&transaction, RpcSendTransactionConfig::default()).await.unwrap();
+    }
+
+    // Mint an NFT named `name` to `seller` and list it at `price` lamports
+    async fn list(chain: &MemoryChain, seller: &Keypair, name: &str, price: u64) {
+        let mint = Keypair::new();
+        let data = mpl_token_metadata::types::DataV2 {
+            name: name.to_string(),
+            symbol: "TST".to_string(),
+            uri: String::new(),
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: None,
+            uses: None,
+        };
+        let rent = Rent::default().minimum_balance(Mint::LEN);
+        let instructions = crate::nft::mint_instructions(&seller.pubkey(), &seller.pubkey(), &mint.pubkey(), rent, data).unwrap();
+        send(chain, &instructions, &[seller, &mint]).await;
+        send(chain, &[marketplace_program::list_nft(&seller.pubkey(), &mint.pubkey(), price)], &[seller]).await;
+    }
+
+    fn query(text: Option<&str>, sort: ListingSort) -> ListingQuery {
+        ListingQuery { text: text.map(str::to_string), min_price: None, max_price: None, sort, offset: 0, limit: 10 }
+    }
+
+    async fn prices(cache: &ChainCache, query: &ListingQuery) -> (Vec<u64>, usize) {
+        let page = query_listings(cache, query).await.unwrap();
+        (page.listings.iter().map(|listing| listing.listing.price).collect(), page.total_count)
+    }
+
+    #[test]
+    fn listing_sorts_are_parsed_by_name() {
+        assert!(ListingSort::from_str("price_asc") == Ok(ListingSort::PriceAsc));
+        assert!(ListingSort::from_str("price_desc") == Ok(ListingSort::PriceDesc));
+        assert!(ListingSort::from_str("recent") == Ok(ListingSort::Recent));
+        assert!(ListingSort::from_str("cheapest").is_err());
+    }
+
+    #[tokio::test]
+    async fn listings_are_filtered_sorted_and_paged() {
+        let chain = Arc::new(MemoryChain::new());
+        let cache = ChainCache::new(chain.clone(), None, std::time::Duration::ZERO);
+        let seller = Keypair::new();
+        chain.airdrop(&seller.pubkey(), 10 * LAMPORTS_PER_SOL);
+        list(&chain, &seller, "Cat One", 3000).await;
+        list(&chain, &seller, "Dog", 1000).await;
+        list(&chain, &seller, "Cat Two", 2000).await;
+
+        assert_eq!(prices(&cache, &query(None, ListingSort::PriceAsc)).await, (vec![1000, 2000, 3000], 3));
+        assert_eq!(prices(&cache, &query(None, ListingSort::PriceDesc)).await, (vec![3000, 2000, 1000], 3));
+        // Names and symbols match case-insensitively
+        assert_eq!(prices(&cache, &query(Some(" cAT "), ListingSort::PriceDesc)).await, (vec![3000, 2000], 2));
+        assert_eq!(prices(&cache, &query(Some("tst"), ListingSort::PriceAsc)).await.1, 3);
+        assert_eq!(prices(&cache, &query(Some("bird"), ListingSort::PriceAsc)).await, (vec![], 0));
+
+        let in_range = ListingQuery { min_price: Some(1500), max_price: Some(3000), ..query(None, ListingSort::PriceAsc) };
+        assert_eq!(prices(&cache, &in_range).await, (vec![2000, 3000], 2));
+        let second_page = ListingQuery { offset: 1, limit: 1, ..query(None, ListingSort::PriceAsc) };
+        let page = query_listings(&cache, &second_page).await.unwrap();
+        assert_eq!((page.listings.len(), page.total_count), (1, 3));
+        // Metadata and listing times are filled in for the returned page
+        assert_eq!(page.listings[0].name(), "Cat Two");
+        assert!(page.listings[0].listed_at.is_some());
+
+        let recent = query_listings(&cache, &query(None, ListingSort::Recent)).await.unwrap();
+        let order: Vec<_> = recent.listings.iter().map(|listing| (std::cmp::Reverse(listing.listed_at), listing.address)).collect();
+        assert!(order.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(recent.listings.iter().all(|listing| listing.listed_at.is_some() && listing.metadata.is_some()));
+    }
+
+    #[test]
+    fn history_defaults_to_the_latest_intervals() {
+        let now = 100 * DAY + 90;
+        assert_eq!(history_range(CandleInterval::Hour, None, None, now), Ok((now - 90 - 23 * HOUR, now - 90 + HOUR)));
+        assert_eq!(history_range(CandleInterval::Day, None, None, now), Ok((71 * DAY, 101 * DAY)));
+        // Unaligned times cover the intervals they fall in
+        assert_eq!(history_range(CandleInterval::Hour, Some(HOUR + 1), Some(2 * HOUR + 1), now), Ok((HOUR, 3 * HOUR)));
+    }
+
+    #[test]
+    fn history_never_reaches_past_the_current_interval() {
+        let now = 10 * HOUR + 1;
+        assert_eq!(history_range(CandleInterval::Hour, Some(0), Some(now + DAY), now), Ok((0, 11 * HOUR)));
+        assert!(history_range(CandleInterval::Hour, Some(12 * HOUR), None, now).is_err());
+    }
+
+    #[test]
+    fn history_needs_from_before_to() {
+        let now = 10 * HOUR;
+        assert!(history_range(CandleInterval::Hour, Some(5 * HOUR), Some(5 * HOUR), now).is_err());
+        assert!(history_range(CandleInterval::Hour, Some(6 * HOUR), Some(5 * HOUR), now).is_err());
+    }
+
+    #[test]
+    fn history_is_capped_at_max_candles() {
+        let now = 2000 * DAY;
+        assert!(history_range(CandleInterval::Day, Some(now - MAX_CANDLES * DAY), Some(now), now).is_ok());
+        assert!(history_range(CandleInterval::Day, Some(now - (MAX_CANDLES + 1) * DAY), Some(now), now).is_err());
+    }
+
+    #[tokio::test]
+    async fn the_floor_is_the_cheapest_listing_open_at_the_end_of_each_candle() {
+        let index = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let collection = Pubkey::new_unique();
+        let seller = Pubkey::new_unique();
+        let (dear, cheap) = ((Pubkey::new_unique(), Pubkey::new_unique()), (Pubkey::new_unique(), Pubkey::new_unique()));
+        let events = [
+            (100, MarketplaceEvent::Listed { listing: dear.0, nft_mint: dear.1, seller, price: 2000 }),
+            (200, MarketplaceEvent::Listed { listing: cheap.0, nft_mint: cheap.1, seller, price: 1000 }),
+            (HOUR + 100, MarketplaceEvent::Sold { listing: cheap.0, nft_mint: cheap.1, seller, buyer: Pubkey::new_unique(), price: 1000 }),
+        ];
+        for (i, (time, event)) in events.into_iter().enumerate() {
+            let nft = NftRecord { mint: event.nft_mint().to_string(), name: None, symbol: None, uri: None, collection: Some(collection.to_string()), minted: None };
+            let transaction = IndexedTransaction {
+                signature: i.to_string(),
+                slot: time as u64,
+                block_time: Some(time),
+                commitment: Commitment::Finalized,
+                fee: None,
+                fee_payer: None,
+                events: vec![(0, event)],
+            };
+            index.apply(&transaction, &[nft], None).await.unwrap();
+        }
+
+        let now = 2 * HOUR + 10;
+        let range = history_range(CandleInterval::Hour, Some(0), None, now).unwrap();
+        let history = get_collection_history(Some(&index), &collection, CandleInterval::Hour, range, now).await.unwrap();
+        let floors: Vec<_> = history.candles.iter().map(|candle| candle.floor_price).collect();
+        assert_eq!(floors, [Some(lamports_to_sol(1000)), Some(lamports_to_sol(2000)), Some(lamports_to_sol(2000))]);
+        assert_eq!(history.candles[1].sales, 1);
+    }
+}
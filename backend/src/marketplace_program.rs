@@ -0,0 +1,287 @@
+// Typed client for the nft_marketplace Anchor program.
+// Mirrors the instructions, account contexts and accounts declared in
+// contracts/nft_marketplace/src/lib.rs; keep the two in sync.
+use anchor_client::anchor_lang::{
+    self,
+    error::ErrorCode,
+    prelude::{borsh, AccountMeta},
+    AccountDeserialize, AnchorDeserialize, AnchorSerialize, Discriminator, InstructionData,
+    ToAccountMetas,
+};
+use solana_sdk::{instruction::Instruction, pubkey, pubkey::Pubkey};
+use std::{str::FromStr, sync::OnceLock};
+
+// Program id declared by the contract (`declare_id!`)
+pub const DECLARED_ID: Pubkey = pubkey!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
+
+pub const LISTING_SEED: &[u8] = b"listing";
+
+// Deployed program id, overridable with MARKETPLACE_PROGRAM_ID for other clusters
+pub fn program_id() -> Pubkey {
+    static PROGRAM_ID: OnceLock<Pubkey> = OnceLock::new();
+    *PROGRAM_ID.get_or_init(|| {
+        std::env::var("MARKETPLACE_PROGRAM_ID")
+            .ok()
+            .and_then(|id| Pubkey::from_str(&id).ok())
+            .unwrap_or(DECLARED_ID)
+    })
+}
+
+pub fn find_listing_address(nft_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LISTING_SEED, nft_mint.as_ref()], &program_id())
+}
+
+// ==================== ACCOUNTS ====================
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct Listing {
+    pub nft_mint: Pubkey,
+    pub seller: Pubkey,
+    pub price: u64,
+    pub is_active: bool,
+}
+
+impl Listing {
+    // Discriminator plus fields, matching `space` in the ListNft context
+    pub const LEN: usize = 8 + 32 + 32 + 8 + 1;
+}
+
+impl Discriminator for Listing {
+    // sha256("account:Listing")[..8]
+    const DISCRIMINATOR: [u8; 8] = [218, 32, 50, 73, 43, 134, 26, 58];
+}
+
+impl AccountDeserialize for Listing {
+    fn try_deserialize(buf: &mut &[u8]) -> anchor_lang::Result<Self> {
+        if buf.len() < Self::DISCRIMINATOR.len() {
+            return Err(ErrorCode::AccountDiscriminatorNotFound.into());
+        }
+        if buf[..8] != Self::DISCRIMINATOR {
+            return Err(ErrorCode::AccountDiscriminatorMismatch.into());
+        }
+        Self::try_deserialize_unchecked(buf)
+    }
+
+    fn try_deserialize_unchecked(buf: &mut &[u8]) -> anchor_lang::Result<Self> {
+        let mut data: &[u8] = &buf[8..];
+        AnchorDeserialize::deserialize(&mut data)
+            .map_err(|_| ErrorCode::AccountDidNotDeserialize.into())
+    }
+}
+
+// ==================== INSTRUCTION ARGUMENTS ====================
+
+pub mod instruction {
+    use super::*;
+
+    #[derive(AnchorSerialize, AnchorDeserialize)]
+    pub struct ListNft {
+        pub price: u64,
+    }
+
+    impl Discriminator for ListNft {
+        // sha256("global:list_nft")[..8]
+        const DISCRIMINATOR: [u8; 8] = [88, 221, 93, 166, 63, 220, 106, 232];
+    }
+
+    impl InstructionData for ListNft {}
+
+    #[derive(AnchorSerialize, AnchorDeserialize)]
+    pub struct BuyNft;
+
+    impl Discriminator for BuyNft {
+        // sha256("global:buy_nft")[..8]
+        const DISCRIMINATOR: [u8; 8] = [96, 0, 28, 190, 49, 107, 83, 222];
+    }
+
+    impl InstructionData for BuyNft {}
+}
+
+// ==================== ACCOUNT CONTEXTS ====================
+
+pub mod accounts {
+    use super::*;
+
+    pub struct ListNft {
+        pub listing: Pubkey,
+        pub nft_mint: Pubkey,
+        pub seller_token_account: Pubkey,
+        pub escrow_token_account: Pubkey,
+        pub seller: Pubkey,
+        pub token_program: Pubkey,
+        pub associated_token_program: Pubkey,
+        pub system_program: Pubkey,
+        pub rent: Pubkey,
+    }
+
+    impl ToAccountMetas for ListNft {
+        fn to_account_metas(&self, _is_signer: Option<bool>) -> Vec<AccountMeta> {
+            vec![
+                AccountMeta::new(self.listing, false),
+                AccountMeta::new_readonly(self.nft_mint, false),
+                AccountMeta::new(self.seller_token_account, false),
+                AccountMeta::new(self.escrow_token_account, false),
+                AccountMeta::new(self.seller, true),
+                AccountMeta::new_readonly(self.token_program, false),
+                AccountMeta::new_readonly(self.associated_token_program, false),
+                AccountMeta::new_readonly(self.system_program, false),
+                AccountMeta::new_readonly(self.rent, false),
+            ]
+        }
+    }
+
+    pub struct BuyNft {
+        pub listing: Pubkey,
+        pub nft_mint: Pubkey,
+        pub escrow_token_account: Pubkey,
+        pub buyer_token_account: Pubkey,
+        pub seller: Pubkey,
+        pub buyer: Pubkey,
+        pub token_program: Pubkey,
+        pub associated_token_program: Pubkey,
+        pub system_program: Pubkey,
+    }
+
+    impl ToAccountMetas for BuyNft {
+        fn to_account_metas(&self, _is_signer: Option<bool>) -> Vec<AccountMeta> {
+            vec![
+                AccountMeta::new(self.listing, false),
+                AccountMeta::new_readonly(self.nft_mint, false),
+                AccountMeta::new(self.escrow_token_account, false),
+                AccountMeta::new(self.buyer_token_account, false),
+                AccountMeta::new(self.seller, false),
+                AccountMeta::new(self.buyer, true),
+                AccountMeta::new_readonly(self.token_program, false),
+                AccountMeta::new_readonly(self.associated_token_program, false),
+                AccountMeta::new_readonly(self.system_program, false),
+            ]
+        }
+    }
+}
+
+// ==================== INSTRUCTION BUILDERS ====================
+
+// list_nft(price): moves the seller's NFT into escrow owned by the listing PDA
+pub fn list_nft(seller: &Pubkey, nft_mint: &Pubkey, price: u64) -> Instruction {
+    let (listing, _) = find_listing_address(nft_mint);
+    let accounts = accounts::ListNft {
+        listing,
+        nft_mint: *nft_mint,
+        seller_token_account: spl_associated_token_account::get_associated_token_address(seller, nft_mint),
+        escrow_token_account: spl_associated_token_account::get_associated_token_address(&listing, nft_mint),
+        seller: *seller,
+        token_program: spl_token::id(),
+        associated_token_program: spl_associated_token_account::id(),
+        system_program: solana_sdk::system_program::id(),
+        rent: solana_sdk::sysvar::rent::id(),
+    };
+
+    Instruction {
+        program_id: program_id(),
+        accounts: accounts.to_account_metas(None),
+        data: instruction::ListNft { price }.data(),
+    }
+}
+
+// buy_nft(): pays `listing.price` to the seller and releases the NFT from escrow
+pub fn buy_nft(buyer: &Pubkey, listing_address: &Pubkey, listing: &Listing) -> Instruction {
+    let accounts = accounts::BuyNft {
+        listing: *listing_address,
+        nft_mint: listing.nft_mint,
+        escrow_token_account: spl_associated_token_account::get_associated_token_address(listing_address, &listing.nft_mint),
+        buyer_token_account: spl_associated_token_account::get_associated_token_address(buyer, &listing.nft_mint),
+        seller: listing.seller,
+        buyer: *buyer,
+        token_program: spl_token::id(),
+        associated_token_program: spl_associated_token_account::id(),
+        system_program: solana_sdk::system_program::id(),
+    };
+
+    Instruction {
+        program_id: program_id(),
+        accounts: accounts.to_account_metas(None),
+        data: instruction::BuyNft.data(),
+    }
+}
+
+// ==================== INSTRUCTION DECODING ====================
+
+pub enum MarketplaceInstruction {
+    ListNft { listing: Pubkey, nft_mint: Pubkey, seller: Pubkey, price: u64 },
+    BuyNft { listing: Pubkey, nft_mint: Pubkey, seller: Pubkey, buyer: Pubkey },
+}
+
+// Decodes a marketplace instruction from its data and account keys, which are in
+// the order of the account contexts above. `None` for other instructions.
+pub fn decode_instruction(data: &[u8], accounts: &[Pubkey]) -> Option<MarketplaceInstruction> {
+    let discriminator = data.get(..8)?;
+    let mut args = &data[8..];
+    if discriminator == instruction::ListNft::DISCRIMINATOR {
+        let instruction::ListNft { price } = AnchorDeserialize::deserialize(&mut args).ok()?;
+        Some(MarketplaceInstruction::ListNft {
+            listing: *accounts.first()?,
+            nft_mint: *accounts.get(1)?,
+            seller: *accounts.get(4)?,
+            price,
+        })
+    } else if discriminator == instruction::BuyNft::DISCRIMINATOR {
+        Some(MarketplaceInstruction::BuyNft {
+            listing: *accounts.first()?,
+            nft_mint: *accounts.get(1)?,
+            seller: *accounts.get(4)?,
+            buyer: *accounts.get(5)?,
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::hash::hash;
+
+    // Anchor's discriminator: the first 8 bytes of sha256("<namespace>:<name>")
+    fn anchor_discriminator(preimage: &str) -> [u8; 8] {
+        hash(preimage.as_bytes()).to_bytes()[..8].try_into().unwrap()
+    }
+
+    fn keys(instruction: &Instruction) -> Vec<Pubkey> {
+        instruction.accounts.iter().map(|account| account.pubkey).collect()
+    }
+
+    #[test]
+    fn discriminators_match_anchor() {
+        assert_eq!(Listing::DISCRIMINATOR, anchor_discriminator("account:Listing"));
+        assert_eq!(instruction::ListNft::DISCRIMINATOR, anchor_discriminator("global:list_nft"));
+        assert_eq!(instruction::BuyNft::DISCRIMINATOR, anchor_discriminator("global:buy_nft"));
+    }
+
+    #[test]
+    fn built_instructions_decode_to_what_they_were_built_from() {
+        let (seller, buyer, nft_mint) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (listing_address, _) = find_listing_address(&nft_mint);
+
+        let list = list_nft(&seller, &nft_mint, 1_500_000);
+        match decode_instruction(&list.data, &keys(&list)) {
+            Some(MarketplaceInstruction::ListNft { listing, nft_mint: mint, seller: listed_by, price }) => {
+                assert_eq!((listing, mint, listed_by, price), (listing_address, nft_mint, seller, 1_500_000));
+            }
+            _ => panic!("expected list_nft"),
+        }
+
+        let listing = Listing { nft_mint, seller, price: 1_500_000, is_active: true };
+        let buy = buy_nft(&buyer, &listing_address, &listing);
+        match decode_instruction(&buy.data, &keys(&buy)) {
+            Some(MarketplaceInstruction::BuyNft { listing, nft_mint: mint, seller: sold_by, buyer: bought_by }) => {
+                assert_eq!((listing, mint, sold_by, bought_by), (listing_address, nft_mint, seller, buyer));
+            }
+            _ => panic!("expected buy_nft"),
+        }
+
+        // Other instructions, and accounts cut short, decode to nothing
+        assert!(decode_instruction(&[0; 8], &keys(&buy)).is_none());
+        assert!(decode_instruction(&buy.data, &keys(&buy)[..5]).is_none());
+        assert!(decode_instruction(&list.data[..7], &keys(&list)).is_none());
+    }
+}
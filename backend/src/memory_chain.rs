@@ -0,0 +1,1225 @@
+// In-memory ledger implementing `ChainClient`, for offline tests and local
+// development (CHAIN_BACKEND=memory). It runs the instructions the backend builds
+// against a map of accounts: system transfers and account creation, SPL token
+// mints and transfers, associated token accounts, Metaplex metadata and master
+// editions, and the marketplace program's list_nft and buy_nft. Programs may only
+// debit accounts they own, as the runtime enforces. Every accepted transaction
+// lands in a block of its own and is immediately finalized.
+use anchor_client::anchor_lang::{
+    error::ErrorCode, AccountDeserialize, AnchorDeserialize, AnchorSerialize, Discriminator,
+};
+use async_trait::async_trait;
+use base64::Engine;
+use solana_account_decoder::parse_token::token_amount_to_ui_amount;
+use mpl_token_metadata::{
+    accounts::{MasterEdition, Metadata},
+    errors::MplTokenMetadataError,
+    instructions::{CreateMasterEditionV3InstructionArgs, CreateMetadataAccountV3InstructionArgs},
+    types::{Key, TokenStandard},
+};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::{
+        RpcProgramAccountsConfig, RpcSendTransactionConfig, RpcSimulateTransactionConfig,
+        RpcTransactionConfig,
+    },
+    rpc_request::{RpcError, RpcResponseErrorData, TokenAccountsFilter},
+    rpc_response::{
+        Response, RpcConfirmedTransactionStatusWithSignature, RpcPrioritizationFee,
+        RpcTokenAccountBalance, RpcResponseContext, RpcResult, RpcSimulateTransactionResult,
+    },
+};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    clock::MAX_PROCESSING_AGE,
+    commitment_config::CommitmentConfig,
+    hash::{hashv, Hash},
+    instruction::InstructionError,
+    message::Message,
+    program_option::COption,
+    program_pack::Pack,
+    program_utils::limited_deserialize,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::Signature,
+    system_instruction::{SystemError, SystemInstruction},
+    system_program, sysvar,
+    transaction::{Transaction, TransactionError},
+};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction,
+    EncodedTransactionWithStatusMeta, TransactionBinaryEncoding, TransactionConfirmationStatus,
+    TransactionStatus, TransactionStatusMeta, TransactionTokenBalance, UiTransactionStatusMeta,
+};
+use spl_associated_token_account::get_associated_token_address;
+use spl_token::{
+    error::TokenError,
+    instruction::TokenInstruction,
+    state::{Account as TokenAccount, AccountState, Mint as SplMint},
+};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    chain::ChainClient,
+    marketplace_program::{self, find_listing_address, instruction, Listing},
+    nft::{MASTER_EDITION_ACCOUNT_LEN, METADATA_ACCOUNT_LEN},
+};
+
+const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+
+// getTokenLargestAccounts returns at most this many accounts
+const LARGEST_ACCOUNTS_LIMIT: usize = 20;
+
+// JSON-RPC "Invalid params", as returned by a node for an unknown mint
+const JSON_RPC_INVALID_PARAMS: i64 = -32602;
+
+pub struct MemoryChain {
+    ledger: Mutex<Ledger>,
+}
+
+struct Ledger {
+    accounts: HashMap<Pubkey, Account>,
+    transactions: HashMap<Signature, Landed>,
+    block_height: u64,
+}
+
+struct Landed {
+    slot: u64,
+    block_time: i64,
+    transaction: Transaction,
+    meta: TransactionStatusMeta,
+}
+
+impl Default for MemoryChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryChain {
+    pub fn new() -> Self {
+        let rent = Account {
+            lamports: 1,
+            data: bincode::serialize(&Rent::default()).expect("rent serializes"),
+            owner: sysvar::id(),
+            executable: false,
+            rent_epoch: 0,
+        };
+
+        MemoryChain {
+            ledger: Mutex::new(Ledger {
+                accounts: HashMap::from([(sysvar::rent::id(), rent)]),
+                transactions: HashMap::new(),
+                block_height: 1,
+            }),
+        }
+    }
+
+    // Credit `lamports` to a wallet, creating it if needed
+    pub fn airdrop(&self, pubkey: &Pubkey, lamports: u64) {
+        let mut ledger = self.ledger();
+        let account = ledger.accounts.entry(*pubkey)
+            .or_insert_with(|| Account::new(0, 0, &system_program::id()));
+        account.lamports += lamports;
+    }
+
+    // Replace an account outright, for state no instruction can reach (e.g. a
+    // listing left behind by a sale, while purchases always fail)
+    #[cfg(test)]
+    pub fn set_account(&self, pubkey: &Pubkey, account: Account) {
+        self.ledger().accounts.insert(*pubkey, account);
+    }
+
+    fn ledger(&self) -> MutexGuard<'_, Ledger> {
+        self.ledger.lock().unwrap()
+    }
+}
+
+impl Ledger {
+    fn blockhash(&self) -> Hash {
+        hashv(&[b"memory-chain", &self.block_height.to_le_bytes()])
+    }
+
+    fn context(&self) -> RpcResponseContext {
+        RpcResponseContext {
+            slot: self.block_height,
+            api_version: None,
+        }
+    }
+
+    fn status(&self, signature: &Signature) -> Option<TransactionStatus> {
+        self.transactions.get(signature).map(|landed| TransactionStatus {
+            slot: landed.slot,
+            confirmations: None,
+            status: landed.meta.status.clone(),
+            err: landed.meta.status.clone().err(),
+            confirmation_status: Some(TransactionConfirmationStatus::Finalized),
+        })
+    }
+}
+
+fn not_found(what: String) -> ClientError {
+    RpcError::ForUser(what).into()
+}
+
+fn invalid_params(message: String) -> ClientError {
+    RpcError::RpcResponseError {
+        code: JSON_RPC_INVALID_PARAMS,
+        message,
+        data: RpcResponseErrorData::Empty,
+    }.into()
+}
+
+#[async_trait]
+impl ChainClient for MemoryChain {
+    async fn get_balance(&self, pubkey: &Pubkey) -> ClientResult<u64> {
+        Ok(self.ledger().accounts.get(pubkey).map_or(0, |account| account.lamports))
+    }
+
+    async fn get_account(&self, pubkey: &Pubkey) -> ClientResult<Account> {
+        self.ledger().accounts.get(pubkey).cloned()
+            .ok_or_else(|| not_found(format!("AccountNotFound: pubkey={}", pubkey)))
+    }
+
+    async fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> ClientResult<Vec<Option<Account>>> {
+        let ledger = self.ledger();
+        Ok(pubkeys.iter().map(|pubkey| ledger.accounts.get(pubkey).cloned()).collect())
+    }
+
+    async fn get_program_accounts_with_config(
+        &self,
+        program_id: &Pubkey,
+        config: RpcProgramAccountsConfig,
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        let filters = config.filters.unwrap_or_default();
+        let ledger = self.ledger();
+        let mut accounts: Vec<(Pubkey, Account)> = ledger.accounts.iter()
+            .filter(|(_, account)| account.owner == *program_id)
+            .filter(|(_, account)| {
+                let shared = AccountSharedData::from((*account).clone());
+                filters.iter().all(|filter| filter.allows(&shared))
+            })
+            .map(|(pubkey, account)| (*pubkey, account.clone()))
+            .collect();
+        accounts.sort_by_key(|(pubkey, _)| *pubkey);
+        Ok(accounts)
+    }
+
+    // Only the legacy token program runs here, so Token-2022 queries find nothing
+    async fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        filter: TokenAccountsFilter,
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        let ledger = self.ledger();
+        let mut accounts: Vec<(Pubkey, Account)> = ledger.accounts.iter()
+            .filter(|(_, account)| account.owner == spl_token::id())
+            .filter(|(_, account)| {
+                let Ok(token) = TokenAccount::unpack(&account.data) else {
+                    return false;
+                };
+                token.owner == *owner && match filter {
+                    TokenAccountsFilter::Mint(mint) => token.mint == mint,
+                    TokenAccountsFilter::ProgramId(program_id) => program_id == spl_token::id(),
+                }
+            })
+            .map(|(pubkey, account)| (*pubkey, account.clone()))
+            .collect();
+        accounts.sort_by_key(|(pubkey, _)| *pubkey);
+        Ok(accounts)
+    }
+
+    async fn get_token_largest_accounts(&self, mint: &Pubkey) -> ClientResult<Vec<RpcTokenAccountBalance>> {
+        let ledger = self.ledger();
+        let decimals = ledger.accounts.get(mint)
+            .filter(|account| account.owner == spl_token::id())
+            .and_then(|account| SplMint::unpack(&account.data).ok())
+            .ok_or_else(|| invalid_params(format!("Invalid param: could not find mint {}", mint)))?
+            .decimals;
+        let mut balances: Vec<(Pubkey, u64)> = ledger.accounts.iter()
+            .filter(|(_, account)| account.owner == spl_token::id())
+            .filter_map(|(address, account)| {
+                let token = TokenAccount::unpack(&account.data).ok()?;
+                (token.mint == *mint).then_some((*address, token.amount))
+            })
+            .collect();
+        // Largest first, at most 20 like the RPC method
+        balances.sort_by_key(|(address, amount)| (std::cmp::Reverse(*amount), *address));
+        Ok(balances.into_iter()
+            .take(LARGEST_ACCOUNTS_LIMIT)
+            .map(|(address, amount)| RpcTokenAccountBalance {
+                address: address.to_string(),
+                amount: token_amount_to_ui_amount(amount, decimals),
+            })
+            .collect())
+    }
+
+    async fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        Ok(self.ledger().blockhash())
+    }
+
+    async fn get_latest_blockhash_with_commitment(
+        &self,
+        _commitment: CommitmentConfig,
+    ) -> ClientResult<(Hash, u64)> {
+        let ledger = self.ledger();
+        Ok((ledger.blockhash(), ledger.block_height + MAX_PROCESSING_AGE as u64))
+    }
+
+    async fn get_block_height_with_commitment(&self, _commitment: CommitmentConfig) -> ClientResult<u64> {
+        Ok(self.ledger().block_height)
+    }
+
+    // One block per slot, none skipped
+    async fn get_slot_with_commitment(&self, _commitment: CommitmentConfig) -> ClientResult<u64> {
+        Ok(self.ledger().block_height)
+    }
+
+    async fn get_fee_for_message(&self, message: &Message) -> ClientResult<u64> {
+        Ok(fee_for(message))
+    }
+
+    // Nothing competes for block space here, so priority fees are always zero
+    async fn get_recent_prioritization_fees(
+        &self,
+        _addresses: &[Pubkey],
+    ) -> ClientResult<Vec<RpcPrioritizationFee>> {
+        Ok(Vec::new())
+    }
+
+    async fn simulate_transaction_with_config(
+        &self,
+        transaction: &Transaction,
+        config: RpcSimulateTransactionConfig,
+    ) -> RpcResult<RpcSimulateTransactionResult> {
+        if config.sig_verify {
+            transaction.verify().map_err(ClientErrorKind::TransactionError)?;
+        }
+
+        let ledger = self.ledger();
+        let (execution, result) = execute(&ledger.accounts, transaction);
+        Ok(Response {
+            context: ledger.context(),
+            value: RpcSimulateTransactionResult {
+                err: result.err(),
+                logs: Some(execution.logs),
+                accounts: None,
+                units_consumed: Some(execution.units_consumed),
+                return_data: None,
+                inner_instructions: None,
+            },
+        })
+    }
+
+    async fn send_transaction_with_config(
+        &self,
+        transaction: &Transaction,
+        _config: RpcSendTransactionConfig,
+    ) -> ClientResult<Signature> {
+        let signature = *transaction.signatures.first()
+            .ok_or(ClientErrorKind::TransactionError(TransactionError::MissingSignatureForFee))?;
+        transaction.verify().map_err(ClientErrorKind::TransactionError)?;
+
+        let mut ledger = self.ledger();
+        // Rebroadcasts of a transaction that already landed are ignored
+        if ledger.transactions.contains_key(&signature) {
+            return Ok(signature);
+        }
+
+        let (execution, result) = execute(&ledger.accounts, transaction);
+        // Without the fee the transaction is dropped rather than landing as failed
+        if let Err(err @ TransactionError::InsufficientFundsForFee) = result {
+            return Err(ClientErrorKind::TransactionError(err).into());
+        }
+
+        let keys = &transaction.message.account_keys;
+        let balance = |accounts: &HashMap<Pubkey, Account>, key: &Pubkey| {
+            accounts.get(key).map_or(0, |account| account.lamports)
+        };
+        let meta = TransactionStatusMeta {
+            status: result,
+            fee: execution.fee,
+            pre_balances: keys.iter().map(|key| balance(&ledger.accounts, key)).collect(),
+            post_balances: keys.iter().map(|key| balance(&execution.accounts, key)).collect(),
+            pre_token_balances: Some(token_balances(&ledger.accounts, keys)),
+            post_token_balances: Some(token_balances(&execution.accounts, keys)),
+            log_messages: Some(execution.logs),
+            compute_units_consumed: Some(execution.units_consumed),
+            ..TransactionStatusMeta::default()
+        };
+
+        ledger.accounts = execution.accounts;
+        ledger.block_height += 1;
+        let slot = ledger.block_height;
+        ledger.transactions.insert(signature, Landed {
+            slot,
+            block_time: chrono::Utc::now().timestamp(),
+            transaction: transaction.clone(),
+            meta,
+        });
+        Ok(signature)
+    }
+
+    async fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+    ) -> RpcResult<Vec<Option<TransactionStatus>>> {
+        let ledger = self.ledger();
+        Ok(Response {
+            context: ledger.context(),
+            value: signatures.iter().map(|signature| ledger.status(signature)).collect(),
+        })
+    }
+
+    async fn get_signature_statuses_with_history(
+        &self,
+        signatures: &[Signature],
+    ) -> RpcResult<Vec<Option<TransactionStatus>>> {
+        self.get_signature_statuses(signatures).await
+    }
+
+    async fn get_signatures_for_address_with_config(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> ClientResult<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+        let ledger = self.ledger();
+        let slot_of = |signature: Option<Signature>| {
+            signature.and_then(|signature| ledger.transactions.get(&signature)).map(|landed| landed.slot)
+        };
+        let (before, until) = (slot_of(config.before), slot_of(config.until));
+
+        // Newest first, like the RPC method; every transaction has a slot of its own
+        let mut landed: Vec<(&Signature, &Landed)> = ledger.transactions.iter()
+            .filter(|(_, landed)| landed.transaction.message.account_keys.contains(address))
+            .filter(|(_, landed)| before.is_none_or(|slot| landed.slot < slot))
+            .filter(|(_, landed)| until.is_none_or(|slot| landed.slot > slot))
+            .collect();
+        landed.sort_by_key(|(_, landed)| std::cmp::Reverse(landed.slot));
+
+        Ok(landed.into_iter()
+            .take(config.limit.unwrap_or(1000))
+            .map(|(signature, landed)| RpcConfirmedTransactionStatusWithSignature {
+                signature: signature.to_string(),
+                slot: landed.slot,
+                err: landed.meta.status.clone().err(),
+                memo: None,
+                block_time: Some(landed.block_time),
+                confirmation_status: Some(TransactionConfirmationStatus::Finalized),
+            })
+            .collect())
+    }
+
+    async fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        _config: RpcTransactionConfig,
+    ) -> ClientResult<EncodedConfirmedTransactionWithStatusMeta> {
+        let ledger = self.ledger();
+        let landed = ledger.transactions.get(signature)
+            .ok_or_else(|| not_found(format!("Transaction {} not found", signature)))?;
+        let serialized = bincode::serialize(&landed.transaction)
+            .map_err(|e| ClientErrorKind::Custom(e.to_string()))?;
+
+        Ok(EncodedConfirmedTransactionWithStatusMeta {
+            slot: landed.slot,
+            transaction: EncodedTransactionWithStatusMeta {
+                transaction: EncodedTransaction::Binary(
+                    base64::engine::general_purpose::STANDARD.encode(serialized),
+                    TransactionBinaryEncoding::Base64,
+                ),
+                meta: Some(UiTransactionStatusMeta::from(landed.meta.clone())),
+                version: None,
+            },
+            block_time: Some(landed.block_time),
+        })
+    }
+}
+
+// ==================== EXECUTION ====================
+
+type InstructionResult = Result<(), InstructionError>;
+
+// SPL token accounts among the transaction's keys, as the RPC reports them
+fn token_balances(accounts: &HashMap<Pubkey, Account>, keys: &[Pubkey]) -> Vec<TransactionTokenBalance> {
+    keys.iter().enumerate()
+        .filter_map(|(index, key)| {
+            let account = accounts.get(key).filter(|account| account.owner == spl_token::id())?;
+            let token = TokenAccount::unpack(&account.data).ok()?;
+            let decimals = accounts.get(&token.mint)
+                .and_then(|mint| SplMint::unpack(&mint.data).ok())?
+                .decimals;
+            Some(TransactionTokenBalance {
+                account_index: index as u8,
+                mint: token.mint.to_string(),
+                ui_token_amount: token_amount_to_ui_amount(token.amount, decimals),
+                owner: token.owner.to_string(),
+                program_id: spl_token::id().to_string(),
+            })
+        })
+        .collect()
+}
+
+fn fee_for(message: &Message) -> u64 {
+    LAMPORTS_PER_SIGNATURE * message.header.num_required_signatures as u64
+}
+
+// Rough per-instruction costs, in the range the real programs report
+fn compute_units(program_id: &Pubkey) -> u64 {
+    if *program_id == marketplace_program::program_id() {
+        35_000
+    } else if *program_id == mpl_token_metadata::ID {
+        45_000
+    } else if *program_id == spl_associated_token_account::id() {
+        25_000
+    } else if *program_id == spl_token::id() {
+        4_500
+    } else {
+        150
+    }
+}
+
+// A transaction being run against its own copy of the accounts
+struct Execution {
+    accounts: HashMap<Pubkey, Account>,
+    signers: Vec<Pubkey>,
+    rent: Rent,
+    fee: u64,
+    logs: Vec<String>,
+    units_consumed: u64,
+    depth: usize,
+}
+
+// Charge the fee and run every instruction; a failed instruction undoes all of
+// them but keeps the fee, like the runtime does
+fn execute(
+    accounts: &HashMap<Pubkey, Account>,
+    transaction: &Transaction,
+) -> (Execution, Result<(), TransactionError>) {
+    let message = &transaction.message;
+    let mut execution = Execution {
+        accounts: accounts.clone(),
+        signers: message.account_keys.iter()
+            .take(message.header.num_required_signatures as usize)
+            .copied()
+            .collect(),
+        rent: Rent::default(),
+        fee: fee_for(message),
+        logs: Vec::new(),
+        units_consumed: 0,
+        depth: 0,
+    };
+
+    let Some(payer) = message.account_keys.first().copied() else {
+        return (execution, Err(TransactionError::MissingSignatureForFee));
+    };
+    if execution.debit(&payer, execution.fee).is_err() {
+        return (execution, Err(TransactionError::InsufficientFundsForFee));
+    }
+    let after_fee = execution.accounts.clone();
+
+    for (index, ix) in message.instructions.iter().enumerate() {
+        let program_id = message.account_keys[ix.program_id_index as usize];
+        let keys: Vec<Pubkey> = ix.accounts.iter()
+            .map(|&account| message.account_keys[account as usize])
+            .collect();
+
+        execution.units_consumed += compute_units(&program_id);
+        let result = execution.invoke(&program_id, |chain| chain.process(&program_id, &keys, &ix.data));
+        if let Err(err) = result {
+            execution.accounts = after_fee;
+            return (execution, Err(TransactionError::InstructionError(index as u8, err)));
+        }
+    }
+
+    (execution, Ok(()))
+}
+
+fn account_key(keys: &[Pubkey], index: usize) -> Result<Pubkey, InstructionError> {
+    keys.get(index).copied().ok_or(InstructionError::NotEnoughAccountKeys)
+}
+
+fn token_error(error: TokenError) -> InstructionError {
+    InstructionError::Custom(error as u32)
+}
+
+fn metadata_error(error: MplTokenMetadataError) -> InstructionError {
+    InstructionError::Custom(error as u32)
+}
+
+impl Execution {
+    // Run a program, logging its invocation and result the way the runtime does
+    fn invoke(
+        &mut self,
+        program_id: &Pubkey,
+        call: impl FnOnce(&mut Self) -> InstructionResult,
+    ) -> InstructionResult {
+        self.depth += 1;
+        self.logs.push(format!("Program {} invoke [{}]", program_id, self.depth));
+        let result = call(self);
+        match &result {
+            Ok(()) => self.logs.push(format!("Program {} success", program_id)),
+            Err(err) => self.logs.push(format!("Program {} failed: {}", program_id, err)),
+        }
+        self.depth -= 1;
+        result
+    }
+
+    fn process(&mut self, program_id: &Pubkey, keys: &[Pubkey], data: &[u8]) -> InstructionResult {
+        if *program_id == system_program::id() {
+            self.system(keys, data)
+        } else if *program_id == spl_token::id() {
+            self.token(keys, data)
+        } else if *program_id == spl_associated_token_account::id() {
+            self.associated_token(keys, data)
+        } else if *program_id == mpl_token_metadata::ID {
+            self.token_metadata(keys, data)
+        } else if *program_id == marketplace_program::program_id() {
+            self.marketplace(keys, data)
+        } else if *program_id == solana_sdk::compute_budget::id() {
+            Ok(())
+        } else {
+            Err(InstructionError::UnsupportedProgramId)
+        }
+    }
+
+    fn is_signer(&self, key: &Pubkey) -> bool {
+        self.signers.contains(key)
+    }
+
+    fn require_signer(&self, key: &Pubkey) -> InstructionResult {
+        if self.is_signer(key) {
+            Ok(())
+        } else {
+            Err(InstructionError::MissingRequiredSignature)
+        }
+    }
+
+    fn exists(&self, key: &Pubkey) -> bool {
+        self.accounts.get(key).is_some_and(|account| account.lamports > 0 || !account.data.is_empty())
+    }
+
+    fn debit(&mut self, key: &Pubkey, lamports: u64) -> InstructionResult {
+        let balance = self.accounts.get(key).map_or(0, |account| account.lamports);
+        if balance < lamports {
+            self.logs.push(format!("Transfer: insufficient lamports {}, need {}", balance, lamports));
+            return Err(InstructionError::Custom(SystemError::ResultWithNegativeLamports as u32));
+        }
+        if let Some(account) = self.accounts.get_mut(key) {
+            account.lamports -= lamports;
+        }
+        Ok(())
+    }
+
+    // Lamports a program moves by writing account balances itself instead of
+    // calling the system program. Its overflow-checked subtraction panics on an
+    // overdraft, and the runtime only lets a program debit accounts it owns.
+    fn program_transfer(&mut self, program_id: &Pubkey, from: &Pubkey, to: &Pubkey, lamports: u64) -> InstructionResult {
+        let (balance, owner) = self.accounts.get(from)
+            .map_or((0, system_program::id()), |account| (account.lamports, account.owner));
+        if balance < lamports {
+            self.logs.push("Program log: panicked at 'attempt to subtract with overflow'".to_string());
+            return Err(InstructionError::ProgramFailedToComplete);
+        }
+        if owner != *program_id {
+            return Err(InstructionError::ExternalAccountLamportSpend);
+        }
+        self.debit(from, lamports)?;
+        self.credit(to, lamports);
+        Ok(())
+    }
+
+    fn credit(&mut self, key: &Pubkey, lamports: u64) {
+        let account = self.accounts.entry(*key)
+            .or_insert_with(|| Account::new(0, 0, &system_program::id()));
+        account.lamports += lamports;
+    }
+
+    fn create_account(
+        &mut self,
+        payer: &Pubkey,
+        address: &Pubkey,
+        lamports: u64,
+        space: usize,
+        owner: &Pubkey,
+    ) -> InstructionResult {
+        if self.exists(address) {
+            self.logs.push(format!("Create Account: account Address {{ address: {}, base: None }} already in use", address));
+            return Err(InstructionError::Custom(SystemError::AccountAlreadyInUse as u32));
+        }
+        self.debit(payer, lamports)?;
+        self.accounts.insert(*address, Account::new(lamports, space, owner));
+        Ok(())
+    }
+
+    fn mint(&self, key: &Pubkey) -> Result<SplMint, InstructionError> {
+        self.accounts.get(key)
+            .filter(|account| account.owner == spl_token::id())
+            .and_then(|account| SplMint::unpack(&account.data).ok())
+            .ok_or(InstructionError::InvalidAccountData)
+    }
+
+    fn token_account(&self, key: &Pubkey) -> Result<TokenAccount, InstructionError> {
+        self.accounts.get(key)
+            .filter(|account| account.owner == spl_token::id())
+            .and_then(|account| TokenAccount::unpack(&account.data).ok())
+            .ok_or(InstructionError::InvalidAccountData)
+    }
+
+    fn pack<T: Pack>(&mut self, key: &Pubkey, state: T) -> InstructionResult {
+        let account = self.accounts.get_mut(key).ok_or(InstructionError::UninitializedAccount)?;
+        T::pack(state, &mut account.data).map_err(|_| InstructionError::AccountDataTooSmall)
+    }
+
+    fn write(&mut self, key: &Pubkey, bytes: &[u8]) -> InstructionResult {
+        let account = self.accounts.get_mut(key).ok_or(InstructionError::UninitializedAccount)?;
+        account.data.get_mut(..bytes.len())
+            .ok_or(InstructionError::AccountDataTooSmall)?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    // ==================== SYSTEM PROGRAM ====================
+
+    fn system(&mut self, keys: &[Pubkey], data: &[u8]) -> InstructionResult {
+        match limited_deserialize(data)? {
+            SystemInstruction::CreateAccount { lamports, space, owner } => {
+                let (from, to) = (account_key(keys, 0)?, account_key(keys, 1)?);
+                self.require_signer(&from)?;
+                self.require_signer(&to)?;
+                self.create_account(&from, &to, lamports, space as usize, &owner)
+            }
+            SystemInstruction::Transfer { lamports } => {
+                let (from, to) = (account_key(keys, 0)?, account_key(keys, 1)?);
+                self.require_signer(&from)?;
+                self.debit(&from, lamports)?;
+                self.credit(&to, lamports);
+                Ok(())
+            }
+            _ => Err(InstructionError::InvalidInstructionData),
+        }
+    }
+
+    // ==================== TOKEN PROGRAM ====================
+
+    fn token(&mut self, keys: &[Pubkey], data: &[u8]) -> InstructionResult {
+        let instruction = TokenInstruction::unpack(data)
+            .map_err(|_| InstructionError::InvalidInstructionData)?;
+        match instruction {
+            TokenInstruction::InitializeMint { decimals, mint_authority, freeze_authority }
+            | TokenInstruction::InitializeMint2 { decimals, mint_authority, freeze_authority } => {
+                let mint = account_key(keys, 0)?;
+                let account = self.accounts.get(&mint)
+                    .filter(|account| account.owner == spl_token::id() && account.data.len() == SplMint::LEN)
+                    .ok_or(InstructionError::InvalidAccountData)?;
+                if SplMint::unpack_unchecked(&account.data).is_ok_and(|mint| mint.is_initialized) {
+                    return Err(token_error(TokenError::AlreadyInUse));
+                }
+                self.pack(&mint, SplMint {
+                    mint_authority: COption::Some(mint_authority),
+                    supply: 0,
+                    decimals,
+                    is_initialized: true,
+                    freeze_authority,
+                })
+            }
+            TokenInstruction::MintTo { amount } => {
+                let (mint_key, destination, authority) =
+                    (account_key(keys, 0)?, account_key(keys, 1)?, account_key(keys, 2)?);
+                let mut mint = self.mint(&mint_key)?;
+                let mut account = self.token_account(&destination)?;
+                if account.mint != mint_key {
+                    return Err(token_error(TokenError::MintMismatch));
+                }
+                match mint.mint_authority {
+                    COption::None => return Err(token_error(TokenError::FixedSupply)),
+                    COption::Some(expected) if expected != authority => {
+                        return Err(token_error(TokenError::OwnerMismatch));
+                    }
+                    COption::Some(_) => {}
+                }
+                self.require_signer(&authority)?;
+
+                mint.supply = mint.supply.checked_add(amount).ok_or(token_error(TokenError::Overflow))?;
+                account.amount += amount;
+                self.pack(&mint_key, mint)?;
+                self.pack(&destination, account)
+            }
+            TokenInstruction::Transfer { amount } => {
+                let (source, destination, authority) =
+                    (account_key(keys, 0)?, account_key(keys, 1)?, account_key(keys, 2)?);
+                let signed = self.is_signer(&authority);
+                self.transfer_tokens(&source, &destination, &authority, amount, signed)
+            }
+            TokenInstruction::TransferChecked { amount, .. } => {
+                let (source, destination, authority) =
+                    (account_key(keys, 0)?, account_key(keys, 2)?, account_key(keys, 3)?);
+                let signed = self.is_signer(&authority);
+                self.transfer_tokens(&source, &destination, &authority, amount, signed)
+            }
+            _ => Err(InstructionError::InvalidInstructionData),
+        }
+    }
+
+    // `signed` is false unless `authority` signed the transaction or, for a PDA,
+    // the calling program signed with its seeds
+    fn transfer_tokens(
+        &mut self,
+        source: &Pubkey,
+        destination: &Pubkey,
+        authority: &Pubkey,
+        amount: u64,
+        signed: bool,
+    ) -> InstructionResult {
+        let mut from = self.token_account(source)?;
+        let mut to = self.token_account(destination)?;
+        if from.mint != to.mint {
+            return Err(token_error(TokenError::MintMismatch));
+        }
+        if from.owner != *authority {
+            return Err(token_error(TokenError::OwnerMismatch));
+        }
+        if !signed {
+            return Err(InstructionError::MissingRequiredSignature);
+        }
+        if from.amount < amount {
+            return Err(token_error(TokenError::InsufficientFunds));
+        }
+        if source == destination {
+            return Ok(());
+        }
+
+        from.amount -= amount;
+        to.amount += amount;
+        self.pack(source, from)?;
+        self.pack(destination, to)
+    }
+
+    // ==================== ASSOCIATED TOKEN ACCOUNT PROGRAM ====================
+
+    fn associated_token(&mut self, keys: &[Pubkey], data: &[u8]) -> InstructionResult {
+        let idempotent = match data {
+            [] | [0] => false,
+            [1] => true,
+            _ => return Err(InstructionError::InvalidInstructionData),
+        };
+        let (payer, address, wallet, mint) =
+            (account_key(keys, 0)?, account_key(keys, 1)?, account_key(keys, 2)?, account_key(keys, 3)?);
+        self.require_signer(&payer)?;
+        self.create_associated_token_account(&payer, &address, &wallet, &mint, idempotent)
+    }
+
+    fn create_associated_token_account(
+        &mut self,
+        payer: &Pubkey,
+        address: &Pubkey,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        idempotent: bool,
+    ) -> InstructionResult {
+        if *address != get_associated_token_address(wallet, mint) {
+            return Err(InstructionError::InvalidSeeds);
+        }
+        if idempotent && self.token_account(address).is_ok_and(|token| token.owner == *wallet && token.mint == *mint) {
+            return Ok(());
+        }
+        self.mint(mint)?;
+
+        let lamports = self.rent.minimum_balance(TokenAccount::LEN);
+        self.invoke(&system_program::id(), |chain| {
+            chain.create_account(payer, address, lamports, TokenAccount::LEN, &spl_token::id())
+        })?;
+        self.pack(address, TokenAccount {
+            mint: *mint,
+            owner: *wallet,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        })
+    }
+
+    // ==================== TOKEN METADATA PROGRAM ====================
+
+    fn token_metadata(&mut self, keys: &[Pubkey], data: &[u8]) -> InstructionResult {
+        let unpack_error = || metadata_error(MplTokenMetadataError::InstructionUnpackError);
+        match data.split_first() {
+            Some((33, mut args)) => {
+                let args = CreateMetadataAccountV3InstructionArgs::deserialize(&mut args)
+                    .map_err(|_| unpack_error())?;
+                self.create_metadata(keys, args)
+            }
+            Some((17, mut args)) => {
+                let args = CreateMasterEditionV3InstructionArgs::deserialize(&mut args)
+                    .map_err(|_| unpack_error())?;
+                self.create_master_edition(keys, args)
+            }
+            _ => Err(InstructionError::InvalidInstructionData),
+        }
+    }
+
+    fn create_metadata(&mut self, keys: &[Pubkey], args: CreateMetadataAccountV3InstructionArgs) -> InstructionResult {
+        let (metadata, mint_key, mint_authority, payer, update_authority) = (
+            account_key(keys, 0)?,
+            account_key(keys, 1)?,
+            account_key(keys, 2)?,
+            account_key(keys, 3)?,
+            account_key(keys, 4)?,
+        );
+        if metadata != Metadata::find_pda(&mint_key).0 {
+            return Err(metadata_error(MplTokenMetadataError::InvalidMetadataKey));
+        }
+        if self.exists(&metadata) {
+            return Err(metadata_error(MplTokenMetadataError::AlreadyInitialized));
+        }
+        let mint = self.mint(&mint_key)?;
+        if mint.mint_authority != COption::Some(mint_authority) {
+            return Err(metadata_error(MplTokenMetadataError::InvalidMintAuthority));
+        }
+        if !self.is_signer(&mint_authority) {
+            return Err(metadata_error(MplTokenMetadataError::NotMintAuthority));
+        }
+
+        let data = args.data;
+        if data.name.len() > mpl_token_metadata::MAX_NAME_LENGTH {
+            return Err(metadata_error(MplTokenMetadataError::NameTooLong));
+        }
+        if data.symbol.len() > mpl_token_metadata::MAX_SYMBOL_LENGTH {
+            return Err(metadata_error(MplTokenMetadataError::SymbolTooLong));
+        }
+        if data.uri.len() > mpl_token_metadata::MAX_URI_LENGTH {
+            return Err(metadata_error(MplTokenMetadataError::UriTooLong));
+        }
+
+        let lamports = self.rent.minimum_balance(METADATA_ACCOUNT_LEN);
+        self.invoke(&system_program::id(), |chain| {
+            chain.create_account(&payer, &metadata, lamports, METADATA_ACCOUNT_LEN, &mpl_token_metadata::ID)
+        })?;
+
+        let state = Metadata {
+            key: Key::MetadataV1,
+            update_authority,
+            mint: mint_key,
+            name: data.name,
+            symbol: data.symbol,
+            uri: data.uri,
+            seller_fee_basis_points: data.seller_fee_basis_points,
+            creators: data.creators,
+            primary_sale_happened: false,
+            is_mutable: args.is_mutable,
+            edition_nonce: Some(MasterEdition::find_pda(&mint_key).1),
+            // Becomes NonFungible once a master edition is created
+            token_standard: Some(if mint.decimals == 0 {
+                TokenStandard::FungibleAsset
+            } else {
+                TokenStandard::Fungible
+            }),
+            collection: data.collection,
+            uses: data.uses,
+            collection_details: args.collection_details,
+            programmable_config: None,
+        };
+        self.write_metadata(&metadata, &state)
+    }
+
+    fn create_master_edition(&mut self, keys: &[Pubkey], args: CreateMasterEditionV3InstructionArgs) -> InstructionResult {
+        let (edition, mint_key, update_authority, mint_authority, payer, metadata) = (
+            account_key(keys, 0)?,
+            account_key(keys, 1)?,
+            account_key(keys, 2)?,
+            account_key(keys, 3)?,
+            account_key(keys, 4)?,
+            account_key(keys, 5)?,
+        );
+        if edition != MasterEdition::find_pda(&mint_key).0 {
+            return Err(metadata_error(MplTokenMetadataError::InvalidEditionKey));
+        }
+        if self.exists(&edition) {
+            return Err(metadata_error(MplTokenMetadataError::AlreadyInitialized));
+        }
+
+        let mut state = self.accounts.get(&metadata)
+            .filter(|account| account.owner == mpl_token_metadata::ID)
+            .and_then(|account| Metadata::from_bytes(&account.data).ok())
+            .ok_or(metadata_error(MplTokenMetadataError::Uninitialized))?;
+        if state.mint != mint_key {
+            return Err(metadata_error(MplTokenMetadataError::MintMismatch));
+        }
+        if state.update_authority != update_authority {
+            return Err(metadata_error(MplTokenMetadataError::UpdateAuthorityIncorrect));
+        }
+        if !self.is_signer(&update_authority) {
+            return Err(metadata_error(MplTokenMetadataError::UpdateAuthorityIsNotSigner));
+        }
+
+        let mut mint = self.mint(&mint_key)?;
+        if mint.mint_authority != COption::Some(mint_authority) {
+            return Err(metadata_error(MplTokenMetadataError::InvalidMintAuthority));
+        }
+        if !self.is_signer(&mint_authority) {
+            return Err(metadata_error(MplTokenMetadataError::NotMintAuthority));
+        }
+        if mint.supply != 1 || mint.decimals != 0 {
+            return Err(metadata_error(MplTokenMetadataError::EditionsMustHaveExactlyOneToken));
+        }
+
+        let lamports = self.rent.minimum_balance(MASTER_EDITION_ACCOUNT_LEN);
+        self.invoke(&system_program::id(), |chain| {
+            chain.create_account(&payer, &edition, lamports, MASTER_EDITION_ACCOUNT_LEN, &mpl_token_metadata::ID)
+        })?;
+        let master_edition = MasterEdition {
+            key: Key::MasterEditionV2,
+            supply: 0,
+            max_supply: args.max_supply,
+        };
+        let bytes = master_edition.try_to_vec().map_err(|_| InstructionError::InvalidAccountData)?;
+        self.write(&edition, &bytes)?;
+
+        // The edition takes over both mint authorities, so no more tokens can be minted
+        mint.mint_authority = COption::Some(edition);
+        mint.freeze_authority = COption::Some(edition);
+        self.pack(&mint_key, mint)?;
+
+        state.token_standard = Some(TokenStandard::NonFungible);
+        self.write_metadata(&metadata, &state)
+    }
+
+    fn write_metadata(&mut self, key: &Pubkey, metadata: &Metadata) -> InstructionResult {
+        let bytes = metadata.try_to_vec().map_err(|_| InstructionError::InvalidAccountData)?;
+        self.write(key, &bytes)
+    }
+
+    // ==================== MARKETPLACE PROGRAM ====================
+
+    // Fail the way Anchor does: log the AnchorError line, return its error number
+    fn anchor_error(&mut self, account: &str, code: ErrorCode) -> InstructionError {
+        let number: u32 = code.into();
+        self.logs.push(format!(
+            "Program log: AnchorError caused by account: {}. Error Code: {:?}. Error Number: {}. Error Message: {}.",
+            account, code, number, code
+        ));
+        InstructionError::Custom(number)
+    }
+
+    fn marketplace(&mut self, keys: &[Pubkey], data: &[u8]) -> InstructionResult {
+        if data.len() < 8 {
+            return Err(InstructionError::Custom(ErrorCode::InstructionMissing.into()));
+        }
+        let (discriminator, mut args) = data.split_at(8);
+        if discriminator == instruction::ListNft::DISCRIMINATOR {
+            let args = instruction::ListNft::deserialize(&mut args)
+                .map_err(|_| InstructionError::Custom(ErrorCode::InstructionDidNotDeserialize.into()))?;
+            self.list_nft(keys, args.price)
+        } else if discriminator == instruction::BuyNft::DISCRIMINATOR {
+            self.buy_nft(keys)
+        } else {
+            Err(InstructionError::Custom(ErrorCode::InstructionFallbackNotFound.into()))
+        }
+    }
+
+    fn list_nft(&mut self, keys: &[Pubkey], price: u64) -> InstructionResult {
+        let (listing_key, nft_mint, seller_token_account, escrow_token_account, seller) = (
+            account_key(keys, 0)?,
+            account_key(keys, 1)?,
+            account_key(keys, 2)?,
+            account_key(keys, 3)?,
+            account_key(keys, 4)?,
+        );
+        if listing_key != find_listing_address(&nft_mint).0 {
+            return Err(self.anchor_error("listing", ErrorCode::ConstraintSeeds));
+        }
+        if self.mint(&nft_mint).is_err() {
+            return Err(self.anchor_error("nft_mint", ErrorCode::AccountNotInitialized));
+        }
+        if seller_token_account != get_associated_token_address(&seller, &nft_mint) {
+            return Err(self.anchor_error("seller_token_account", ErrorCode::ConstraintAssociated));
+        }
+        if escrow_token_account != get_associated_token_address(&listing_key, &nft_mint) {
+            return Err(self.anchor_error("escrow_token_account", ErrorCode::ConstraintAssociated));
+        }
+        if !self.is_signer(&seller) {
+            return Err(self.anchor_error("seller", ErrorCode::AccountNotSigner));
+        }
+
+        // `init` creates the listing and the escrow account before the handler runs
+        let lamports = self.rent.minimum_balance(Listing::LEN);
+        self.invoke(&system_program::id(), |chain| {
+            chain.create_account(&seller, &listing_key, lamports, Listing::LEN, &marketplace_program::program_id())
+        })?;
+        self.invoke(&spl_associated_token_account::id(), |chain| {
+            chain.create_associated_token_account(&seller, &escrow_token_account, &listing_key, &nft_mint, false)
+        })?;
+
+        let listing = Listing {
+            nft_mint,
+            seller,
+            price,
+            is_active: true,
+        };
+        self.write_listing(&listing_key, &listing)?;
+
+        self.invoke(&spl_token::id(), |chain| {
+            chain.transfer_tokens(&seller_token_account, &escrow_token_account, &seller, 1, true)
+        })
+    }
+
+    fn buy_nft(&mut self, keys: &[Pubkey]) -> InstructionResult {
+        let (listing_key, nft_mint, escrow_token_account, buyer_token_account, seller, buyer) = (
+            account_key(keys, 0)?,
+            account_key(keys, 1)?,
+            account_key(keys, 2)?,
+            account_key(keys, 3)?,
+            account_key(keys, 4)?,
+            account_key(keys, 5)?,
+        );
+
+        let Some(account) = self.accounts.get(&listing_key).cloned() else {
+            return Err(self.anchor_error("listing", ErrorCode::AccountNotInitialized));
+        };
+        if account.owner != marketplace_program::program_id() {
+            return Err(self.anchor_error("listing", ErrorCode::AccountOwnedByWrongProgram));
+        }
+        let mut listing = match Listing::try_deserialize(&mut account.data.as_slice()) {
+            Ok(listing) => listing,
+            Err(_) => return Err(self.anchor_error("listing", ErrorCode::AccountDiscriminatorMismatch)),
+        };
+        if listing_key != find_listing_address(&nft_mint).0 {
+            return Err(self.anchor_error("listing", ErrorCode::ConstraintSeeds));
+        }
+        if !listing.is_active {
+            return Err(self.anchor_error("listing", ErrorCode::ConstraintRaw));
+        }
+        if escrow_token_account != get_associated_token_address(&listing_key, &nft_mint) {
+            return Err(self.anchor_error("escrow_token_account", ErrorCode::ConstraintAssociated));
+        }
+        if buyer_token_account != get_associated_token_address(&buyer, &nft_mint) {
+            return Err(self.anchor_error("buyer_token_account", ErrorCode::ConstraintAssociated));
+        }
+        if seller != listing.seller {
+            return Err(self.anchor_error("seller", ErrorCode::ConstraintAddress));
+        }
+        if !self.is_signer(&buyer) {
+            return Err(self.anchor_error("buyer", ErrorCode::AccountNotSigner));
+        }
+
+        // `init_if_needed` on the buyer's token account
+        if !self.exists(&buyer_token_account) {
+            self.invoke(&spl_associated_token_account::id(), |chain| {
+                chain.create_associated_token_account(&buyer, &buyer_token_account, &buyer, &nft_mint, false)
+            })?;
+        }
+
+        // The program moves the price straight from the buyer to the seller
+        // (contracts/nft_marketplace/src/lib.rs). The buyer is a system account, so
+        // the runtime rejects this and every purchase fails, as it does on chain.
+        self.program_transfer(&marketplace_program::program_id(), &buyer, &seller, listing.price)?;
+
+        // Signed with the listing PDA's seeds
+        self.invoke(&spl_token::id(), |chain| {
+            chain.transfer_tokens(&escrow_token_account, &buyer_token_account, &listing_key, 1, true)
+        })?;
+
+        listing.is_active = false;
+        self.write_listing(&listing_key, &listing)
+    }
+
+    fn write_listing(&mut self, key: &Pubkey, listing: &Listing) -> InstructionResult {
+        let mut bytes = Listing::DISCRIMINATOR.to_vec();
+        listing.serialize(&mut bytes).map_err(|_| InstructionError::InvalidAccountData)?;
+        self.write(key, &bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{instruction::Instruction, native_token::LAMPORTS_PER_SOL, signature::{Keypair, Signer}};
+
+    async fn send(chain: &MemoryChain, instructions: &[Instruction], signers: &[&Keypair]) -> Result<(), TransactionError> {
+        let blockhash = chain.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(instructions, Some(&signers[0].pubkey()), signers, blockhash);
+        let signature = chain.send_transaction_with_config(&transaction, RpcSendTransactionConfig::default()).await.unwrap();
+        chain.ledger().status(&signature).unwrap().status
+    }
+
+    // An NFT minted to and paid for by `owner`
+    async fn mint_nft(chain: &MemoryChain, owner: &Keypair) -> Pubkey {
+        let mint = Keypair::new();
+        let data = mpl_token_metadata::types::DataV2 {
+            name: "Test".to_string(),
+            symbol: "TST".to_string(),
+            uri: "https://example.com/nft.json".to_string(),
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: None,
+            uses: None,
+        };
+        let rent = Rent::default().minimum_balance(SplMint::LEN);
+        let instructions = crate::nft::mint_instructions(&owner.pubkey(), &owner.pubkey(), &mint.pubkey(), rent, data).unwrap();
+        send(chain, &instructions, &[owner, &mint]).await.unwrap();
+        mint.pubkey()
+    }
+
+    async fn balance(chain: &MemoryChain, wallet: &Keypair) -> u64 {
+        chain.get_balance(&wallet.pubkey()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn buy_fails_because_the_program_debits_the_buyer_wallet() {
+        let chain = MemoryChain::new();
+        let (seller, buyer) = (Keypair::new(), Keypair::new());
+        chain.airdrop(&seller.pubkey(), 10 * LAMPORTS_PER_SOL);
+        chain.airdrop(&buyer.pubkey(), 10 * LAMPORTS_PER_SOL);
+
+        let nft = mint_nft(&chain, &seller).await;
+        send(&chain, &[marketplace_program::list_nft(&seller.pubkey(), &nft, LAMPORTS_PER_SOL)], &[&seller]).await.unwrap();
+        let (listing_address, _) = find_listing_address(&nft);
+        let account = chain.get_account(&listing_address).await.unwrap();
+        let listing = Listing::try_deserialize(&mut account.data.as_slice()).unwrap();
+
+        let seller_balance = balance(&chain, &seller).await;
+        let result = send(&chain, &[marketplace_program::buy_nft(&buyer.pubkey(), &listing_address, &listing)], &[&buyer]).await;
+        assert_eq!(result, Err(TransactionError::InstructionError(0, InstructionError::ExternalAccountLamportSpend)));
+
+        // Only the fee is charged, and the NFT stays in escrow
+        assert_eq!(balance(&chain, &buyer).await, 10 * LAMPORTS_PER_SOL - LAMPORTS_PER_SIGNATURE);
+        assert_eq!(balance(&chain, &seller).await, seller_balance);
+        let escrow = chain.get_account(&get_associated_token_address(&listing_address, &nft)).await.unwrap();
+        assert_eq!(TokenAccount::unpack(&escrow.data).unwrap().amount, 1);
+    }
+
+    #[test]
+    fn programs_only_debit_accounts_they_own() {
+        let program_id = marketplace_program::program_id();
+        let (owned, wallet, other) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut execution = Execution {
+            accounts: HashMap::from([
+                (owned, Account::new(100, 0, &program_id)),
+                (wallet, Account::new(100, 0, &system_program::id())),
+            ]),
+            signers: vec![wallet],
+            rent: Rent::default(),
+            fee: 0,
+            logs: Vec::new(),
+            units_consumed: 0,
+            depth: 0,
+        };
+
+        assert_eq!(execution.program_transfer(&program_id, &owned, &other, 40), Ok(()));
+        assert_eq!(execution.accounts[&owned].lamports, 60);
+        assert_eq!(execution.accounts[&other].lamports, 40);
+
+        // Signing the transaction doesn't let another program's account be debited
+        assert_eq!(
+            execution.program_transfer(&program_id, &wallet, &other, 40),
+            Err(InstructionError::ExternalAccountLamportSpend)
+        );
+        assert_eq!(
+            execution.program_transfer(&program_id, &owned, &other, 61),
+            Err(InstructionError::ProgramFailedToComplete)
+        );
+        assert_eq!(execution.accounts[&wallet].lamports, 100);
+    }
+}
@@ -1,12 +1,22 @@
 use serde::{Deserialize, Serialize};
-use solana_sdk::{pubkey::Pubkey, signature::{Keypair, Signer}, system_instruction, transaction::Transaction, program_pack::Pack};
+use solana_sdk::{pubkey::Pubkey, signature::{Keypair, Signer}, system_instruction, program_pack::Pack};
+use solana_sdk::{instruction::Instruction, message::Message, native_token::lamports_to_sol, rent::Rent};
 use spl_associated_token_account::instruction as ata_instruction;
 use spl_token::{instruction as token_instruction, state::Mint as SplMint};
 use mpl_token_metadata::instructions as mpl_instruction;
 use mpl_token_metadata::types::DataV2;
+use solana_sdk::{commitment_config::CommitmentConfig, program_utils::limited_deserialize, signature::Signature, system_instruction::SystemInstruction};
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_transaction_status::UiTransactionEncoding;
 use std::{str::FromStr, sync::Arc, collections::HashMap};
 
+use crate::chain::{rfc3339, ChainClient};
 use crate::freepik_api::FreepikApiClient;
+use crate::marketplace_program;
+use anchor_client::anchor_lang::AccountDeserialize;
+use crate::compute_budget;
+use crate::fee_payments::FeePaymentLedger;
+use crate::transactions::{self, PreparedTransaction, TransactionError, TransactionTracker};
 use utoipa::ToSchema;
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -14,7 +24,9 @@ pub struct FeeBreakdown {
     pub mint_account_rent: u64,
     pub metadata_account_rent: u64,
     pub master_edition_rent: u64,
-    pub transaction_fee: u64,
+    pub token_account_rent: u64, // Creator's associated token account
+    pub transaction_fee: u64, // Signature fees of the mint message
+    pub priority_fee: u64, // Compute unit price x compute unit limit
     pub total_minting_cost: u64,
     pub platform_fee: u64,
     pub total_fee: u64,
@@ -29,6 +41,7 @@ pub struct MintNftRequest {
     pub signature: String,
     pub message: String,
     pub fee_payment_signature: Option<String>, // Signature for fee payment transaction
+    pub fee_quote: Option<String>, // `prepaid_fee.quote` from the fee estimate the payment was made for
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -41,6 +54,7 @@ pub struct GenerateAndMintNftRequest {
     pub signature: String,
     pub message: String,
     pub fee_payment_signature: Option<String>, // Signature for fee payment transaction
+    pub fee_quote: Option<String>,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -48,195 +62,386 @@ pub struct MintNftResponse {
     pub nft_address: String,
     pub transaction_signature: String,
     pub fee_breakdown: FeeBreakdown,
+    pub transaction: Option<PreparedTransaction>, // Partially signed by the platform; the creator signs and submits it
 }
 
 #[derive(Serialize, ToSchema)]
 pub struct FeeEstimateResponse {
-    pub fee_breakdown: FeeBreakdown,
+    pub fee_breakdown: FeeBreakdown, // Co-signed mint, fee collected inside the mint transaction
+    pub prepaid_fee: FeeQuote, // Mint funded by an earlier `fee_payment_signature`
     pub platform_wallet: String,
+    pub actions: Vec<ActionFeeEstimate>, // Marketplace actions
+}
+
+/// Fee for a prepaid mint, signed by the platform wallet so the mint is held to
+/// this amount rather than to fees recomputed when it is requested
+#[derive(Serialize, ToSchema)]
+pub struct FeeQuote {
+    pub fee_breakdown: FeeBreakdown,
+    pub quote: String, // Pass as `fee_quote` with the mint request
+    pub expires_at: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ActionFeeEstimate {
+    pub action: String, // list_nft, buy_nft, cancel_listing, make_offer
+    pub supported: bool, // Whether the marketplace program has an instruction for it
+    pub rent: u64, // Rent for accounts the action creates, paid by the signer
+    pub transaction_fee: u64,
+    pub priority_fee: u64,
+    pub total: u64, // Excludes the NFT price for purchases
+    pub note: Option<String>,
 }
 
-// Calculate minting fees
+// Sizes of the accounts the Token Metadata program allocates, from its
+// MAX_METADATA_LEN and MAX_MASTER_EDITION_LEN
+const METADATA_DATA_LEN: usize = 4 + mpl_token_metadata::MAX_NAME_LENGTH
+    + 4 + mpl_token_metadata::MAX_SYMBOL_LENGTH
+    + 4 + mpl_token_metadata::MAX_URI_LENGTH
+    + 2 + 1 + 4 + mpl_token_metadata::MAX_CREATOR_LIMIT * mpl_token_metadata::MAX_CREATOR_LEN;
+pub(crate) const METADATA_ACCOUNT_LEN: usize = 1 + 32 + 32 + METADATA_DATA_LEN + 1 + 1 + 9 + 172;
+pub(crate) const MASTER_EDITION_ACCOUNT_LEN: usize = 1 + 9 + 8 + 264;
+
+// Current rent parameters, read once from the sysvar instead of per account size
+async fn rent(client: &dyn ChainClient) -> Result<Rent, String> {
+    let account = client.get_account(&solana_sdk::sysvar::rent::id()).await
+        .map_err(|e| format!("Failed to get rent sysvar: {}", e))?;
+    bincode::deserialize(&account.data)
+        .map_err(|e| format!("Failed to decode rent sysvar: {}", e))
+}
+
+// Signature fees the cluster charges for a message built from `instructions`,
+// excluding compute budget instructions (priority fees are reported separately)
+async fn network_fee(
+    client: &dyn ChainClient,
+    instructions: &[Instruction],
+    payer: &Pubkey,
+) -> Result<u64, String> {
+    let blockhash = client.get_latest_blockhash().await
+        .map_err(|e| format!("Failed to get blockhash: {}", e))?;
+    let message = Message::new_with_blockhash(instructions, Some(payer), &blockhash);
+    client.get_fee_for_message(&message).await
+        .map_err(|e| format!("Failed to get fee for message: {}", e))
+}
+
+// How a mint's fee reaches the platform wallet, which decides who signs the mint
+#[derive(Clone, Copy, PartialEq)]
+pub enum MintFunding {
+    // Paid beforehand; the payer and the mint account sign
+    Prepaid,
+    // Transferred inside the mint transaction, which the creator also signs
+    CoSigned,
+}
+
+// Calculate minting fees from the mint transaction the backend builds for `payer`
 pub async fn calculate_minting_fees(
-    client: Arc<solana_client::rpc_client::RpcClient>,
+    client: Arc<dyn ChainClient>,
+    payer: &Pubkey,
+    funding: MintFunding,
 ) -> Result<FeeBreakdown, String> {
-    // Get rent exemption amounts for different account types
-    let mint_account_rent = client
-        .get_minimum_balance_for_rent_exemption(SplMint::LEN)
-        .map_err(|e| format!("Failed to get mint account rent: {}", e))?;
-    
-    // Metadata account size (approximate)
-    let metadata_account_rent = client
-        .get_minimum_balance_for_rent_exemption(679) // Standard metadata account size
-        .map_err(|e| format!("Failed to get metadata account rent: {}", e))?;
-    
-    // Master edition account size
-    let master_edition_rent = client
-        .get_minimum_balance_for_rent_exemption(282) // Standard master edition size
-        .map_err(|e| format!("Failed to get master edition rent: {}", e))?;
-    
-    // Estimate transaction fee (5000 lamports is typical for complex transactions)
-    let transaction_fee = 5000u64;
-    
-    let total_minting_cost = mint_account_rent + metadata_account_rent + master_edition_rent + transaction_fee;
-    
-    // Platform fee: 10% of minting cost
-    let platform_fee = total_minting_cost / 10;
-    
-    let total_fee = total_minting_cost + platform_fee;
-    
-    Ok(FeeBreakdown {
+    let rent = rent(client.as_ref()).await?;
+    let mint_account_rent = rent.minimum_balance(SplMint::LEN);
+    let metadata_account_rent = rent.minimum_balance(METADATA_ACCOUNT_LEN);
+    let master_edition_rent = rent.minimum_balance(MASTER_EDITION_ACCOUNT_LEN);
+    let token_account_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    // Same instructions and signers as the mint: payer and mint, plus the creator
+    // when the fee transfer is part of it
+    let creator = Pubkey::new_unique();
+    let mut instructions = mint_instructions(
+        payer,
+        &creator,
+        &Pubkey::new_unique(),
+        mint_account_rent,
+        DataV2 {
+            name: String::new(),
+            symbol: String::new(),
+            uri: String::new(),
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: None,
+            uses: None,
+        },
+    )?;
+    if funding == MintFunding::CoSigned {
+        instructions.push(system_instruction::transfer(&creator, payer, 0));
+    }
+    let transaction_fee = network_fee(client.as_ref(), &instructions, payer).await?;
+
+    // Priority fee at current network prices for the configured mint compute budget
+    let priority_fee = compute_budget::estimate(client.as_ref(), "mint_nft").await?.priority_fee();
+
+    Ok(FeeBreakdown::new(
         mint_account_rent,
         metadata_account_rent,
         master_edition_rent,
+        token_account_rent,
         transaction_fee,
-        total_minting_cost,
-        platform_fee,
-        total_fee,
-    })
+        priority_fee,
+    ))
 }
 
-// Get fee estimate endpoint
-pub async fn get_fee_estimate(
-    client: Arc<solana_client::rpc_client::RpcClient>,
-    platform_keypair: &Keypair,
-) -> Result<FeeEstimateResponse, String> {
-    let fee_breakdown = calculate_minting_fees(client).await?;
-    
-    Ok(FeeEstimateResponse {
-        fee_breakdown,
-        platform_wallet: platform_keypair.pubkey().to_string(),
-    })
-}
-
-async fn upload_to_ipfs(data: &[u8], _content_type: &str) -> Result<String, String> {
-    // For now, use a simple approach that doesn't make the transaction too large
-    // We'll create a mock IPFS URL that points to the actual image
-    let metadata_str = String::from_utf8_lossy(data);
-    
-    // Extract the image URL from the metadata JSON
-    if let Ok(metadata_json) = serde_json::from_str::<serde_json::Value>(&metadata_str) {
-        if let Some(image_url) = metadata_json.get("image").and_then(|v| v.as_str()) {
-            // Create a simple hash from the image URL for a mock IPFS hash
-            use std::collections::hash_map::DefaultHasher;
-            use std::hash::{Hash, Hasher};
-            
-            let mut hasher = DefaultHasher::new();
-            image_url.hash(&mut hasher);
-            let hash = hasher.finish();
-            
-            // Return a mock IPFS URL - in production, this would be a real IPFS upload
-            return Ok(format!("https://ipfs.io/ipfs/Qm{:x}", hash));
+impl FeeBreakdown {
+    fn new(
+        mint_account_rent: u64,
+        metadata_account_rent: u64,
+        master_edition_rent: u64,
+        token_account_rent: u64,
+        transaction_fee: u64,
+        priority_fee: u64,
+    ) -> Self {
+        let total_minting_cost = mint_account_rent + metadata_account_rent + master_edition_rent
+            + token_account_rent + transaction_fee + priority_fee;
+
+        // Platform fee: 10% of minting cost
+        let platform_fee = total_minting_cost / 10;
+
+        let total_fee = total_minting_cost + platform_fee;
+
+        FeeBreakdown {
+            mint_account_rent,
+            metadata_account_rent,
+            master_edition_rent,
+            token_account_rent,
+            transaction_fee,
+            priority_fee,
+            total_minting_cost,
+            platform_fee,
+            total_fee,
         }
     }
-    
-    // Fallback to a simple mock URL
-    Ok("https://ipfs.io/ipfs/QmTestMetadata".to_string())
+
+    // Same breakdown with the priority fee of the transaction actually built
+    fn with_priority_fee(&self, priority_fee: u64) -> Self {
+        FeeBreakdown::new(
+            self.mint_account_rent,
+            self.metadata_account_rent,
+            self.master_edition_rent,
+            self.token_account_rent,
+            self.transaction_fee,
+            priority_fee,
+        )
+    }
 }
 
-pub async fn mint_nft(
-    client: Arc<solana_client::rpc_client::RpcClient>,
-    keypair: &solana_sdk::signature::Keypair,
-    req: MintNftRequest,
-    url_mappings: Arc<tokio::sync::RwLock<HashMap<String, String>>>,
-) -> Result<MintNftResponse, String> {
-    // Validate inputs
-    if req.name.is_empty() || req.symbol.is_empty() || req.uri.is_empty() {
-        return Err("Invalid input: name, symbol, and uri are required".to_string());
+// Maximum age of a fee payment transaction that can still fund a mint
+const FEE_PAYMENT_MAX_AGE_SECS: i64 = 600;
+
+// A quote lives as long as the payment made for it can fund a mint
+const FEE_QUOTE_TTL_SECS: i64 = FEE_PAYMENT_MAX_AGE_SECS;
+
+fn fee_quote_message(total_fee: u64, expires_at: i64) -> String {
+    format!("mintmint.fun fee quote: {} lamports until {}", total_fee, expires_at)
+}
+
+/// Sign the prepaid mint fee of `fee_breakdown` with the platform wallet. The
+/// quote reads `{total_fee}.{expires_at}.{signature}`.
+pub fn quote_fee(platform_keypair: &Keypair, fee_breakdown: FeeBreakdown, now: i64) -> FeeQuote {
+    let expires_at = now + FEE_QUOTE_TTL_SECS;
+    let signature = platform_keypair.sign_message(fee_quote_message(fee_breakdown.total_fee, expires_at).as_bytes());
+    FeeQuote {
+        quote: format!("{}.{}.{}", fee_breakdown.total_fee, expires_at, signature),
+        expires_at: rfc3339(Some(expires_at)).unwrap_or_default(),
+        fee_breakdown,
     }
+}
 
-    if req.name.len() > 32 || req.symbol.len() > 10 {
-        return Err("Invalid input: name max 32 chars, symbol max 10 chars".to_string());
+// Total fee of an unexpired quote signed by `platform_wallet`
+fn quoted_fee(quote: &str, platform_wallet: &Pubkey, now: i64) -> Result<u64, String> {
+    let invalid = || "Invalid fee quote".to_string();
+    let mut parts = quote.splitn(3, '.');
+    let total_fee: u64 = parts.next().and_then(|part| part.parse().ok()).ok_or_else(invalid)?;
+    let expires_at: i64 = parts.next().and_then(|part| part.parse().ok()).ok_or_else(invalid)?;
+    let signature = parts.next().and_then(|part| Signature::from_str(part).ok()).ok_or_else(invalid)?;
+
+    if !signature.verify(platform_wallet.as_ref(), fee_quote_message(total_fee, expires_at).as_bytes()) {
+        return Err(invalid());
+    }
+    if expires_at < now {
+        return Err("Fee quote has expired; request a new fee estimate".to_string());
     }
+    Ok(total_fee)
+}
 
-    let creator_pubkey = Pubkey::from_str(&req.creator_pubkey)
-        .map_err(|_| "Invalid creator pubkey format".to_string())?;
+// Verify that `payment_signature` is a finalized, recent transaction transferring at
+// least `min_lamports` from `payer` to `platform_wallet`, and return its block time
+pub async fn verify_fee_payment(
+    client: Arc<dyn ChainClient>,
+    payment_signature: &str,
+    payer: &Pubkey,
+    platform_wallet: &Pubkey,
+    min_lamports: u64,
+) -> Result<i64, String> {
+    let signature = Signature::from_str(payment_signature)
+        .map_err(|_| "Invalid fee payment signature format".to_string())?;
+
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(CommitmentConfig::finalized()),
+        max_supported_transaction_version: Some(0),
+    };
 
-    // Calculate required fees
-    let fee_breakdown = calculate_minting_fees(client.clone()).await?;
+    let confirmed = client.get_transaction_with_config(&signature, config).await
+        .map_err(|e| format!("Fee payment transaction not found or not finalized: {}", e))?;
 
-    // Verify signature using proper Solana wallet signature verification
-    if !crate::wallet::validate_signature(&req.message, &req.signature, &req.creator_pubkey) {
-        return Err("Invalid signature - message not signed by the provided wallet".to_string());
+    if let Some(meta) = &confirmed.transaction.meta {
+        if let Some(err) = &meta.err {
+            return Err(format!("Fee payment transaction failed: {}", err));
+        }
     }
 
-    // Validate fee payment - check that user has sufficient balance
-    let user_balance = client.get_balance(&creator_pubkey)
-        .map_err(|e| format!("Failed to get user balance: {}", e))?;
-
-    if user_balance < fee_breakdown.total_fee {
+    let block_time = confirmed.block_time
+        .ok_or("Fee payment transaction has no block time")?;
+    if chrono::Utc::now().timestamp() - block_time > FEE_PAYMENT_MAX_AGE_SECS {
         return Err(format!(
-            "Insufficient balance. Required: {} lamports, Available: {} lamports",
-            fee_breakdown.total_fee,
-            user_balance
+            "Fee payment is too old; it must be made within {} seconds of minting",
+            FEE_PAYMENT_MAX_AGE_SECS
         ));
     }
 
-    // TODO: In a production system, you would verify the fee_payment_signature
-    // to ensure the user has actually sent the payment to the platform wallet
-    // For now, we'll make this optional for testing purposes
-    if req.fee_payment_signature.is_some() {
-        // Verify fee payment signature if provided
-        // TODO: Implement fee payment signature verification
-    }
+    let transaction = confirmed.transaction.transaction.decode()
+        .ok_or("Failed to decode fee payment transaction")?;
 
-    // Create a short URL for the image to enable proxying
-    let short_id = format!("{:x}", md5::compute(&req.uri));
+    // Sum every system transfer from the payer to the platform wallet
+    let account_keys = transaction.message.static_account_keys();
+    let mut paid = 0u64;
+    for ix in transaction.message.instructions() {
+        let program_id = account_keys.get(ix.program_id_index as usize);
+        if program_id != Some(&solana_sdk::system_program::id()) || ix.accounts.len() < 2 {
+            continue;
+        }
 
-    // Store the mapping
-    {
-        let mut mappings = url_mappings.write().await;
-        mappings.insert(short_id.clone(), req.uri.clone());
+        if let Ok(SystemInstruction::Transfer { lamports }) = limited_deserialize(&ix.data) {
+            let from = account_keys.get(ix.accounts[0] as usize);
+            let to = account_keys.get(ix.accounts[1] as usize);
+            if from == Some(payer) && to == Some(platform_wallet) {
+                paid = paid.saturating_add(lamports);
+            }
+        }
     }
 
-    // Create the short URL
-    let metadata_uri = format!("http://localhost:3001/image/{}", short_id);
+    if paid < min_lamports {
+        return Err(format!(
+            "Fee payment too low. Required: {} lamports, Paid: {} lamports",
+            min_lamports,
+            paid
+        ));
+    }
 
-    // Generate mint keypair
-    let mint = Keypair::new();
+    Ok(block_time)
+}
 
-    // Parse the creator's public key
-    let creator_pubkey = Pubkey::from_str(&req.creator_pubkey)
-        .map_err(|e| format!("Invalid creator pubkey: {}", e))?;
+// Get fee estimate endpoint
+pub async fn get_fee_estimate(
+    client: Arc<dyn ChainClient>,
+    platform_keypair: &Keypair,
+) -> Result<FeeEstimateResponse, String> {
+    let fee_breakdown = calculate_minting_fees(client.clone(), &platform_keypair.pubkey(), MintFunding::CoSigned).await?;
+    let prepaid = calculate_minting_fees(client.clone(), &platform_keypair.pubkey(), MintFunding::Prepaid).await?;
+    let actions = estimate_marketplace_fees(client.as_ref()).await?;
 
-    // Derive token account (ATA) for the user's wallet (the actual owner)
-    let token_account = spl_associated_token_account::get_associated_token_address(&creator_pubkey, &mint.pubkey());
+    Ok(FeeEstimateResponse {
+        fee_breakdown,
+        prepaid_fee: quote_fee(platform_keypair, prepaid, chrono::Utc::now().timestamp()),
+        platform_wallet: platform_keypair.pubkey().to_string(),
+        actions,
+    })
+}
 
-    // Derive metadata account
-    let (metadata_account, _) = Pubkey::find_program_address(
-        &[b"metadata", mpl_token_metadata::ID.as_ref(), mint.pubkey().as_ref()],
-        &mpl_token_metadata::ID,
-    );
+// Cost of each marketplace action for the wallet that signs it, from the same
+// instructions `list_nft` and `buy_nft` build
+async fn estimate_marketplace_fees(
+    client: &dyn ChainClient,
+) -> Result<Vec<ActionFeeEstimate>, String> {
+    let rent = rent(client).await?;
+    let token_account_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    let unsupported = |action: &str, note: &str| ActionFeeEstimate {
+        action: action.to_string(),
+        supported: false,
+        rent: 0,
+        transaction_fee: 0,
+        priority_fee: 0,
+        total: 0,
+        note: Some(note.to_string()),
+    };
 
-    // Derive master edition account
-    let (master_edition, _) = Pubkey::find_program_address(
-        &[b"metadata", mpl_token_metadata::ID.as_ref(), mint.pubkey().as_ref(), b"edition"],
-        &mpl_token_metadata::ID,
-    );
+    let seller = Pubkey::new_unique();
+    let buyer = Pubkey::new_unique();
+    let nft_mint = Pubkey::new_unique();
+    let (listing_address, _) = marketplace_program::find_listing_address(&nft_mint);
+    let listing = marketplace_program::Listing {
+        nft_mint,
+        seller,
+        price: 0,
+        is_active: true,
+    };
 
-    // Get recent blockhash
-    let recent_blockhash = client.get_latest_blockhash().map_err(|e| format!("Failed to get blockhash: {}", e))?;
+    Ok(vec![
+        // The seller pays for the listing account and the escrow token account
+        estimate_action(
+            client,
+            "list_nft",
+            marketplace_program::list_nft(&seller, &nft_mint, 0),
+            &seller,
+            rent.minimum_balance(marketplace_program::Listing::LEN) + token_account_rent,
+            None,
+        ).await?,
+        estimate_action(
+            client,
+            "buy_nft",
+            marketplace_program::buy_nft(&buyer, &listing_address, &listing),
+            &buyer,
+            token_account_rent,
+            Some("Plus the listing price. Token account rent applies only if the buyer has no token account for the NFT"),
+        ).await?,
+        unsupported("cancel_listing", "The marketplace program has no instruction to cancel a listing"),
+        unsupported("make_offer", "The marketplace program has no instruction for offers"),
+    ])
+}
 
-    println!("Mint NFT Debug Info:");
-    println!("  Backend wallet: {}", keypair.pubkey());
-    println!("  Creator wallet (NFT owner): {}", creator_pubkey);
-    println!("  Mint address: {}", mint.pubkey());
-    println!("  Token account (ATA): {}", token_account);
-    println!("  Metadata account: {}", metadata_account);
-    println!("  Master edition: {}", master_edition);
+async fn estimate_action(
+    client: &dyn ChainClient,
+    action: &str,
+    instruction: Instruction,
+    payer: &Pubkey,
+    rent: u64,
+    note: Option<&str>,
+) -> Result<ActionFeeEstimate, String> {
+    let transaction_fee = network_fee(client, &[instruction], payer).await?;
+    let priority_fee = compute_budget::estimate(client, action).await?.priority_fee();
+    Ok(ActionFeeEstimate {
+        action: action.to_string(),
+        supported: true,
+        rent,
+        transaction_fee,
+        priority_fee,
+        total: rent + transaction_fee + priority_fee,
+        note: note.map(str::to_string),
+    })
+}
 
-    // Check if backend wallet has sufficient balance
-    let balance = client.get_balance(&keypair.pubkey()).map_err(|e| format!("Failed to get balance: {}", e))?;
-    println!("  Backend wallet balance: {} lamports ({} SOL)", balance, balance as f64 / 1_000_000_000.0);
+// Instructions that create a 0-decimal mint, mint one token to `creator` and
+// attach metadata and a master edition. `payer` pays rent and holds the mint
+// and update authorities.
+pub(crate) fn mint_instructions(
+    payer: &Pubkey,
+    creator: &Pubkey,
+    mint: &Pubkey,
+    mint_rent: u64,
+    data: DataV2,
+) -> Result<Vec<Instruction>, String> {
+    let token_account = spl_associated_token_account::get_associated_token_address(creator, mint);
+    let (metadata_account, _) = mpl_token_metadata::accounts::Metadata::find_pda(mint);
+    let (master_edition, _) = mpl_token_metadata::accounts::MasterEdition::find_pda(mint);
 
-    // Create instructions
     let mut instructions = Vec::new();
 
     // 1. Create mint account
     let create_mint_ix = system_instruction::create_account(
-        &keypair.pubkey(),
-        &mint.pubkey(),
-        client.get_minimum_balance_for_rent_exemption(SplMint::LEN).map_err(|e| format!("Failed to get rent: {}", e))?,
+        payer,
+        mint,
+        mint_rent,
         SplMint::LEN as u64,
         &spl_token::id(),
     );
@@ -245,18 +450,18 @@ pub async fn mint_nft(
     // 2. Initialize mint
     let init_mint_ix = token_instruction::initialize_mint(
         &spl_token::id(),
-        &mint.pubkey(),
-        &keypair.pubkey(),
-        Some(&keypair.pubkey()),
+        mint,
+        payer,
+        Some(payer),
         0, // decimals = 0 for NFT
     ).map_err(|e| format!("Failed to create init mint ix: {}", e))?;
     instructions.push(init_mint_ix);
 
     // 3. Create ATA for user's wallet
     let create_ata_ix = ata_instruction::create_associated_token_account(
-        &keypair.pubkey(), // Backend pays for the ATA creation
-        &creator_pubkey,   // User owns the ATA
-        &mint.pubkey(),
+        payer,   // Backend pays for the ATA creation
+        creator,   // User owns the ATA
+        mint,
         &spl_token::id(),
     );
     instructions.push(create_ata_ix);
@@ -264,31 +469,21 @@ pub async fn mint_nft(
     // 4. Mint 1 token
     let mint_to_ix = token_instruction::mint_to(
         &spl_token::id(),
-        &mint.pubkey(),
+        mint,
         &token_account,
-        &keypair.pubkey(),
+        payer,
         &[],
         1,
     ).map_err(|e| format!("Failed to create mint to ix: {}", e))?;
     instructions.push(mint_to_ix);
 
     // 5. Create metadata
-    let data = DataV2 {
-        name: req.name,
-        symbol: req.symbol,
-        uri: metadata_uri,
-        seller_fee_basis_points: 0,
-        creators: None,
-        collection: None,
-        uses: None,
-    };
-
     let create_metadata_ix = mpl_instruction::CreateMetadataAccountV3 {
         metadata: metadata_account,
-        mint: mint.pubkey(),
-        mint_authority: keypair.pubkey(),
-        payer: keypair.pubkey(),
-        update_authority: (keypair.pubkey(), true),
+        mint: *mint,
+        mint_authority: *payer,
+        payer: *payer,
+        update_authority: (*payer, true),
         system_program: solana_sdk::system_program::id(),
         rent: Some(solana_sdk::sysvar::rent::id()),
     }.instruction(mpl_instruction::CreateMetadataAccountV3InstructionArgs {
@@ -301,10 +496,10 @@ pub async fn mint_nft(
     // 6. Create master edition
     let create_master_edition_ix = mpl_instruction::CreateMasterEditionV3 {
         edition: master_edition,
-        mint: mint.pubkey(),
-        update_authority: keypair.pubkey(),
-        mint_authority: keypair.pubkey(),
-        payer: keypair.pubkey(),
+        mint: *mint,
+        update_authority: *payer,
+        mint_authority: *payer,
+        payer: *payer,
         metadata: metadata_account,
         token_program: spl_token::id(),
         system_program: solana_sdk::system_program::id(),
@@ -314,36 +509,227 @@ pub async fn mint_nft(
     });
     instructions.push(create_master_edition_ix);
 
-    // Create transaction
-    let mut transaction = Transaction::new_with_payer(&instructions, Some(&keypair.pubkey()));
-    transaction.sign(&[keypair, &mint], recent_blockhash);
+    Ok(instructions)
+}
 
-    println!("  Transaction created with {} instructions", instructions.len());
-    println!("  Sending transaction...");
+pub async fn mint_nft(
+    client: Arc<dyn ChainClient>,
+    keypair: &solana_sdk::signature::Keypair,
+    req: MintNftRequest,
+    url_mappings: Arc<tokio::sync::RwLock<HashMap<String, String>>>,
+    fee_payments: &FeePaymentLedger,
+    tracker: &TransactionTracker,
+) -> Result<MintNftResponse, TransactionError> {
+    // Validate inputs
+    if req.name.is_empty() || req.symbol.is_empty() || req.uri.is_empty() {
+        return Err("Invalid input: name, symbol, and uri are required".to_string().into());
+    }
+
+    if req.name.len() > 32 || req.symbol.len() > 10 {
+        return Err("Invalid input: name max 32 chars, symbol max 10 chars".to_string().into());
+    }
+
+    let creator_pubkey = Pubkey::from_str(&req.creator_pubkey)
+        .map_err(|_| "Invalid creator pubkey format".to_string())?;
+
+    // A prepaid mint references an earlier fee transfer to the platform wallet. Without
+    // one, the creator co-signs a transaction that collects the fee alongside the mint.
+    let funding = match req.fee_payment_signature {
+        Some(_) => MintFunding::Prepaid,
+        None => MintFunding::CoSigned,
+    };
+
+    // Calculate required fees
+    let fee_breakdown = calculate_minting_fees(client.clone(), &keypair.pubkey(), funding).await?;
+
+    if let Some(payment_signature) = &req.fee_payment_signature {
+        // The payment is held to the quote it was made for. Without one, the live
+        // total applies except for the priority fee, which moves with network load.
+        let required = match &req.fee_quote {
+            Some(quote) => quoted_fee(quote, &keypair.pubkey(), chrono::Utc::now().timestamp())?,
+            None => fee_breakdown.with_priority_fee(0).total_fee,
+        };
+
+        fee_payments.claim(payment_signature).await?;
+
+        let verified = verify_fee_payment(
+            client.clone(),
+            payment_signature,
+            &creator_pubkey,
+            &keypair.pubkey(),
+            required,
+        ).await
+        .and_then(|block_time| fee_payments.check_block_time(block_time));
+        if let Err(e) = verified {
+            fee_payments.release(payment_signature).await;
+            return Err(e.into());
+        }
+    } else {
+        // Validate fee payment - check that user has sufficient balance
+        let user_balance = client.get_balance(&creator_pubkey).await
+            .map_err(|e| format!("Failed to get user balance: {}", e))?;
+
+        if user_balance < fee_breakdown.total_fee {
+            return Err(format!(
+                "Insufficient balance. Required: {} lamports, Available: {} lamports",
+                fee_breakdown.total_fee,
+                user_balance
+            ).into());
+        }
+    }
 
-    // Send transaction
-    let signature = client.send_and_confirm_transaction(&transaction).map_err(|e| {
-        println!("  Transaction failed: {}", e);
-        format!("Failed to send tx: {}", e)
-    })?;
+    // Create a short URL for the image to enable proxying
+    let short_id = format!("{:x}", md5::compute(&req.uri));
 
-    println!("  Transaction successful: {}", signature);
+    // Store the mapping
+    {
+        let mut mappings = url_mappings.write().await;
+        mappings.insert(short_id.clone(), req.uri.clone());
+    }
+
+    // Create the short URL
+    let metadata_uri = format!("http://localhost:3001/image/{}", short_id);
+
+    // Generate mint keypair
+    let mint = Keypair::new();
+
+    // Parse the creator's public key
+    let creator_pubkey = Pubkey::from_str(&req.creator_pubkey)
+        .map_err(|e| format!("Invalid creator pubkey: {}", e))?;
+
+    // Derive token account (ATA) for the user's wallet (the actual owner)
+    let token_account = spl_associated_token_account::get_associated_token_address(&creator_pubkey, &mint.pubkey());
+    let (metadata_account, _) = mpl_token_metadata::accounts::Metadata::find_pda(&mint.pubkey());
+    let (master_edition, _) = mpl_token_metadata::accounts::MasterEdition::find_pda(&mint.pubkey());
+
+    println!("Mint NFT Debug Info:");
+    println!("  Backend wallet: {}", keypair.pubkey());
+    println!("  Creator wallet (NFT owner): {}", creator_pubkey);
+    println!("  Mint address: {}", mint.pubkey());
+    println!("  Token account (ATA): {}", token_account);
+    println!("  Metadata account: {}", metadata_account);
+    println!("  Master edition: {}", master_edition);
+
+    // Check if backend wallet has sufficient balance
+    let balance = client.get_balance(&keypair.pubkey()).await.map_err(|e| format!("Failed to get balance: {}", e))?;
+    println!("  Backend wallet balance: {} lamports ({} SOL)", balance, lamports_to_sol(balance));
+
+    let data = DataV2 {
+        name: req.name,
+        symbol: req.symbol,
+        uri: metadata_uri,
+        seller_fee_basis_points: 0,
+        creators: None,
+        collection: None,
+        uses: None,
+    };
+    let mut instructions = mint_instructions(
+        &keypair.pubkey(),
+        &creator_pubkey,
+        &mint.pubkey(),
+        fee_breakdown.mint_account_rent,
+        data,
+    )?;
+
+    // The fee was already paid and verified, so the backend signs and sends on its own
+    if let Some(payment_signature) = &req.fee_payment_signature {
+        let budget = match compute_budget::plan(client.as_ref(), "mint_nft", &instructions, &keypair.pubkey()).await {
+            Ok(budget) => budget,
+            Err(e) => {
+                fee_payments.release(payment_signature).await;
+                return Err(e.into());
+            }
+        };
+
+        println!("  Transaction created with {} instructions (prepaid by {})", instructions.len(), payment_signature);
+        println!("  Sending transaction...");
+
+        let nft_address = mint.pubkey().to_string();
+        let resigner = transactions::Resigner {
+            instructions: budget.apply(&instructions),
+            payer: keypair.pubkey(),
+            signers: vec![keypair.insecure_clone(), mint],
+        };
+        let signature = match transactions::send_backend_signed(client, tracker, "mint_nft", resigner).await {
+            Ok(signature) => signature.to_string(),
+            Err(e) => {
+                println!("  Transaction failed: {}", e);
+                // Release the payment so the creator can retry with it
+                fee_payments.release(payment_signature).await;
+                return Err(e);
+            }
+        };
+
+        println!("  Transaction sent: {}", signature);
+
+        // The payment funds exactly one mint, so it is released again if this one never lands
+        fee_payments.track_mint(tracker, payment_signature, &signature).await;
+
+        return Ok(MintNftResponse {
+            nft_address,
+            transaction_signature: signature,
+            fee_breakdown,
+            transaction: None,
+        });
+    }
+
+    // 7. Collect the fee from the creator. The backend fronts rent and network fees
+    // as fee payer, and this transfer reimburses it plus the platform fee atomically.
+    let fee_transfer_ix = system_instruction::transfer(
+        &creator_pubkey,
+        &keypair.pubkey(),
+        fee_breakdown.total_fee,
+    );
+    instructions.push(fee_transfer_ix);
+
+    // Charge the priority fee of the budget actually used. The transfer amount
+    // doesn't change compute usage, so the simulated budget still holds.
+    let budget = compute_budget::plan(client.as_ref(), "mint_nft", &instructions, &keypair.pubkey()).await?;
+    let fee_breakdown = fee_breakdown.with_priority_fee(budget.priority_fee());
+    instructions.pop();
+    instructions.push(system_instruction::transfer(
+        &creator_pubkey,
+        &keypair.pubkey(),
+        fee_breakdown.total_fee,
+    ));
+
+    // The creator is a required signer because of the fee transfer, so we only
+    // partially sign and hand the transaction back to their wallet
+    let prepared = transactions::prepare_with_budget(
+        client.as_ref(),
+        tracker,
+        "mint_nft",
+        &instructions,
+        &keypair.pubkey(),
+        &[keypair, &mint],
+        budget,
+    ).await?;
+
+    // The platform pays the network fee, so its signature is the transaction id
+    let transaction_signature = prepared.transaction_signature.clone()
+        .ok_or_else(|| "Mint transaction was not signed by the platform".to_string())?;
+
+    println!("  Transaction created with {} instructions", instructions.len());
+    println!("  Fee charged to creator: {} lamports", fee_breakdown.total_fee);
 
     Ok(MintNftResponse {
         nft_address: mint.pubkey().to_string(),
-        transaction_signature: signature.to_string(),
+        transaction_signature,
         fee_breakdown,
+        transaction: Some(prepared),
     })
 }
 
 pub async fn generate_and_mint_nft(
-    client: Arc<solana_client::rpc_client::RpcClient>,
+    client: Arc<dyn ChainClient>,
     keypair: &solana_sdk::signature::Keypair,
     freepik_client: Option<&FreepikApiClient>,
     url_mappings: Arc<tokio::sync::RwLock<HashMap<String, String>>>,
+    fee_payments: &FeePaymentLedger,
+    tracker: &TransactionTracker,
     req: GenerateAndMintNftRequest,
-) -> Result<MintNftResponse, String> {
-    let image_resp = freepik_client.ok_or("Freepik API not configured")?
+) -> Result<MintNftResponse, TransactionError> {
+    let image_resp = freepik_client.ok_or_else(|| "Freepik API not configured".to_string())?
         .generate_image(&req.prompt, req.style.as_deref())
         .await
         .map_err(|e| format!("Image generation failed: {}", e))?;
@@ -368,9 +754,10 @@ pub async fn generate_and_mint_nft(
         signature: req.signature,
         message: req.message,
         fee_payment_signature: req.fee_payment_signature,
+        fee_quote: req.fee_quote,
     };
 
-    mint_nft(client, keypair, mint_req, url_mappings).await
+    mint_nft(client, keypair, mint_req, url_mappings, fee_payments, tracker).await
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -380,82 +767,138 @@ pub struct ListNftRequest {
     pub seller_pubkey: String,
 }
 
+#[derive(Serialize, ToSchema)]
+pub struct ListNftResponse {
+    pub status: String,
+    pub listing_address: String,
+    pub transaction: PreparedTransaction, // Seller signs and submits via /api/v1/transactions/submit
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ListingError {
+    #[error("Seller does not hold this NFT")]
+    NotOwner,
+    #[error("Not an NFT: {0}")]
+    NotAnNft(String),
+    #[error("NFT already has a listing - the program creates one listing account per mint")]
+    AlreadyListed,
+    #[error(transparent)]
+    Failed(#[from] TransactionError),
+}
+
+impl ListingError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ListingError::NotOwner => "NOT_OWNER",
+            ListingError::NotAnNft(_) => "NOT_AN_NFT",
+            ListingError::AlreadyListed => "ALREADY_LISTED",
+            ListingError::Failed(_) => "LIST_FAILED",
+        }
+    }
+}
+
+impl ListingError {
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            ListingError::Failed(e) => e.details(),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for ListingError {
+    fn from(message: String) -> Self {
+        ListingError::Failed(message.into())
+    }
+}
+
+// Pre-flight checks so a listing that the program would reject is never built
+async fn validate_listing(
+    client: &dyn ChainClient,
+    seller: &Pubkey,
+    nft_mint: &Pubkey,
+    listing_address: &Pubkey,
+) -> Result<(), ListingError> {
+    let (metadata_address, _) = mpl_token_metadata::accounts::Metadata::find_pda(nft_mint);
+    let seller_token_account = spl_associated_token_account::get_associated_token_address(seller, nft_mint);
+
+    let accounts = client
+        .get_multiple_accounts(&[*nft_mint, metadata_address, seller_token_account, *listing_address]).await
+        .map_err(|e| format!("Failed to fetch accounts: {}", e))?;
+    let [mint_account, metadata_account, token_account, listing_account]: [_; 4] = accounts
+        .try_into()
+        .map_err(|_| "Unexpected RPC response".to_string())?;
+
+    // Mint: SPL token, no decimals, exactly one token in existence
+    let mint_account = mint_account
+        .filter(|account| account.owner == spl_token::id())
+        .ok_or_else(|| ListingError::NotAnNft("address is not an SPL token mint".to_string()))?;
+    let mint = SplMint::unpack(&mint_account.data)
+        .map_err(|_| ListingError::NotAnNft("address is not an SPL token mint".to_string()))?;
+    if mint.decimals != 0 || mint.supply != 1 {
+        return Err(ListingError::NotAnNft(format!(
+            "mint has {} decimals and a supply of {}", mint.decimals, mint.supply
+        )));
+    }
+
+    // Metadata: a Metaplex metadata account for this mint
+    let metadata = metadata_account
+        .filter(|account| account.owner == mpl_token_metadata::ID)
+        .and_then(|account| mpl_token_metadata::accounts::Metadata::from_bytes(&account.data).ok())
+        .ok_or_else(|| ListingError::NotAnNft("mint has no token metadata".to_string()))?;
+    if metadata.mint != *nft_mint {
+        return Err(ListingError::NotAnNft("metadata does not belong to this mint".to_string()));
+    }
+
+    // Ownership: the seller's associated token account holds the token
+    let holds_token = token_account
+        .filter(|account| account.owner == spl_token::id())
+        .and_then(|account| spl_token::state::Account::unpack(&account.data).ok())
+        .is_some_and(|token| token.owner == *seller && token.mint == *nft_mint && token.amount == 1);
+    if !holds_token {
+        return Err(ListingError::NotOwner);
+    }
+
+    // The listing PDA is created with `init`, so any existing account blocks a new listing
+    if listing_account.is_some() {
+        return Err(ListingError::AlreadyListed);
+    }
+
+    Ok(())
+}
+
+// Builds the listing transaction for the seller's wallet to sign and submit
 pub async fn list_nft(
-    client: Arc<solana_client::rpc_client::RpcClient>,
-    keypair: &solana_sdk::signature::Keypair,
+    client: Arc<dyn ChainClient>,
+    tracker: &TransactionTracker,
     req: ListNftRequest,
-) -> Result<serde_json::Value, String> {
+) -> Result<ListNftResponse, ListingError> {
     // Validate inputs
     let nft_pubkey = Pubkey::from_str(&req.nft_address)
         .map_err(|_| "Invalid NFT address".to_string())?;
     let seller_pubkey = Pubkey::from_str(&req.seller_pubkey)
         .map_err(|_| "Invalid seller pubkey".to_string())?;
 
-    // Program ID
-    let program_id = Pubkey::from_str("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS")
-        .map_err(|_| "Invalid program ID".to_string())?;
-
-    // Load signer
-    let signer = solana_sdk::signature::Keypair::from_base58_string(
-        &std::env::var("SOLANA_PRIVATE_KEY").expect("SOLANA_PRIVATE_KEY not set")
-    );
-
-    // Program ID
-    let program_id = Pubkey::from_str("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS")
-        .map_err(|_| "Invalid program ID".to_string())?;
-
-    // Use provided keypair
-    let signer = keypair;
-
-    // Derive listing PDA
-    let (listing_pubkey, _bump) = Pubkey::find_program_address(
-        &[b"listing", nft_pubkey.as_ref()],
-        &program_id,
-    );
+    let instruction = marketplace_program::list_nft(&seller_pubkey, &nft_pubkey, req.price);
+    let (listing_pubkey, _) = marketplace_program::find_listing_address(&nft_pubkey);
 
-    // Derive seller token account (ATA)
-    let seller_token_account = spl_associated_token_account::get_associated_token_address(&seller_pubkey, &nft_pubkey);
-
-    // Derive escrow token account (ATA for listing PDA)
-    let escrow_token_account = spl_associated_token_account::get_associated_token_address(&listing_pubkey, &nft_pubkey);
-
-    // Get recent blockhash
-    let recent_blockhash = client.get_latest_blockhash().map_err(|e| format!("Failed to get blockhash: {}", e))?;
-
-    // Build instruction data: list_nft(price: u64)
-    let mut data = vec![1]; // discriminator for list_nft (assuming 0 for mint, 1 for list, 2 for buy)
-    data.extend_from_slice(&req.price.to_le_bytes());
-
-    // Accounts
-    let accounts = vec![
-        solana_sdk::instruction::AccountMeta::new(listing_pubkey, false),
-        solana_sdk::instruction::AccountMeta::new_readonly(nft_pubkey, false),
-        solana_sdk::instruction::AccountMeta::new(seller_token_account, false),
-        solana_sdk::instruction::AccountMeta::new(escrow_token_account, false),
-        solana_sdk::instruction::AccountMeta::new(seller_pubkey, true),
-        solana_sdk::instruction::AccountMeta::new_readonly(spl_token::id(), false),
-        solana_sdk::instruction::AccountMeta::new_readonly(spl_associated_token_account::id(), false),
-        solana_sdk::instruction::AccountMeta::new_readonly(solana_sdk::system_program::id(), false),
-        solana_sdk::instruction::AccountMeta::new_readonly(solana_sdk::sysvar::rent::id(), false),
-    ];
-
-    let instruction = solana_sdk::instruction::Instruction {
-        program_id,
-        accounts,
-        data,
-    };
+    validate_listing(client.as_ref(), &seller_pubkey, &nft_pubkey, &listing_pubkey).await?;
 
-    let transaction = Transaction::new_signed_with_payer(
+    // The seller pays and signs; the backend holds none of the required keys
+    let prepared = transactions::prepare(
+        client.as_ref(),
+        tracker,
+        "list_nft",
         &[instruction],
-        Some(&signer.pubkey()),
-        &[signer],
-        recent_blockhash,
-    );
-
-    let signature = client.send_and_confirm_transaction(&transaction)
-        .map_err(|e| format!("Failed to send tx: {}", e))?;
-
-    Ok(serde_json::json!({"status": "listed", "listing_address": listing_pubkey.to_string(), "transaction_signature": signature.to_string()}))
+        &seller_pubkey,
+        &Vec::<&Keypair>::new(),
+    ).await?;
+
+    Ok(ListNftResponse {
+        status: "pending_signature".to_string(),
+        listing_address: listing_pubkey.to_string(),
+        transaction: prepared,
+    })
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -465,132 +908,149 @@ pub struct BuyNftRequest {
     pub buyer_pubkey: String,
 }
 
+#[derive(Serialize, ToSchema)]
+pub struct BuyNftResponse {
+    pub status: String,
+    pub transaction: PreparedTransaction, // Buyer signs and submits via /api/v1/transactions/submit
+}
+
+// Builds the purchase transaction for the buyer's wallet to sign and submit
 pub async fn buy_nft(
-    client: Arc<solana_client::rpc_client::RpcClient>,
-    keypair: &solana_sdk::signature::Keypair,
+    client: Arc<dyn ChainClient>,
+    tracker: &TransactionTracker,
     req: BuyNftRequest,
-) -> Result<serde_json::Value, String> {
+) -> Result<BuyNftResponse, TransactionError> {
     // Validate inputs
     let listing_pubkey = Pubkey::from_str(&req.listing_address)
         .map_err(|_| "Invalid listing address".to_string())?;
     let buyer_pubkey = Pubkey::from_str(&req.buyer_pubkey)
         .map_err(|_| "Invalid buyer pubkey".to_string())?;
 
-    // Program ID
-    let program_id = Pubkey::from_str("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS")
-        .map_err(|_| "Invalid program ID".to_string())?;
-
-    // Use provided keypair
-    let signer = keypair;
-
-    // For buy_nft, we need to fetch the listing account to get nft_mint and seller
-    // But since we don't have the IDL loaded, we'll assume the listing is passed or derive
-    // For simplicity, let's derive nft_mint from listing seeds, but actually we need to query the account
-    // To keep it simple, let's add nft_address to BuyNftRequest
-
-    // Wait, looking back, BuyNftRequest has listing_address, but to get nft_mint, we need to query the listing account
-    // For now, let's add nft_address to BuyNftRequest to simplify
-
-    // Actually, since listing PDA is [b"listing", nft_mint], we can derive nft_mint from listing if we know the bump, but it's complicated
-    // Let's modify BuyNftRequest to include nft_address
-
-    // For now, to proceed, I'll assume we can get the listing data, but since anchor-client isn't set up, let's hardcode or skip
-    // To make it work, let's add nft_address to BuyNftRequest
-
-    // Edit the struct first
-    // Wait, I can't edit the struct here, but in the request, let's assume it's added
-
-    // For simplicity, let's derive assuming the listing is for a known nft, but that's not good
-    // Perhaps use anchor-client to fetch the account
-
-    // Since time is limited, let's implement a basic version assuming we have the nft_address
-
-    // Actually, let's modify the BuyNftRequest to include nft_address
-    // But since it's a breaking change, perhaps implement with anchor-client
-
-    // Let's add anchor-client usage
-
-    // First, add to imports
-    // use anchor_client::Client;
-
-    // But to keep it simple, let's implement manually by fetching the account
-
-    // The Listing struct is: nft_mint (32), seller (32), price (8), is_active (1) = 73 bytes + 8 disc = 81
-
-    // Let's fetch the account data
-
-    let account_info = client.get_account(&listing_pubkey)
+    let account_info = client.get_account(&listing_pubkey).await
         .map_err(|e| format!("Failed to get listing account: {}", e))?;
-
-    if account_info.data.len() < 8 + 32 + 32 + 8 + 1 {
-        return Err("Invalid listing account data".to_string());
+    if account_info.owner != marketplace_program::program_id() {
+        return Err("Listing account is not owned by the marketplace program".to_string().into());
     }
+    let listing = marketplace_program::Listing::try_deserialize(&mut account_info.data.as_slice())
+        .map_err(|e| format!("Invalid listing account data: {}", e))?;
 
-    let nft_mint_bytes: [u8; 32] = account_info.data[8..40].try_into().unwrap();
-    let nft_pubkey = Pubkey::new_from_array(nft_mint_bytes);
-
-    let seller_bytes: [u8; 32] = account_info.data[40..72].try_into().unwrap();
-    let seller_pubkey = Pubkey::new_from_array(seller_bytes);
-
-    let _price_bytes: [u8; 8] = account_info.data[72..80].try_into().unwrap();
-    let _price = u64::from_le_bytes(_price_bytes);
-
-    let is_active = account_info.data[80] != 0;
-
-    if !is_active {
-        return Err("Listing is not active".to_string());
+    if !listing.is_active {
+        return Err("Listing is not active".to_string().into());
+    }
+    if listing.nft_mint.to_string() != req.nft_address {
+        return Err("Listing is for a different NFT".to_string().into());
     }
 
-    // Now proceed
-
-    // Derive escrow token account (ATA for listing PDA)
-    let escrow_token_account = spl_associated_token_account::get_associated_token_address(&listing_pubkey, &nft_pubkey);
-
-    // Derive buyer token account
-    let buyer_token_account = spl_associated_token_account::get_associated_token_address(&buyer_pubkey, &nft_pubkey);
-
-    // Get recent blockhash
-    let recent_blockhash = client.get_latest_blockhash().map_err(|e| format!("Failed to get blockhash: {}", e))?;
-
-    // Build instruction data: buy_nft() - discriminator 2
-    let data = vec![2];
-
-    // Accounts
-    let accounts = vec![
-        solana_sdk::instruction::AccountMeta::new(listing_pubkey, false),
-        solana_sdk::instruction::AccountMeta::new_readonly(nft_pubkey, false),
-        solana_sdk::instruction::AccountMeta::new(escrow_token_account, false),
-        solana_sdk::instruction::AccountMeta::new(buyer_token_account, false),
-        solana_sdk::instruction::AccountMeta::new(seller_pubkey, false),
-        solana_sdk::instruction::AccountMeta::new(buyer_pubkey, true),
-        solana_sdk::instruction::AccountMeta::new_readonly(spl_token::id(), false),
-        solana_sdk::instruction::AccountMeta::new_readonly(spl_associated_token_account::id(), false),
-        solana_sdk::instruction::AccountMeta::new_readonly(solana_sdk::system_program::id(), false),
-    ];
+    let instruction = marketplace_program::buy_nft(&buyer_pubkey, &listing_pubkey, &listing);
 
-    let instruction = solana_sdk::instruction::Instruction {
-        program_id,
-        accounts,
-        data,
-    };
-
-    let transaction = Transaction::new_signed_with_payer(
+    // The buyer pays and signs; the backend holds none of the required keys
+    let prepared = transactions::prepare(
+        client.as_ref(),
+        tracker,
+        "buy_nft",
         &[instruction],
-        Some(&signer.pubkey()),
-        &[signer],
-        recent_blockhash,
-    );
+        &buyer_pubkey,
+        &Vec::<&Keypair>::new(),
+    ).await?;
 
-    let signature = client.send_and_confirm_transaction(&transaction)
-        .map_err(|e| format!("Failed to send tx: {}", e))?;
-
-    Ok(serde_json::json!({"status": "purchased", "transaction_signature": signature.to_string()}))
+    Ok(BuyNftResponse {
+        status: "pending_signature".to_string(),
+        transaction: prepared,
+    })
 }
 
 pub async fn get_nfts(
-    _client: Arc<solana_client::rpc_client::RpcClient>,
+    _client: Arc<dyn ChainClient>,
 ) -> Result<Vec<serde_json::Value>, String> {
     // TODO: Query on-chain data for NFTs
 
     Ok(vec![])
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_chain::MemoryChain;
+    use solana_client::rpc_config::RpcSendTransactionConfig;
+    use solana_sdk::{native_token::LAMPORTS_PER_SOL, transaction::Transaction};
+
+    fn breakdown(total_minting_cost: u64) -> FeeBreakdown {
+        FeeBreakdown::new(total_minting_cost, 0, 0, 0, 0, 0)
+    }
+
+    // A transfer from `from` to `to`, landed on the in-memory chain
+    async fn pay(chain: &MemoryChain, from: &Keypair, to: &Pubkey, lamports: u64) -> String {
+        let blockhash = chain.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[system_instruction::transfer(&from.pubkey(), to, lamports)],
+            Some(&from.pubkey()),
+            &[from],
+            blockhash,
+        );
+        chain.send_transaction_with_config(&transaction, RpcSendTransactionConfig::default()).await
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn quotes_are_held_to_the_signed_amount() {
+        let platform = Keypair::new();
+        let now = 1_700_000_000;
+        let quote = quote_fee(&platform, breakdown(1_000_000), now);
+        assert_eq!(quote.fee_breakdown.total_fee, 1_100_000);
+        assert_eq!(quoted_fee(&quote.quote, &platform.pubkey(), now), Ok(1_100_000));
+        assert_eq!(quoted_fee(&quote.quote, &platform.pubkey(), now + FEE_QUOTE_TTL_SECS), Ok(1_100_000));
+
+        // A lowered amount, another signer or a late mint are all refused
+        let (_, rest) = quote.quote.split_once('.').unwrap();
+        assert_eq!(quoted_fee(&format!("1.{}", rest), &platform.pubkey(), now), Err("Invalid fee quote".to_string()));
+        assert_eq!(quoted_fee(&quote.quote, &Keypair::new().pubkey(), now), Err("Invalid fee quote".to_string()));
+        assert!(quoted_fee(&quote.quote, &platform.pubkey(), now + FEE_QUOTE_TTL_SECS + 1).unwrap_err().contains("expired"));
+        assert_eq!(quoted_fee("garbage", &platform.pubkey(), now), Err("Invalid fee quote".to_string()));
+    }
+
+    #[tokio::test]
+    async fn prepaid_mints_are_quoted_without_the_creator_signature() {
+        let chain: Arc<dyn ChainClient> = Arc::new(MemoryChain::new());
+        let payer = Pubkey::new_unique();
+        let co_signed = calculate_minting_fees(chain.clone(), &payer, MintFunding::CoSigned).await.unwrap();
+        let prepaid = calculate_minting_fees(chain, &payer, MintFunding::Prepaid).await.unwrap();
+
+        // Payer, mint and creator against payer and mint
+        assert_eq!(co_signed.transaction_fee, 15_000);
+        assert_eq!(prepaid.transaction_fee, 10_000);
+        assert_eq!(prepaid.mint_account_rent, co_signed.mint_account_rent);
+        assert!(prepaid.total_fee < co_signed.total_fee);
+    }
+
+    #[tokio::test]
+    async fn fee_payments_must_cover_the_fee_from_the_payer() {
+        let chain = Arc::new(MemoryChain::new());
+        let (creator, platform) = (Keypair::new(), Keypair::new());
+        chain.airdrop(&creator.pubkey(), LAMPORTS_PER_SOL);
+        let client: Arc<dyn ChainClient> = chain.clone();
+
+        let payment = pay(&chain, &creator, &platform.pubkey(), 1_000_000).await;
+        let block_time = verify_fee_payment(client.clone(), &payment, &creator.pubkey(), &platform.pubkey(), 1_000_000).await;
+        assert!(block_time.unwrap() <= chrono::Utc::now().timestamp());
+
+        let too_low = verify_fee_payment(client.clone(), &payment, &creator.pubkey(), &platform.pubkey(), 1_000_001).await;
+        assert_eq!(too_low, Err("Fee payment too low. Required: 1000001 lamports, Paid: 1000000 lamports".to_string()));
+
+        // Transfers from anyone else, or to anyone else, don't count
+        let other_payer = verify_fee_payment(client.clone(), &payment, &platform.pubkey(), &platform.pubkey(), 1).await;
+        assert!(other_payer.unwrap_err().starts_with("Fee payment too low"));
+        let other_wallet = verify_fee_payment(client.clone(), &payment, &creator.pubkey(), &creator.pubkey(), 1).await;
+        assert!(other_wallet.unwrap_err().starts_with("Fee payment too low"));
+
+        // A transfer beyond the creator's balance lands as failed
+        let failed = pay(&chain, &creator, &platform.pubkey(), 10 * LAMPORTS_PER_SOL).await;
+        let failed = verify_fee_payment(client.clone(), &failed, &creator.pubkey(), &platform.pubkey(), 1).await;
+        assert!(failed.unwrap_err().starts_with("Fee payment transaction failed"));
+
+        let unknown = Signature::new_unique().to_string();
+        let unknown = verify_fee_payment(client.clone(), &unknown, &creator.pubkey(), &platform.pubkey(), 1).await;
+        assert!(unknown.unwrap_err().starts_with("Fee payment transaction not found"));
+        let malformed = verify_fee_payment(client, "not-a-signature", &creator.pubkey(), &platform.pubkey(), 1).await;
+        assert_eq!(malformed, Err("Invalid fee payment signature format".to_string()));
+    }
+}
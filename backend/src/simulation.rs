@@ -0,0 +1,241 @@
+// Transaction simulation with program errors decoded into something a user can act on
+use num_traits::FromPrimitive;
+use serde::Serialize;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    system_instruction::SystemError,
+    transaction::{Transaction, TransactionError},
+};
+use utoipa::ToSchema;
+
+use crate::{chain::ChainClient, marketplace_program};
+
+/// Why a simulated transaction would fail, returned in `ApiError.details`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SimulationDetails {
+    pub error: String, // Raw transaction error reported by the cluster
+    pub instruction_index: Option<u8>,
+    pub program_id: Option<String>,
+    pub program: Option<String>, // marketplace, token, token_metadata, associated_token, system
+    pub error_code: Option<u32>,
+    pub error_name: Option<String>,
+    pub error_message: Option<String>,
+    pub units_consumed: Option<u64>,
+    pub logs: Vec<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Transaction simulation failed: {}", .details.error_message.as_deref().unwrap_or(&.details.error))]
+pub struct SimulationFailure {
+    pub details: SimulationDetails,
+}
+
+#[derive(Debug)]
+pub enum SimulationError {
+    Rpc(String),
+    Failed(SimulationFailure),
+}
+
+/// Simulate `transaction` and return the compute units it consumed. Unsigned or
+/// partially signed transactions are simulated with `sig_verify` off, against the
+/// latest blockhash.
+pub async fn simulate(
+    client: &dyn ChainClient,
+    transaction: &Transaction,
+    sig_verify: bool,
+) -> Result<Option<u64>, SimulationError> {
+    let config = RpcSimulateTransactionConfig {
+        sig_verify,
+        replace_recent_blockhash: !sig_verify,
+        commitment: Some(CommitmentConfig::confirmed()),
+        ..RpcSimulateTransactionConfig::default()
+    };
+    let result = client.simulate_transaction_with_config(transaction, config).await
+        .map_err(|e| SimulationError::Rpc(format!("Failed to simulate transaction: {}", e)))?
+        .value;
+
+    let logs = result.logs.unwrap_or_default();
+    match result.err {
+        None => Ok(result.units_consumed),
+        Some(err) => Err(SimulationError::Failed(SimulationFailure {
+            details: decode(transaction, err, logs, result.units_consumed),
+        })),
+    }
+}
+
+fn decode(
+    transaction: &Transaction,
+    err: TransactionError,
+    logs: Vec<String>,
+    units_consumed: Option<u64>,
+) -> SimulationDetails {
+    let mut details = SimulationDetails {
+        error: err.to_string(),
+        instruction_index: None,
+        program_id: None,
+        program: None,
+        error_code: None,
+        error_name: None,
+        error_message: None,
+        units_consumed,
+        logs,
+    };
+
+    let TransactionError::InstructionError(index, instruction_error) = err else {
+        details.error_message = Some(err.to_string());
+        return details;
+    };
+
+    let program_id = transaction.message.instructions.get(index as usize)
+        .and_then(|ix| transaction.message.account_keys.get(ix.program_id_index as usize))
+        .copied();
+    details.instruction_index = Some(index);
+    details.program_id = program_id.map(|id| id.to_string());
+    details.program = program_id.and_then(program_name).map(str::to_string);
+
+    let InstructionError::Custom(code) = instruction_error else {
+        details.error_message = Some(instruction_error.to_string());
+        return details;
+    };
+    details.error_code = Some(code);
+
+    let (name, message) = match program_id {
+        // Anchor logs the error name and message itself; a CPI failure inside the
+        // program (token transfer, ATA creation) is reported by the inner program
+        Some(id) if id == marketplace_program::program_id() => anchor_error(&details.logs)
+            .or_else(|| inner_program_error(&details.logs, code))
+            .unwrap_or_default(),
+        Some(id) => known_error(&id, code).unwrap_or_default(),
+        None => Default::default(),
+    };
+    details.error_name = name;
+    details.error_message = message.or_else(|| Some(instruction_error.to_string()));
+    details
+}
+
+fn program_name(program_id: Pubkey) -> Option<&'static str> {
+    if program_id == marketplace_program::program_id() {
+        Some("marketplace")
+    } else if program_id == spl_token::id() {
+        Some("token")
+    } else if program_id == mpl_token_metadata::ID {
+        Some("token_metadata")
+    } else if program_id == spl_associated_token_account::id() {
+        Some("associated_token")
+    } else if program_id == solana_sdk::system_program::id() {
+        Some("system")
+    } else {
+        None
+    }
+}
+
+type DecodedError = (Option<String>, Option<String>);
+
+// Custom error codes of the programs our transactions call directly
+fn known_error(program_id: &Pubkey, code: u32) -> Option<DecodedError> {
+    if *program_id == spl_token::id() {
+        spl_token::error::TokenError::from_u32(code)
+            .map(|e| (Some(format!("{:?}", e)), Some(e.to_string())))
+    } else if *program_id == mpl_token_metadata::ID {
+        mpl_token_metadata::errors::MplTokenMetadataError::from_u32(code)
+            .map(|e| (Some(format!("{:?}", e)), Some(e.to_string())))
+    } else if *program_id == solana_sdk::system_program::id() {
+        SystemError::from_u32(code)
+            .map(|e| (Some(format!("{:?}", e)), Some(e.to_string())))
+    } else {
+        None
+    }
+}
+
+// "AnchorError caused by account: listing. Error Code: ConstraintRaw. Error Number: 2003. Error Message: A raw constraint was violated."
+fn anchor_error(logs: &[String]) -> Option<DecodedError> {
+    let line = logs.iter().rev().find(|line| line.contains("AnchorError"))?;
+    let field = |key: &str| {
+        let start = line.find(key)? + key.len();
+        let rest = &line[start..];
+        let end = rest.find(". Error ").unwrap_or(rest.len());
+        Some(rest[..end].trim_end_matches('.').to_string())
+    };
+    Some((field("Error Code: "), field("Error Message: ")))
+}
+
+// The innermost program that logged `failed: custom program error: 0x..` for this code
+fn inner_program_error(logs: &[String], code: u32) -> Option<DecodedError> {
+    let failure = format!("failed: custom program error: {:#x}", code);
+    let line = logs.iter().find(|line| line.ends_with(&failure))?;
+    let program_id = line.strip_prefix("Program ")?.split_whitespace().next()?.parse().ok()?;
+    known_error(&program_id, code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::instruction::Instruction;
+
+    // A transaction whose only instruction calls `program_id`
+    fn calling(program_id: Pubkey) -> Transaction {
+        let instruction = Instruction::new_with_bytes(program_id, &[], vec![]);
+        Transaction::new_with_payer(&[instruction], Some(&Pubkey::new_unique()))
+    }
+
+    fn custom(code: u32) -> TransactionError {
+        TransactionError::InstructionError(0, InstructionError::Custom(code))
+    }
+
+    #[test]
+    fn errors_of_called_programs_are_named() {
+        let details = decode(&calling(spl_token::id()), custom(1), vec![], Some(1200));
+        assert_eq!(details.instruction_index, Some(0));
+        assert_eq!(details.program_id, Some(spl_token::id().to_string()));
+        assert_eq!(details.program.as_deref(), Some("token"));
+        assert_eq!(details.error_code, Some(1));
+        assert_eq!(details.error_name.as_deref(), Some("InsufficientFunds"));
+        assert_eq!(details.error_message.as_deref(), Some("Insufficient funds"));
+        assert_eq!(details.units_consumed, Some(1200));
+    }
+
+    #[test]
+    fn marketplace_errors_are_read_from_the_anchor_log() {
+        let logs = vec![
+            format!("Program {} invoke [1]", marketplace_program::program_id()),
+            "Program log: AnchorError caused by account: listing. Error Code: ConstraintRaw. Error Number: 2003. Error Message: A raw constraint was violated.".to_string(),
+        ];
+        let details = decode(&calling(marketplace_program::program_id()), custom(2003), logs, None);
+        assert_eq!(details.program.as_deref(), Some("marketplace"));
+        assert_eq!(details.error_name.as_deref(), Some("ConstraintRaw"));
+        assert_eq!(details.error_message.as_deref(), Some("A raw constraint was violated"));
+    }
+
+    #[test]
+    fn marketplace_failures_inside_a_cpi_are_named_after_the_inner_program() {
+        let logs = vec![
+            format!("Program {} invoke [1]", marketplace_program::program_id()),
+            format!("Program {} invoke [2]", spl_token::id()),
+            format!("Program {} failed: custom program error: 0x1", spl_token::id()),
+            format!("Program {} failed: custom program error: 0x1", marketplace_program::program_id()),
+        ];
+        let details = decode(&calling(marketplace_program::program_id()), custom(1), logs, None);
+        assert_eq!(details.program.as_deref(), Some("marketplace"));
+        assert_eq!(details.error_name.as_deref(), Some("InsufficientFunds"));
+        assert_eq!(details.logs.len(), 4);
+    }
+
+    #[test]
+    fn other_errors_keep_the_cluster_message() {
+        let unknown = Pubkey::new_unique();
+        let details = decode(&calling(unknown), custom(7), vec![], None);
+        assert_eq!((details.program, details.error_code, details.error_name), (None, Some(7), None));
+        assert_eq!(details.error_message, Some(InstructionError::Custom(7).to_string()));
+
+        let details = decode(&calling(unknown), TransactionError::InstructionError(0, InstructionError::MissingRequiredSignature), vec![], None);
+        assert_eq!((details.instruction_index, details.error_code), (Some(0), None));
+        assert_eq!(details.error_message, Some(InstructionError::MissingRequiredSignature.to_string()));
+
+        let details = decode(&calling(unknown), TransactionError::AccountNotFound, vec![], None);
+        assert_eq!((details.instruction_index, details.program_id), (None, None));
+        assert_eq!(details.error_message, Some(TransactionError::AccountNotFound.to_string()));
+    }
+}
@@ -0,0 +1,227 @@
+// Metaplex token metadata: decoding the on-chain metadata account of a mint and
+// fetching the off-chain JSON its `uri` points to.
+use futures::{stream, StreamExt};
+use mpl_token_metadata::accounts::{MasterEdition, Metadata};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+// Off-chain JSON lives on third-party hosts; a slow one must not hold up a page
+const OFFCHAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const OFFCHAIN_CONCURRENCY: usize = 8;
+
+// Metadata JSON is a few kilobytes; anything far larger isn't metadata
+const MAX_OFFCHAIN_BYTES: usize = 1024 * 1024;
+
+const IPFS_GATEWAY: &str = "https://ipfs.io/ipfs/";
+const ARWEAVE_GATEWAY: &str = "https://arweave.net/";
+
+pub struct TokenMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub seller_fee_basis_points: u16,
+    pub collection: Option<Pubkey>,
+    pub collection_verified: bool,
+}
+
+impl TokenMetadata {
+    /// Decode a metadata account, `None` unless it is owned by the metadata program
+    pub fn from_account(account: &Account) -> Option<Self> {
+        if account.owner != mpl_token_metadata::ID {
+            return None;
+        }
+        let metadata = Metadata::from_bytes(&account.data).ok()?;
+        Some(TokenMetadata {
+            name: trim_padding(&metadata.name),
+            symbol: trim_padding(&metadata.symbol),
+            uri: trim_padding(&metadata.uri),
+            seller_fee_basis_points: metadata.seller_fee_basis_points,
+            collection: metadata.collection.as_ref().map(|collection| collection.key),
+            collection_verified: metadata.collection.is_some_and(|collection| collection.verified),
+        })
+    }
+}
+
+pub fn metadata_address(mint: &Pubkey) -> Pubkey {
+    Metadata::find_pda(mint).0
+}
+
+pub fn master_edition_address(mint: &Pubkey) -> Pubkey {
+    MasterEdition::find_pda(mint).0
+}
+
+/// Supply and max supply of a master edition account
+pub fn decode_master_edition(account: &Account) -> Option<(u64, Option<u64>)> {
+    if account.owner != mpl_token_metadata::ID {
+        return None;
+    }
+    let edition = MasterEdition::from_bytes(&account.data).ok()?;
+    Some((edition.supply, edition.max_supply))
+}
+
+// Metadata strings are stored at their maximum length, padded with NULs
+fn trim_padding(value: &str) -> String {
+    value.trim_end_matches('\0').trim().to_string()
+}
+
+/// HTTP(S) URL for an ipfs://, ar:// or http(s) URI
+pub fn gateway_url(uri: &str) -> Option<String> {
+    if let Some(path) = uri.strip_prefix("ipfs://") {
+        Some(format!("{}{}", IPFS_GATEWAY, path.trim_start_matches("ipfs/")))
+    } else if let Some(path) = uri.strip_prefix("ar://") {
+        Some(format!("{}{}", ARWEAVE_GATEWAY, path))
+    } else if uri.starts_with("https://") || uri.starts_with("http://") {
+        Some(uri.to_string())
+    } else {
+        None
+    }
+}
+
+// Whether `ip` is reachable on the public internet. Anyone who mints an NFT
+// chooses its URI, so the server must not fetch from its own network.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            let shared = first == 100 && second & 0xc0 == 64; // 100.64.0.0/10
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || shared
+                || first == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                let unique_local = first & 0xfe00 == 0xfc00; // fc00::/7
+                let link_local = first & 0xffc0 == 0xfe80; // fe80::/10
+                !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local)
+            }
+        },
+    }
+}
+
+// A client that can only reach `url`'s host at the public addresses it resolves
+// to now, so a second lookup can't return a private one. Redirects are not
+// followed, since their targets would skip this check.
+async fn public_client(url: &reqwest::Url) -> Result<reqwest::Client, String> {
+    let builder = reqwest::Client::builder()
+        .timeout(OFFCHAIN_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .no_proxy();
+    let host = url.host_str().ok_or("URL has no host")?;
+    // IPv6 hosts come bracketed, and IP literals aren't looked up
+    let literal = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok();
+    let builder = match literal {
+        Some(ip) if is_public(ip) => builder,
+        Some(_) => return Err("Host is not a public address".to_string()),
+        None => {
+            let port = url.port_or_known_default().unwrap_or(443);
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await
+                .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+                .collect();
+            if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+                return Err(format!("{} does not resolve to a public address", host));
+            }
+            builder.resolve_to_addrs(host, &addrs)
+        }
+    };
+    builder.build().map_err(|e| format!("Failed to build HTTP client: {}", e))
+}
+
+/// Off-chain JSON at `uri`, or `None` if it cannot be fetched or parsed. Only
+/// public hosts are contacted, redirects are not followed and bodies over 1 MiB
+/// are dropped.
+pub async fn fetch_offchain(uri: &str) -> Option<serde_json::Value> {
+    let url = reqwest::Url::parse(&gateway_url(uri)?).ok()?;
+    let client = match public_client(&url).await {
+        Ok(client) => client,
+        Err(e) => {
+            println!("Refusing off-chain metadata {}: {}", url, e);
+            return None;
+        }
+    };
+    let mut response = match client.get(url.clone()).send().await {
+        Ok(response) if response.status().is_success() => response,
+        Ok(response) => {
+            println!("Off-chain metadata {} returned {}", url, response.status());
+            return None;
+        }
+        Err(e) => {
+            println!("Failed to fetch off-chain metadata {}: {}", url, e);
+            return None;
+        }
+    };
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.ok()? {
+        if body.len() + chunk.len() > MAX_OFFCHAIN_BYTES {
+            println!("Off-chain metadata {} is larger than {} bytes", url, MAX_OFFCHAIN_BYTES);
+            return None;
+        }
+        body.extend_from_slice(&chunk);
+    }
+    serde_json::from_slice(&body).ok().filter(serde_json::Value::is_object)
+}
+
+/// `fetch_offchain` for each URI, a few at a time, in the order given
+pub async fn fetch_offchain_batch(uris: &[String]) -> Vec<Option<serde_json::Value>> {
+    // Owned URIs keep the stream Send inside axum handlers
+    stream::iter(uris.to_vec())
+        .map(|uri| async move { fetch_offchain(&uri).await })
+        .buffered(OFFCHAIN_CONCURRENCY)
+        .collect()
+        .await
+}
+
+/// Gateway URL of the `image` in off-chain JSON
+pub fn offchain_image(json: &serde_json::Value) -> Option<String> {
+    json.get("image").and_then(|image| image.as_str()).and_then(gateway_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_fetched_from() {
+        for ip in ["93.184.216.34", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        let internal = [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1",
+            "0.0.0.0", "255.255.255.255", "::1", "::", "fd00::1", "fe80::1", "::ffff:127.0.0.1",
+        ];
+        for ip in internal {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn internal_hosts_are_not_contacted() {
+        // A server on this machine that would answer with JSON
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let body = r#"{"secret":true}"#;
+            let response = format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}", body.len(), body);
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        for uri in [format!("http://127.0.0.1:{}/", port), format!("http://localhost:{}/", port), format!("http://[::ffff:127.0.0.1]:{}/", port)] {
+            assert!(fetch_offchain(&uri).await.is_none(), "{}", uri);
+        }
+        assert!(!server.is_finished());
+        server.abort();
+    }
+}
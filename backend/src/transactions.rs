@@ -0,0 +1,721 @@
+// Transactions built by the backend, their submission and their lifecycle: sent
+// without preflight, rebroadcast until their blockhash expires, re-signed with a
+// fresh blockhash when the backend holds every key, and tracked by signature.
+// With an index database the tracker writes through to it, and tracking resumes
+// after a restart (see `resume`).
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_config::RpcSendTransactionConfig;
+use solana_sdk::{
+    clock::MAX_PROCESSING_AGE,
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    hash::Hash,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    signers::Signers,
+    transaction::Transaction,
+};
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+use utoipa::ToSchema;
+
+use crate::cache::ChainCache;
+use crate::chain::ChainClient;
+use crate::compute_budget::{self, ComputeBudget};
+use crate::index_db::IndexDb;
+use crate::simulation::{self, SimulationError, SimulationFailure};
+
+// How often a sent transaction's status is polled and, while pending, rebroadcast
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Fresh blockhashes a backend-signed transaction gets before it is given up on
+const MAX_RESIGN_ATTEMPTS: u32 = 3;
+
+/// A transaction waiting for the user's wallet to sign it
+#[derive(Serialize, ToSchema)]
+pub struct PreparedTransaction {
+    pub transaction: String, // Base64 bincode-serialized transaction, partially signed
+    pub transaction_signature: Option<String>, // Known up front when the backend pays the network fee
+    pub required_signers: Vec<String>, // Wallets that still have to sign
+    pub recent_blockhash: String,
+    pub last_valid_block_height: u64, // Sign and submit before this block height
+    pub compute_units_consumed: Option<u64>, // From simulating the transaction before it was returned
+    pub compute_unit_limit: u32,
+    pub compute_unit_price: u64, // Micro-lamports per compute unit
+    pub priority_fee: u64, // Lamports paid by the fee payer on top of the signature fee
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SubmitTransactionRequest {
+    pub transaction: String, // Base64 transaction returned by a prepare endpoint, fully signed
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SubmitTransactionResponse {
+    pub transaction_signature: String,
+    pub action: String,
+    pub status: TransactionStatus,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, ToSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    Submitted,
+    Confirmed,
+    Finalized,
+    Failed,
+    Expired,
+}
+
+impl TransactionStatus {
+    pub fn is_final(&self) -> bool {
+        matches!(self, TransactionStatus::Finalized | TransactionStatus::Failed | TransactionStatus::Expired)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionStatus::Submitted => "submitted",
+            TransactionStatus::Confirmed => "confirmed",
+            TransactionStatus::Finalized => "finalized",
+            TransactionStatus::Failed => "failed",
+            TransactionStatus::Expired => "expired",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "confirmed" => TransactionStatus::Confirmed,
+            "finalized" => TransactionStatus::Finalized,
+            "failed" => TransactionStatus::Failed,
+            "expired" => TransactionStatus::Expired,
+            _ => TransactionStatus::Submitted,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TransactionStatusResponse {
+    pub transaction_signature: String,
+    pub action: String,
+    pub status: TransactionStatus,
+    pub error: Option<String>,
+    pub submitted_at: String,
+    pub updated_at: String,
+    pub last_valid_block_height: u64,
+    pub rebroadcasts: u32, // Successful resends while the blockhash was valid
+    pub replaced_by: Option<String>, // Re-signed copy to poll instead, once this one expired
+}
+
+// Failure to build or send a transaction; simulation failures carry decoded
+// program errors for `ApiError.details`
+#[derive(Debug, thiserror::Error)]
+pub enum TransactionError {
+    #[error(transparent)]
+    Simulation(#[from] SimulationFailure),
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl TransactionError {
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            TransactionError::Simulation(failure) => serde_json::to_value(&failure.details).ok(),
+            TransactionError::Failed(_) => None,
+        }
+    }
+}
+
+impl From<String> for TransactionError {
+    fn from(message: String) -> Self {
+        TransactionError::Failed(message)
+    }
+}
+
+impl From<SimulationError> for TransactionError {
+    fn from(error: SimulationError) -> Self {
+        match error {
+            SimulationError::Rpc(message) => TransactionError::Failed(message),
+            SimulationError::Failed(failure) => TransactionError::Simulation(failure),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PreparedEntry {
+    action: String,
+    last_valid_block_height: u64,
+}
+
+#[derive(Clone)]
+pub struct TrackedTransaction {
+    pub action: String,
+    pub status: TransactionStatus,
+    pub error: Option<String>,
+    pub submitted_at: String,
+    pub updated_at: String,
+    pub last_valid_block_height: u64,
+    pub rebroadcasts: u32,
+    pub replaced_by: Option<String>,
+}
+
+// Prepared transactions are keyed by message hash so only transactions the backend
+// built can be relayed; sent ones are keyed by signature, one entry per signature
+#[derive(Clone)]
+pub struct TransactionTracker {
+    prepared: Arc<tokio::sync::RwLock<HashMap<Hash, PreparedEntry>>>,
+    submitted: Arc<tokio::sync::RwLock<HashMap<String, TrackedTransaction>>>,
+    cache: ChainCache, // Accounts our transactions write are evicted when sent and when they land
+    store: Option<IndexDb>, // Written through so prepared and sent transactions survive a restart
+}
+
+impl TransactionTracker {
+    pub fn new(cache: ChainCache, store: Option<IndexDb>) -> Self {
+        TransactionTracker {
+            prepared: Arc::default(),
+            submitted: Arc::default(),
+            cache,
+            store,
+        }
+    }
+
+    pub async fn get(&self, signature: &str) -> Option<TransactionStatusResponse> {
+        self.tracked(signature).await.map(|tracked| TransactionStatusResponse {
+            transaction_signature: signature.to_string(),
+            action: tracked.action.clone(),
+            status: tracked.status,
+            error: tracked.error.clone(),
+            submitted_at: tracked.submitted_at.clone(),
+            updated_at: tracked.updated_at.clone(),
+            last_valid_block_height: tracked.last_valid_block_height,
+            rebroadcasts: tracked.rebroadcasts,
+            replaced_by: tracked.replaced_by.clone(),
+        })
+    }
+
+    /// Wait until `signature`, or the copy it was re-signed into, reaches a final status
+    pub async fn final_status(&self, signature: &str) -> Option<TransactionStatus> {
+        let mut signature = signature.to_string();
+        loop {
+            let tracked = self.tracked(&signature).await?;
+            match tracked.replaced_by {
+                Some(replacement) => signature = replacement,
+                None if tracked.status.is_final() => return Some(tracked.status),
+                None => tokio::time::sleep(CONFIRMATION_POLL_INTERVAL).await,
+            }
+        }
+    }
+
+    async fn tracked(&self, signature: &str) -> Option<TrackedTransaction> {
+        let tracked = self.submitted.read().await.get(signature).cloned();
+        // Transactions that were final before a restart are only in the store
+        match (tracked, &self.store) {
+            (Some(tracked), _) => Some(tracked),
+            (None, Some(store)) => store.submitted_transaction(signature).await.unwrap_or_else(|e| {
+                println!("{}", e);
+                None
+            }),
+            (None, None) => None,
+        }
+    }
+
+    async fn record(&self, transaction: &Transaction, action: &str, last_valid_block_height: u64, resigner: Option<&Resigner>) {
+        let signature = transaction.signatures[0].to_string();
+        let now = chrono::Utc::now().to_rfc3339();
+        let tracked = TrackedTransaction {
+            action: action.to_string(),
+            status: TransactionStatus::Submitted,
+            error: None,
+            submitted_at: now.clone(),
+            updated_at: now,
+            last_valid_block_height,
+            rebroadcasts: 0,
+            replaced_by: None,
+        };
+        self.submitted.write().await.insert(signature.clone(), tracked.clone());
+
+        if let Some(store) = &self.store {
+            let stored = bincode::serialize(transaction)
+                .map(|bytes| base64::engine::general_purpose::STANDARD.encode(bytes))
+                .map_err(|e| format!("Failed to serialize transaction: {}", e))
+                .and_then(|transaction| Ok((transaction, resigner.map(Resigner::to_stored).transpose()?.flatten())));
+            let saved = match stored {
+                Ok((transaction, resigner)) => {
+                    store.save_submitted(&signature, &tracked, Some((&transaction, resigner.as_deref()))).await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = saved {
+                println!("{}", e);
+            }
+        }
+    }
+
+    async fn update(&self, signature: &str, apply: impl FnOnce(&mut TrackedTransaction)) {
+        let updated = {
+            let mut submitted = self.submitted.write().await;
+            submitted.get_mut(signature).map(|tracked| {
+                apply(tracked);
+                tracked.updated_at = chrono::Utc::now().to_rfc3339();
+                tracked.clone()
+            })
+        };
+        if let (Some(store), Some(tracked)) = (&self.store, updated) {
+            if let Err(e) = store.save_submitted(signature, &tracked, None).await {
+                println!("{}", e);
+            }
+        }
+    }
+
+    async fn save_prepared(&self, message_hash: Hash, entry: PreparedEntry) {
+        // Anything older than a full blockhash lifetime can no longer land
+        let expired_before = entry.last_valid_block_height.saturating_sub(MAX_PROCESSING_AGE as u64);
+        if let Some(store) = &self.store {
+            let saved = store.save_prepared(&message_hash.to_string(), &entry.action, entry.last_valid_block_height, expired_before).await;
+            if let Err(e) = saved {
+                println!("{}", e);
+            }
+        }
+
+        let mut prepared = self.prepared.write().await;
+        prepared.retain(|_, existing| existing.last_valid_block_height >= expired_before);
+        prepared.insert(message_hash, entry);
+    }
+
+    async fn remove_prepared(&self, message_hash: &Hash) {
+        self.prepared.write().await.remove(message_hash);
+        if let Some(store) = &self.store {
+            if let Err(e) = store.delete_prepared(&message_hash.to_string()).await {
+                println!("{}", e);
+            }
+        }
+    }
+
+    async fn set_status(&self, signature: &str, status: TransactionStatus, error: Option<String>) {
+        self.update(signature, |tracked| {
+            tracked.status = status;
+            tracked.error = error;
+        }).await;
+    }
+}
+
+// Everything needed to sign a transaction again with a fresh blockhash
+pub struct Resigner {
+    pub instructions: Vec<Instruction>,
+    pub payer: Pubkey,
+    pub signers: Vec<Keypair>,
+}
+
+// A `Resigner` as stored with its transaction. The payer is the backend wallet,
+// whose key is loaded from the environment, and no other key is ever written out.
+#[derive(Serialize, Deserialize)]
+struct StoredResigner {
+    instructions: Vec<Instruction>,
+    payer: Pubkey,
+}
+
+impl Resigner {
+    // `None` when other keys (e.g. a new mint account) sign too: those only live in
+    // memory, so after a restart the transaction is rebroadcast until it expires
+    fn to_stored(&self) -> Result<Option<String>, String> {
+        if self.signers.iter().any(|signer| signer.pubkey() != self.payer) {
+            return Ok(None);
+        }
+        let stored = StoredResigner {
+            instructions: self.instructions.clone(),
+            payer: self.payer,
+        };
+        bincode::serialize(&stored)
+            .map(|bytes| Some(base64::engine::general_purpose::STANDARD.encode(bytes)))
+            .map_err(|e| format!("Failed to serialize re-signing data: {}", e))
+    }
+
+    fn from_stored(stored: &str, payer: &Keypair) -> Result<Self, String> {
+        let bytes = base64::engine::general_purpose::STANDARD.decode(stored)
+            .map_err(|e| format!("Invalid re-signing data: {}", e))?;
+        let stored: StoredResigner = bincode::deserialize(&bytes)
+            .map_err(|e| format!("Invalid re-signing data: {}", e))?;
+        if stored.payer != payer.pubkey() {
+            return Err(format!("Re-signing data is for another wallet ({})", stored.payer));
+        }
+        Ok(Resigner {
+            instructions: stored.instructions,
+            payer: stored.payer,
+            signers: vec![payer.insecure_clone()],
+        })
+    }
+
+    async fn sign(&self, client: &dyn ChainClient) -> Result<(Transaction, u64), String> {
+        let (recent_blockhash, last_valid_block_height) = client
+            .get_latest_blockhash_with_commitment(CommitmentConfig::confirmed()).await
+            .map_err(|e| format!("Failed to get blockhash: {}", e))?;
+        let signers: Vec<&Keypair> = self.signers.iter().collect();
+        let transaction = Transaction::new_signed_with_payer(
+            &self.instructions,
+            Some(&self.payer),
+            &signers,
+            recent_blockhash,
+        );
+        Ok((transaction, last_valid_block_height))
+    }
+}
+
+// We simulate before sending and rebroadcast ourselves, so the RPC node does neither
+fn send_config() -> RpcSendTransactionConfig {
+    RpcSendTransactionConfig {
+        skip_preflight: true,
+        preflight_commitment: Some(CommitmentLevel::Confirmed),
+        max_retries: Some(0),
+        ..RpcSendTransactionConfig::default()
+    }
+}
+
+/// Build a transaction paid by `payer` with a compute budget sized from simulation,
+/// sign it with the backend-held `signers` (if any) and serialize it for the
+/// remaining wallets to sign
+pub async fn prepare<T: Signers + ?Sized>(
+    client: &dyn ChainClient,
+    tracker: &TransactionTracker,
+    action: &str,
+    instructions: &[Instruction],
+    payer: &Pubkey,
+    signers: &T,
+) -> Result<PreparedTransaction, TransactionError> {
+    let budget = compute_budget::plan(client, action, instructions, payer).await?;
+    prepare_with_budget(client, tracker, action, instructions, payer, signers, budget).await
+}
+
+/// `prepare` with a compute budget the caller already planned, e.g. to charge its
+/// priority fee inside the same transaction
+pub async fn prepare_with_budget<T: Signers + ?Sized>(
+    client: &dyn ChainClient,
+    tracker: &TransactionTracker,
+    action: &str,
+    instructions: &[Instruction],
+    payer: &Pubkey,
+    signers: &T,
+    budget: ComputeBudget,
+) -> Result<PreparedTransaction, TransactionError> {
+    let (recent_blockhash, last_valid_block_height) = client
+        .get_latest_blockhash_with_commitment(CommitmentConfig::confirmed()).await
+        .map_err(|e| format!("Failed to get blockhash: {}", e))?;
+
+    let mut transaction = Transaction::new_with_payer(&budget.apply(instructions), Some(payer));
+    transaction.message.recent_blockhash = recent_blockhash;
+    transaction.try_partial_sign(signers, recent_blockhash)
+        .map_err(|e| format!("Failed to sign transaction: {}", e))?;
+
+    let signed_by = signers.pubkeys();
+    let required_signers = transaction.message.signer_keys()
+        .into_iter()
+        .filter(|key| !signed_by.contains(key))
+        .map(|key| key.to_string())
+        .collect();
+
+    // The fee payer's signature is the transaction id
+    let transaction_signature = signed_by.contains(payer)
+        .then(|| transaction.signatures[0].to_string());
+
+    let serialized = bincode::serialize(&transaction)
+        .map_err(|e| format!("Failed to serialize transaction: {}", e))?;
+
+    tracker.save_prepared(transaction.message.hash(), PreparedEntry {
+        action: action.to_string(),
+        last_valid_block_height,
+    }).await;
+
+    Ok(PreparedTransaction {
+        transaction: base64::engine::general_purpose::STANDARD.encode(serialized),
+        transaction_signature,
+        required_signers,
+        recent_blockhash: recent_blockhash.to_string(),
+        last_valid_block_height,
+        compute_units_consumed: budget.units_consumed,
+        compute_unit_limit: budget.unit_limit,
+        compute_unit_price: budget.unit_price,
+        priority_fee: budget.priority_fee(),
+    })
+}
+
+/// Simulate and send a transaction previously returned by `prepare`, now fully
+/// signed, then track it until it lands or its blockhash expires
+pub async fn submit(
+    client: Arc<dyn ChainClient>,
+    tracker: &TransactionTracker,
+    encoded: &str,
+) -> Result<SubmitTransactionResponse, TransactionError> {
+    let bytes = base64::engine::general_purpose::STANDARD.decode(encoded)
+        .map_err(|_| "Transaction is not valid base64".to_string())?;
+    let transaction: Transaction = bincode::deserialize(&bytes)
+        .map_err(|_| "Transaction could not be decoded".to_string())?;
+
+    let message_hash = transaction.message.hash();
+    let entry = tracker.prepared.read().await
+        .get(&message_hash)
+        .cloned()
+        .ok_or_else(|| "Unknown transaction - it must be built by this API".to_string())?;
+
+    transaction.verify()
+        .map_err(|_| "Transaction is missing signatures or has invalid signatures".to_string())?;
+
+    simulation::simulate(client.as_ref(), &transaction, true).await?;
+
+    // The user's wallet signed it, so it can be rebroadcast but never re-signed
+    let signature = send(client, tracker, &entry.action, transaction, entry.last_valid_block_height, None).await?;
+    tracker.remove_prepared(&message_hash).await;
+
+    Ok(SubmitTransactionResponse {
+        transaction_signature: signature.to_string(),
+        action: entry.action,
+        status: TransactionStatus::Submitted,
+    })
+}
+
+/// Sign `instructions` with keys the backend holds, simulate and send them, and
+/// keep the transaction alive (rebroadcasting, then re-signing) in the background.
+/// Returns the first signature; poll it with `TransactionTracker::get`.
+pub async fn send_backend_signed(
+    client: Arc<dyn ChainClient>,
+    tracker: &TransactionTracker,
+    action: &str,
+    resigner: Resigner,
+) -> Result<Signature, TransactionError> {
+    let (transaction, last_valid_block_height) = resigner.sign(client.as_ref()).await?;
+    simulation::simulate(client.as_ref(), &transaction, true).await?;
+    send(client, tracker, action, transaction, last_valid_block_height, Some(resigner)).await
+}
+
+// Send once, record the signature and hand the transaction to the lifecycle task
+async fn send(
+    client: Arc<dyn ChainClient>,
+    tracker: &TransactionTracker,
+    action: &str,
+    transaction: Transaction,
+    last_valid_block_height: u64,
+    resigner: Option<Resigner>,
+) -> Result<Signature, TransactionError> {
+    let signature = client.send_transaction_with_config(&transaction, send_config()).await
+        .map_err(|e| format!("Failed to send tx: {}", e))?;
+
+    tracker.record(&transaction, action, last_valid_block_height, resigner.as_ref()).await;
+    tracker.cache.invalidate(&transaction).await;
+
+    tokio::spawn(track_lifecycle(
+        client,
+        tracker.clone(),
+        action.to_string(),
+        transaction,
+        last_valid_block_height,
+        resigner,
+    ));
+
+    Ok(signature)
+}
+
+/// Load what the store remembers and pick up tracking of every sent transaction
+/// whose status wasn't final when the server stopped. `backend_wallet` re-signs
+/// the ones the backend signed alone.
+pub async fn resume(client: Arc<dyn ChainClient>, tracker: &TransactionTracker, backend_wallet: &Keypair) -> Result<(), String> {
+    let Some(store) = &tracker.store else {
+        return Ok(());
+    };
+
+    {
+        let mut prepared = tracker.prepared.write().await;
+        for (message_hash, action, last_valid_block_height) in store.prepared_transactions().await? {
+            if let Ok(message_hash) = Hash::from_str(&message_hash) {
+                prepared.insert(message_hash, PreparedEntry { action, last_valid_block_height });
+            }
+        }
+    }
+
+    let pending = store.pending_transactions().await?;
+    println!("Resuming tracking of {} sent transactions", pending.len());
+    for entry in pending {
+        let transaction = base64::engine::general_purpose::STANDARD.decode(&entry.transaction)
+            .ok()
+            .and_then(|bytes| bincode::deserialize::<Transaction>(&bytes).ok());
+        let Some(transaction) = transaction else {
+            println!("Stored transaction {} could not be decoded", entry.signature);
+            continue;
+        };
+        let resigner = match entry.resigner.as_deref().map(|stored| Resigner::from_stored(stored, backend_wallet)) {
+            Some(Ok(resigner)) => Some(resigner),
+            Some(Err(e)) => {
+                println!("Tracking {} without re-signing: {}", entry.signature, e);
+                None
+            }
+            None => None,
+        };
+
+        let (action, last_valid_block_height) = (entry.tracked.action.clone(), entry.tracked.last_valid_block_height);
+        tracker.submitted.write().await.insert(entry.signature, entry.tracked);
+        tokio::spawn(track_lifecycle(client.clone(), tracker.clone(), action, transaction, last_valid_block_height, resigner));
+    }
+    Ok(())
+}
+
+// Poll until the transaction finalizes or fails, rebroadcasting it while it is
+// pending. Once its blockhash has expired it can never land, so a backend-signed
+// transaction is then safely re-signed with a fresh blockhash.
+async fn track_lifecycle(
+    client: Arc<dyn ChainClient>,
+    tracker: TransactionTracker,
+    action: String,
+    mut transaction: Transaction,
+    mut last_valid_block_height: u64,
+    resigner: Option<Resigner>,
+) {
+    let mut resign_attempts = 0;
+    let mut evicted_on_landing = false;
+    loop {
+        tokio::time::sleep(CONFIRMATION_POLL_INTERVAL).await;
+
+        let signature = transaction.signatures[0];
+        let key = signature.to_string();
+
+        let status = match client.get_signature_statuses(&[signature]).await {
+            Ok(response) => response.value.into_iter().next().flatten(),
+            Err(e) => {
+                println!("Failed to poll status of {}: {}", key, e);
+                continue;
+            }
+        };
+
+        if let Some(status) = status {
+            // Reads between sending and landing may have cached the old state
+            if !evicted_on_landing {
+                tracker.cache.invalidate(&transaction).await;
+                evicted_on_landing = true;
+            }
+            if let Some(err) = status.err {
+                tracker.set_status(&key, TransactionStatus::Failed, Some(err.to_string())).await;
+                return;
+            }
+            if status.satisfies_commitment(CommitmentConfig::finalized()) {
+                tracker.set_status(&key, TransactionStatus::Finalized, None).await;
+                return;
+            }
+            tracker.set_status(&key, TransactionStatus::Confirmed, None).await;
+            continue;
+        }
+
+        // Finalized height, so a blockhash counted as expired really is
+        let expired = matches!(
+            client.get_block_height_with_commitment(CommitmentConfig::finalized()).await,
+            Ok(height) if height > last_valid_block_height
+        );
+
+        if !expired {
+            match client.send_transaction_with_config(&transaction, send_config()).await {
+                Ok(_) => tracker.update(&key, |tracked| tracked.rebroadcasts += 1).await,
+                Err(e) => println!("Failed to rebroadcast {}: {}", key, e),
+            }
+            continue;
+        }
+
+        // A last look with full history in case it landed between polls
+        let landed = client.get_signature_statuses_with_history(&[signature]).await
+            .map(|response| response.value.into_iter().next().flatten().is_some())
+            .unwrap_or(true);
+        if landed {
+            continue;
+        }
+
+        let resigned = match &resigner {
+            Some(resigner) if resign_attempts < MAX_RESIGN_ATTEMPTS => resigner.sign(client.as_ref()).await,
+            _ => {
+                tracker.set_status(&key, TransactionStatus::Expired, None).await;
+                return;
+            }
+        };
+        resign_attempts += 1;
+
+        let (resigned, valid_until) = match resigned {
+            Ok(resigned) => resigned,
+            Err(e) => {
+                println!("Failed to re-sign {}: {}", key, e);
+                continue;
+            }
+        };
+        if let Err(e) = client.send_transaction_with_config(&resigned, send_config()).await {
+            println!("Failed to send re-signed copy of {}: {}", key, e);
+        }
+
+        let replacement = resigned.signatures[0].to_string();
+        println!("Re-signed {} ({}) as {}", key, action, replacement);
+        tracker.record(&resigned, &action, valid_until, resigner.as_ref()).await;
+        tracker.update(&key, |tracked| {
+            tracked.status = TransactionStatus::Expired;
+            tracked.replaced_by = Some(replacement);
+        }).await;
+
+        transaction = resigned;
+        last_valid_block_height = valid_until;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_chain::MemoryChain;
+    use solana_sdk::{native_token::LAMPORTS_PER_SOL, system_instruction};
+
+    #[tokio::test]
+    async fn pending_transactions_are_resumed_from_the_store() {
+        let chain = Arc::new(MemoryChain::new());
+        let client: Arc<dyn ChainClient> = chain.clone();
+        let index = IndexDb::connect("sqlite::memory:").await.unwrap();
+        let wallet = Keypair::new();
+        chain.airdrop(&wallet.pubkey(), LAMPORTS_PER_SOL);
+
+        let instructions = vec![system_instruction::transfer(&wallet.pubkey(), &Pubkey::new_unique(), 1000)];
+        let resigner = Resigner {
+            instructions: instructions.clone(),
+            payer: wallet.pubkey(),
+            signers: vec![wallet.insecure_clone()],
+        };
+        let (blockhash, last_valid_block_height) = client
+            .get_latest_blockhash_with_commitment(CommitmentConfig::finalized())
+            .await
+            .unwrap();
+        let transaction = Transaction::new_signed_with_payer(&instructions, Some(&wallet.pubkey()), &[&wallet], blockhash);
+        let signature = transaction.signatures[0].to_string();
+
+        // Recorded, then the server stops before it is ever polled
+        let tracker = TransactionTracker::new(ChainCache::new(client.clone(), None, Duration::ZERO), Some(index.clone()));
+        tracker.record(&transaction, "transfer", last_valid_block_height, Some(&resigner)).await;
+
+        let restarted = TransactionTracker::new(ChainCache::new(client.clone(), None, Duration::ZERO), Some(index));
+        let status = restarted.get(&signature).await.unwrap();
+        assert_eq!(status.status, TransactionStatus::Submitted);
+
+        resume(client, &restarted, &wallet).await.unwrap();
+        tokio::time::sleep(CONFIRMATION_POLL_INTERVAL * 2 + Duration::from_millis(500)).await;
+        assert_eq!(restarted.final_status(&signature).await, Some(TransactionStatus::Finalized));
+        assert_eq!(restarted.get(&signature).await.unwrap().rebroadcasts, 1);
+    }
+
+    #[test]
+    fn stored_resigners_never_hold_secret_keys() {
+        let wallet = Keypair::new();
+        let mint = Keypair::new();
+        let instructions = vec![system_instruction::transfer(&wallet.pubkey(), &mint.pubkey(), 1)];
+        let co_signed = Resigner {
+            instructions: instructions.clone(),
+            payer: wallet.pubkey(),
+            signers: vec![wallet.insecure_clone(), mint.insecure_clone()],
+        };
+        assert!(co_signed.to_stored().unwrap().is_none());
+
+        let backend_signed = Resigner { instructions, payer: wallet.pubkey(), signers: vec![wallet.insecure_clone()] };
+        let stored = backend_signed.to_stored().unwrap().unwrap();
+        let bytes = base64::engine::general_purpose::STANDARD.decode(&stored).unwrap();
+        assert!(!bytes.windows(32).any(|window| window == wallet.secret().as_bytes()));
+
+        let restored = Resigner::from_stored(&stored, &wallet).unwrap();
+        let signers: Vec<Pubkey> = restored.signers.iter().map(|signer| signer.pubkey()).collect();
+        assert_eq!(signers, vec![wallet.pubkey()]);
+        assert_eq!(restored.instructions, backend_signed.instructions);
+        assert!(Resigner::from_stored(&stored, &Keypair::new()).is_err());
+    }
+}
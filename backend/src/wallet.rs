@@ -1,10 +1,17 @@
 // Wallet utilities and signature validation
 use serde::{Deserialize, Serialize};
-use solana_client::rpc_client::RpcClient;
-use solana_sdk::pubkey::Pubkey;
+use solana_client::rpc_request::TokenAccountsFilter;
+use solana_sdk::{native_token::lamports_to_sol, program_pack::Pack, pubkey, pubkey::Pubkey};
+use spl_token::state::{Account as TokenAccount, Mint};
 use std::str::FromStr;
 use utoipa::ToSchema;
 
+use crate::cache::ChainCache;
+use crate::chain::ChainClient;
+use crate::token_metadata::{self, TokenMetadata};
+
+pub(crate) const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct WalletBalanceRequest {
     pub wallet_address: String,
@@ -21,9 +28,23 @@ pub struct WalletNftsRequest {
     pub wallet_address: String,
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct WalletNft {
+    pub mint: String,
+    pub token_account: String,
+    pub token_program: String,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub collection: Option<String>,
+    pub collection_verified: bool,
+    pub image_url: Option<String>, // From the off-chain JSON, when resolved
+    pub offchain_metadata: Option<serde_json::Value>,
+}
+
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct WalletNftsResponse {
-    pub nfts: Vec<serde_json::Value>,
+    pub nfts: Vec<WalletNft>,
     pub wallet_address: String,
     pub count: usize,
 }
@@ -93,16 +114,15 @@ pub fn validate_signature(message: &str, signature: &str, pubkey: &str) -> bool
 }
 
 pub async fn get_wallet_balance(
-    client: std::sync::Arc<RpcClient>,
+    cache: &ChainCache,
     wallet_address: &str,
 ) -> Result<WalletBalanceResponse, String> {
     let pubkey = Pubkey::from_str(wallet_address)
         .map_err(|e| format!("Invalid wallet address: {}", e))?;
     
-   let balance = client.get_balance(&pubkey)
+    let balance = cache.balance(&pubkey).await
         .map_err(|e| format!("Failed to get balance: {}", e))?;
-    // Convert lamports to SOL
-    let balance_sol = balance as f64 / 1_000_000_000_f64;
+    let balance_sol = lamports_to_sol(balance);
     
     Ok(WalletBalanceResponse {
         balance: balance_sol,
@@ -110,20 +130,103 @@ pub async fn get_wallet_balance(
     })
 }
 
+// A wallet's NFTs: the `offset`..`offset + limit` slice, ordered by mint, and the total
+pub struct WalletNftPage {
+    pub nfts: Vec<WalletNft>,
+    pub total_count: usize,
+}
+
+// Token accounts holding exactly one token of a 0-decimal mint, under either
+// token program, ordered by mint. Metadata (and off-chain JSON, when
+// `resolve_offchain` is set) is only fetched for the requested page.
+pub async fn find_wallet_nfts(
+    client: &dyn ChainClient,
+    cache: &ChainCache,
+    owner: &Pubkey,
+    offset: usize,
+    limit: Option<usize>,
+    resolve_offchain: bool,
+) -> Result<WalletNftPage, String> {
+    let mut holdings = Vec::new();
+    for token_program in [spl_token::id(), TOKEN_2022_PROGRAM_ID] {
+        let accounts = client
+            .get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(token_program)).await
+            .map_err(|e| format!("Failed to fetch token accounts: {}", e))?;
+        // Token-2022 accounts carry extensions after the base layout
+        holdings.extend(accounts.into_iter().filter_map(|(address, account)| {
+            let token = TokenAccount::unpack(account.data.get(..TokenAccount::LEN)?).ok()?;
+            (token.amount == 1).then_some((token.mint, address, token_program))
+        }));
+    }
+    holdings.sort_by_key(|(mint, _, _)| *mint);
+
+    let mints: Vec<Pubkey> = holdings.iter().map(|(mint, _, _)| *mint).collect();
+    let mint_accounts = cache.accounts(&mints).await
+        .map_err(|e| format!("Failed to fetch mints: {}", e))?;
+    let holdings: Vec<_> = holdings.into_iter().zip(mint_accounts)
+        .filter(|(_, mint_account)| {
+            mint_account.as_ref()
+                .and_then(|account| Mint::unpack(account.data.get(..Mint::LEN)?).ok())
+                .is_some_and(|mint| mint.decimals == 0)
+        })
+        .map(|(holding, _)| holding)
+        .collect();
+
+    let total_count = holdings.len();
+    let page: Vec<_> = holdings.into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect();
+
+    let metadata_addresses: Vec<Pubkey> = page.iter()
+        .map(|(mint, _, _)| token_metadata::metadata_address(mint))
+        .collect();
+    let metadata: Vec<Option<TokenMetadata>> = cache.accounts(&metadata_addresses).await
+        .map_err(|e| format!("Failed to fetch token metadata: {}", e))?
+        .iter()
+        .map(|account| account.as_ref().and_then(TokenMetadata::from_account))
+        .collect();
+
+    let offchain = if resolve_offchain {
+        let uris: Vec<String> = metadata.iter()
+            .map(|metadata| metadata.as_ref().map(|m| m.uri.clone()).unwrap_or_default())
+            .collect();
+        token_metadata::fetch_offchain_batch(&uris).await
+    } else {
+        vec![None; page.len()]
+    };
+
+    let nfts = page.into_iter().zip(metadata).zip(offchain)
+        .map(|(((mint, token_account, token_program), metadata), offchain)| WalletNft {
+            mint: mint.to_string(),
+            token_account: token_account.to_string(),
+            token_program: token_program.to_string(),
+            name: metadata.as_ref().map(|m| m.name.clone()).unwrap_or_default(),
+            symbol: metadata.as_ref().map(|m| m.symbol.clone()).unwrap_or_default(),
+            uri: metadata.as_ref().map(|m| m.uri.clone()).unwrap_or_default(),
+            collection: metadata.as_ref().and_then(|m| m.collection).map(|c| c.to_string()),
+            collection_verified: metadata.as_ref().is_some_and(|m| m.collection_verified),
+            image_url: offchain.as_ref().and_then(token_metadata::offchain_image),
+            offchain_metadata: offchain,
+        })
+        .collect();
+
+    Ok(WalletNftPage { nfts, total_count })
+}
+
 pub async fn get_wallet_nfts(
-    client: std::sync::Arc<RpcClient>,
+    client: std::sync::Arc<dyn ChainClient>,
+    cache: &ChainCache,
     wallet_address: &str,
 ) -> Result<WalletNftsResponse, String> {
     let pubkey = Pubkey::from_str(wallet_address)
         .map_err(|e| format!("Invalid wallet address: {}", e))?;
-    
-    // TODO: Implement proper NFT fetching logic
-    // For now, return empty list
-    let nfts = Vec::new();
-    
+
+    let page = find_wallet_nfts(client.as_ref(), cache, &pubkey, 0, None, false).await?;
+
     Ok(WalletNftsResponse {
-        nfts: nfts.clone(),
+        count: page.nfts.len(),
+        nfts: page.nfts,
         wallet_address: wallet_address.to_string(),
-        count: nfts.len(),
     })
-}
\ No newline at end of file
+}
@@ -1,8 +1,9 @@
 use shared::{MintNftRequest, MintNftResponse};
-use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+use solana_sdk::signature::{Keypair, Signer};
 use std::sync::Arc;
 
 pub struct ContractClient {
+    #[allow(dead_code)] // Used once the contract call below is implemented
     client: Arc<solana_client::rpc_client::RpcClient>,
 }
 
@@ -13,7 +14,7 @@ impl ContractClient {
         }
     }
 
-    pub async fn mint_nft(&self, req: MintNftRequest) -> Result<MintNftResponse, String> {
+    pub async fn mint_nft(&self, _req: MintNftRequest) -> Result<MintNftResponse, String> {
         // TODO: Implement actual contract call
         // For now, return mock response
 
@@ -1,22 +1,7 @@
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
 use shared::GenerateImageResponse;
 use std::error::Error;
 
-#[derive(Deserialize)]
-struct FreepikResponse {
-    #[serde(default)]
-    data: Vec<FreepikImage>,
-    #[serde(default)]
-    images: Vec<FreepikImage>,
-}
-
-#[derive(Deserialize)]
-struct FreepikImage {
-    url: Option<String>,
-    image_url: Option<String>,
-}
-
 #[derive(Clone)]
 pub struct FreepikClient {
     client: Client,
@@ -72,9 +57,9 @@ impl FreepikClient {
         // Try to parse the response - be flexible with the format
         let image_url = if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&response_text) {
             // Try different possible paths for the image URL
-            if let Some(url) = parsed.get("data").and_then(|d| d.as_array()).and_then(|arr| arr.get(0)).and_then(|img| img.get("url")).and_then(|u| u.as_str()) {
+            if let Some(url) = parsed.get("data").and_then(|d| d.as_array()).and_then(|arr| arr.first()).and_then(|img| img.get("url")).and_then(|u| u.as_str()) {
                 url.to_string()
-            } else if let Some(url) = parsed.get("images").and_then(|d| d.as_array()).and_then(|arr| arr.get(0)).and_then(|img| img.get("url")).and_then(|u| u.as_str()) {
+            } else if let Some(url) = parsed.get("images").and_then(|d| d.as_array()).and_then(|arr| arr.first()).and_then(|img| img.get("url")).and_then(|u| u.as_str()) {
                 url.to_string()
             } else if let Some(url) = parsed.get("url").and_then(|u| u.as_str()) {
                 url.to_string()
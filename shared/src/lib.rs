@@ -6,7 +6,7 @@ pub struct GenerateImageRequest {
     pub style: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub struct GenerateImageResponse {
     pub image_url: String,
     pub image_data: Option<String>,